@@ -0,0 +1,26 @@
+# Needs bash-preexec (https://github.com/rcaloras/bash-preexec) to be loaded
+# before this script.
+#
+# Exports HISTDB_RS_SESSION_ID once per shell and HISTDB_RS_RETVAL with the
+# exit code of the last command before every prompt.
+
+function hstdb-init() {
+  local session_id;
+  session_id="$(hstdb session_id)"
+  export HISTDB_RS_SESSION_ID="${session_id}"
+}
+
+function hstdb-preexec() {
+  unset HISTDB_RS_RETVAL;
+  hstdb zshaddhistory "${1}"
+}
+
+function hstdb-precmd() {
+  export HISTDB_RS_RETVAL="${?}"
+  hstdb precmd
+}
+
+preexec_functions+=(hstdb-preexec)
+precmd_functions+=(hstdb-precmd)
+
+hstdb-init
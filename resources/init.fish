@@ -0,0 +1,16 @@
+function hstdb-init
+  set -gx HISTDB_RS_SESSION_ID (hstdb session_id)
+end
+
+function hstdb-preexec --on-event fish_preexec
+  set -e HISTDB_RS_RETVAL
+  hstdb zshaddhistory $argv[1]
+end
+
+function hstdb-postexec --on-event fish_postexec
+  set -gx HISTDB_RS_RETVAL $status
+  set -gx HISTDB_RS_DURATION_MS $CMD_DURATION
+  hstdb precmd
+end
+
+hstdb-init
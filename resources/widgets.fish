@@ -0,0 +1,17 @@
+
+# Replaces the command line with the most recent command that contains
+# every word of the current command line.
+function hstdb-search-widget
+  # Only the command is printed and --print0 keeps its line breaks so it
+  # can be inserted as it was recorded.
+  set -l command (hstdb --disable-formatting --hide-header --entries-count 1 --columns cmd --format-command multiline --print0 --search (commandline) 2>/dev/null | string split0)
+
+  if test -n "$command"
+    commandline -r -- $command[1]
+    commandline -f end-of-line
+  end
+
+  commandline -f repaint
+end
+
+bind \cx\cr hstdb-search-widget
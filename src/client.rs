@@ -1,13 +1,32 @@
-use crate::message::Message;
+use crate::{
+    message::{
+        CommandStart,
+        Envelope,
+        Message,
+    },
+    transport::Transport,
+};
+use log::{
+    debug,
+    warn,
+};
 use std::{
+    io::ErrorKind,
     os::unix::net::UnixDatagram,
     path::PathBuf,
+    thread,
+    time::Duration,
 };
 use thiserror::Error;
 
 #[derive(Debug)]
 pub struct Client {
     socket_path: PathBuf,
+    timeout: Option<Duration>,
+    retries: u32,
+    retry_delay: Duration,
+    server_address: Option<String>,
+    auth_token: Option<String>,
 }
 
 #[derive(Error, Debug)]
@@ -23,24 +42,344 @@ pub enum Error {
 
     #[error("can not send message to socket: {0}")]
     SendMessage(std::io::Error),
+
+    #[error("can not set socket write timeout: {0}")]
+    SetWriteTimeout(std::io::Error),
+
+    #[error("no parent directory for socket path")]
+    NoSocketPathParent,
+
+    #[error("can not bind reply socket: {0}")]
+    BindReplySocket(std::io::Error),
+
+    #[error("can not set reply socket read timeout: {0}")]
+    SetReadTimeout(std::io::Error),
+
+    #[error("can not receive reply from server: {0}")]
+    ReceiveReply(std::io::Error),
+
+    #[error("can not deserialize reply: {0}")]
+    DeserializeReply(bincode::Error),
+
+    #[error("server can not commit pending entries: {0}")]
+    Flush(String),
+
+    #[cfg(not(feature = "tcp-transport"))]
+    #[error("tcp transport is not enabled in this build")]
+    TcpTransportDisabled,
 }
 
+const REPLY_BUFFER_SIZE: usize = 65_527;
+
 pub const fn new(socket_path: PathBuf) -> Client {
-    Client { socket_path }
+    Client {
+        socket_path,
+        timeout: None,
+        retries: 2,
+        retry_delay: Duration::from_millis(50),
+        server_address: None,
+        auth_token: None,
+    }
 }
 
 impl Client {
+    /// Limits how long `send` waits for the server to accept a message. When
+    /// the timeout is hit the message is dropped with a warning instead of
+    /// blocking the caller. Without a timeout `send` blocks until the server
+    /// has room for the message.
+    pub fn timeout(self, timeout: Option<Duration>) -> Self {
+        Self { timeout, ..self }
+    }
+
+    /// How often `send` tries again when the server can not be reached, for
+    /// example because it is restarting. The delay doubles after every try.
+    /// Defaults to 2 retries after 50ms and 100ms.
+    pub fn retries(self, retries: u32, retry_delay: Duration) -> Self {
+        Self {
+            retries,
+            retry_delay,
+            ..self
+        }
+    }
+
+    /// Sends the messages to the server listening on this TCP address instead
+    /// of the socket. Only messages that do not need a reply are accepted by
+    /// the server over TCP.
+    pub fn server_address(self, server_address: Option<String>) -> Self {
+        Self {
+            server_address,
+            ..self
+        }
+    }
+
+    /// Sends the token with every message so a server with the same
+    /// `auth_token` accepts them.
+    pub fn auth_token(self, auth_token: Option<String>) -> Self {
+        Self { auth_token, ..self }
+    }
+
+    /// Returns true if a server is listening on the socket.
+    pub fn server_reachable(&self) -> bool {
+        UnixDatagram::unbound()
+            .and_then(|socket| socket.connect(&self.socket_path))
+            .is_ok()
+    }
+
     pub fn send(&self, message: &Message) -> Result<(), Error> {
+        let envelope = Envelope {
+            token: self.auth_token.clone(),
+            message: message.clone(),
+        };
+
+        let data = bincode::serialize(&envelope).map_err(Error::SerializeMessage)?;
+
+        let mut retries = self.retries;
+        let mut retry_delay = self.retry_delay;
+
+        loop {
+            match self.send_once(&data) {
+                Err(err @ (Error::ConnectSocket(_) | Error::SendMessage(_))) if retries > 0 => {
+                    debug!(
+                        "can not reach server, trying again in {:?}: {}",
+                        retry_delay, err
+                    );
+
+                    thread::sleep(retry_delay);
+
+                    retries -= 1;
+                    retry_delay *= 2;
+                }
+
+                result => return result,
+            }
+        }
+    }
+
+    fn send_once(&self, data: &[u8]) -> Result<(), Error> {
+        let transport = self.connect()?;
+
+        match transport.send(data) {
+            Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                warn!(
+                    "server did not accept message in time, dropping it: {}",
+                    err
+                );
+
+                Ok(())
+            }
+
+            Err(err) => Err(Error::SendMessage(err)),
+
+            Ok(()) => Ok(()),
+        }
+    }
+
+    fn connect(&self) -> Result<Box<dyn Transport>, Error> {
+        if let Some(address) = &self.server_address {
+            return Self::connect_tcp(address, self.timeout);
+        }
+
         let socket = UnixDatagram::unbound().map_err(Error::CreateSocket)?;
 
+        socket
+            .set_write_timeout(self.timeout)
+            .map_err(Error::SetWriteTimeout)?;
+
         socket
             .connect(&self.socket_path)
             .map_err(Error::ConnectSocket)?;
 
+        Ok(Box::new(socket))
+    }
+
+    #[cfg(feature = "tcp-transport")]
+    fn connect_tcp(address: &str, timeout: Option<Duration>) -> Result<Box<dyn Transport>, Error> {
+        let stream =
+            crate::transport::connect_tcp(address, timeout).map_err(Error::ConnectSocket)?;
+
+        stream
+            .set_write_timeout(timeout)
+            .map_err(Error::SetWriteTimeout)?;
+
+        Ok(Box::new(stream))
+    }
+
+    #[cfg(not(feature = "tcp-transport"))]
+    fn connect_tcp(
+        _address: &str,
+        _timeout: Option<Duration>,
+    ) -> Result<Box<dyn Transport>, Error> {
+        Err(Error::TcpTransportDisabled)
+    }
+
+    /// Asks the server for all started commands that did not finish yet. Fails
+    /// if the server does not reply within `timeout`.
+    pub fn running(&self, timeout: Duration) -> Result<Vec<CommandStart>, Error> {
+        self.with_reply_socket(|socket, reply_path| {
+            self.receive_running(socket, reply_path, timeout)
+        })
+    }
+
+    /// Asks the server for its process id. Fails if no server is listening on
+    /// the socket or it does not reply within `timeout`.
+    pub fn ping(&self, timeout: Duration) -> Result<u32, Error> {
+        self.with_reply_socket(|socket, reply_path| {
+            socket
+                .set_read_timeout(Some(timeout))
+                .map_err(Error::SetReadTimeout)?;
+
+            self.send(&Message::Ping(reply_path))?;
+
+            let mut buffer = vec![0_u8; REPLY_BUFFER_SIZE];
+            let received = socket.recv(&mut buffer).map_err(Error::ReceiveReply)?;
+
+            bincode::deserialize(&buffer[..received]).map_err(Error::DeserializeReply)
+        })
+    }
+
+    /// Asks the server to commit the pending entries and waits until it is
+    /// done. Fails if the commit fails or the server does not reply within
+    /// `timeout`.
+    pub fn flush(&self, timeout: Duration) -> Result<(), Error> {
+        self.with_reply_socket(|socket, reply_path| {
+            socket
+                .set_read_timeout(Some(timeout))
+                .map_err(Error::SetReadTimeout)?;
+
+            self.send(&Message::Flush(reply_path))?;
+
+            let mut buffer = vec![0_u8; REPLY_BUFFER_SIZE];
+            let received = socket.recv(&mut buffer).map_err(Error::ReceiveReply)?;
+
+            let reply: Result<(), String> =
+                bincode::deserialize(&buffer[..received]).map_err(Error::DeserializeReply)?;
+
+            reply.map_err(Error::Flush)
+        })
+    }
+
+    /// Binds a socket next to the server socket for the server to reply to and
+    /// removes it again once `f` returns.
+    fn with_reply_socket<T>(
+        &self,
+        f: impl FnOnce(&UnixDatagram, PathBuf) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let reply_path = self
+            .socket_path
+            .parent()
+            .ok_or(Error::NoSocketPathParent)?
+            .join(format!("reply_{}", uuid::Uuid::new_v4()));
+
+        let socket = UnixDatagram::bind(&reply_path).map_err(Error::BindReplySocket)?;
+
+        let result = f(&socket, reply_path.clone());
+
+        if let Err(err) = std::fs::remove_file(&reply_path) {
+            warn!(
+                "can not remove reply socket {}: {}",
+                reply_path.display(),
+                err
+            );
+        }
+
+        result
+    }
+
+    fn receive_running(
+        &self,
+        socket: &UnixDatagram,
+        reply_path: PathBuf,
+        timeout: Duration,
+    ) -> Result<Vec<CommandStart>, Error> {
         socket
-            .send(&bincode::serialize(&message).map_err(Error::SerializeMessage)?)
-            .map_err(Error::SendMessage)?;
+            .set_read_timeout(Some(timeout))
+            .map_err(Error::SetReadTimeout)?;
+
+        self.send(&Message::Running(reply_path))?;
+
+        let mut running = Vec::new();
+        let mut buffer = vec![0_u8; REPLY_BUFFER_SIZE];
+
+        loop {
+            let received = socket.recv(&mut buffer).map_err(Error::ReceiveReply)?;
+
+            if received == 0 {
+                break;
+            }
+
+            let entry =
+                bincode::deserialize(&buffer[..received]).map_err(Error::DeserializeReply)?;
+            running.push(entry);
+        }
+
+        Ok(running)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::message::Message;
+    use std::{
+        os::unix::net::UnixDatagram,
+        time::{
+            Duration,
+            Instant,
+        },
+    };
+
+    #[test]
+    fn send_retries() {
+        let socket_dir = tempfile::tempdir().expect("can create socket dir");
+        let socket_path = socket_dir.path().join("server_socket");
+
+        let client = super::new(socket_path.clone()).retries(0, Duration::from_millis(10));
+        assert!(client.send(&Message::Stop).is_err());
+
+        // The server comes up while the client is still retrying.
+        let server = {
+            let socket_path = socket_path.clone();
+
+            std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(50));
+
+                let server = UnixDatagram::bind(&socket_path).expect("can bind socket");
+                let mut buffer = [0; 1024];
+                server.recv(&mut buffer).expect("can receive message")
+            })
+        };
+
+        let client = super::new(socket_path).retries(10, Duration::from_millis(10));
+        client
+            .send(&Message::Stop)
+            .expect("send succeeds once the server is up");
+
+        assert!(server.join().expect("server thread does not panic") > 0);
+    }
+
+    #[test]
+    fn send_timeout_on_busy_server() {
+        let socket_dir = tempfile::tempdir().expect("can create socket dir");
+        let socket_path = socket_dir.path().join("server_socket");
+
+        // Bound but never read from, so its receive queue fills up like the
+        // one of a server that can not keep up.
+        let _server = UnixDatagram::bind(&socket_path).expect("can bind socket");
+
+        let timeout = Duration::from_millis(50);
+        let client = super::new(socket_path).timeout(Some(timeout));
+
+        for _ in 0..10_000 {
+            let start = Instant::now();
+            client.send(&Message::Stop).expect("send should not fail");
+            let elapsed = start.elapsed();
+
+            assert!(elapsed < timeout * 20, "send took {elapsed:?}");
+
+            if elapsed >= timeout {
+                return;
+            }
+        }
 
-        Ok(())
+        panic!("receive queue of the server never filled up");
     }
 }
@@ -0,0 +1,30 @@
+use chrono::{
+    DateTime,
+    Utc,
+};
+
+/// Source of the current time. Code that generates timestamps takes a clock
+/// instead of calling `Utc::now` directly so tests can use a fixed time.
+pub trait Clock {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// Clock returning the current system time.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// Clock that always returns the same time.
+#[derive(Debug, Clone, Copy)]
+pub struct FixedClock(pub DateTime<Utc>);
+
+impl Clock for FixedClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.0
+    }
+}
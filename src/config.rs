@@ -1,11 +1,32 @@
+use crate::store::{
+    git::CommitMode,
+    Backend,
+    HostnameAliases,
+    StorageKey,
+};
+use chrono::{
+    TimeZone,
+    Utc,
+};
 use log::{
     debug,
     LevelFilter,
 };
-use std::path::Path;
+use regex::Regex;
+use std::{
+    collections::HashMap,
+    path::{
+        Path,
+        PathBuf,
+    },
+    time::Duration,
+};
 use thiserror::Error;
 
-use serde::Deserialize;
+use serde::{
+    Deserialize,
+    Deserializer,
+};
 
 #[derive(Debug, Error)]
 pub enum Error {
@@ -14,6 +35,21 @@ pub enum Error {
 
     #[error("can not parse config file: {0}")]
     ParseConfig(toml::de::Error),
+
+    #[error("profile {0} is not configured")]
+    UnknownProfile(String),
+
+    #[error("invalid timestamp format {0:?}")]
+    InvalidTimestampFormat(String),
+
+    #[error("invalid merge_hostnames pattern: {0}")]
+    InvalidHostnamePattern(regex::Error),
+
+    #[error("invalid ignore_commands pattern: {0}")]
+    InvalidIgnorePattern(regex::Error),
+
+    #[error("invalid server_address: {0}")]
+    InvalidServerAddress(crate::transport::Error),
 }
 
 #[derive(Debug, Deserialize)]
@@ -22,20 +58,256 @@ pub struct Config {
     /// Then true disables recording commands that start with a space.
     pub ignore_space: bool,
 
+    /// Regexes of commands that are not recorded. A command is skipped when
+    /// any of them matches, independent of `ignore_space`.
+    pub ignore_commands: Vec<String>,
+
+    /// Remove control characters other than newlines and tabs from commands
+    /// before they are recorded. Control characters are always escaped when
+    /// the history is printed.
+    pub sanitize_control_chars: bool,
+
     /// The log level to run under.
     pub log_level: LevelFilter,
 
-    /// The hostname that should be used when writing an entry. If
-    /// unset will dynamically get the hostname from the system.
+    /// The hostname that should be used when writing an entry and that is
+    /// read by default. If unset will dynamically get the hostname from the
+    /// system. `HISTDB_RS_HOSTNAME` takes precedence over it.
     pub hostname: Option<String>,
+
+    /// How `--in` and `--folder` match directories when neither
+    /// `--subdirs` nor `--no-subdirs` is given.
+    pub directory_match: DirectoryMatch,
+
+    /// Size in bytes of the receive buffer of the server socket. Raising it
+    /// avoids dropping messages when many commands are recorded in a
+    /// short burst. If unset the system default is used.
+    pub socket_recv_buffer_bytes: Option<usize>,
+
+    /// How many milliseconds the shell hooks wait for the server to accept
+    /// a message before dropping it. If unset or 0 they wait until the
+    /// server accepts the message.
+    pub send_timeout_ms: Option<u64>,
+
+    /// How often the shell hooks try again to reach the server, for example
+    /// while it restarts, before giving up.
+    pub send_retries: u32,
+
+    /// How many milliseconds the shell hooks wait before trying again to
+    /// reach the server. Doubles after every try.
+    pub send_retry_delay_ms: u64,
+
+    /// Address of a server started with `--listen`, e.g.
+    /// `tcp://devbox:9999`. When set the shell hooks send the recorded
+    /// commands there instead of to the local server.
+    pub server_address: Option<String>,
+
+    /// Shared secret that the shell hooks send with every message. When set
+    /// the server drops messages without the same token.
+    pub auth_token: Option<String>,
+
+    /// Named sets of paths that can be selected with `--profile`.
+    pub profiles: HashMap<String, Profile>,
+
+    /// Where entries are written to and read from.
+    pub backend: Backend,
+
+    /// When the server commits the history files of the data dir to git.
+    pub git_commit_mode: CommitMode,
+
+    /// With `Batched` the server commits once this many entries were added.
+    pub git_commit_batch_entries: usize,
+
+    /// With `Batched` the server commits once the oldest uncommitted entry
+    /// was added this many seconds ago.
+    pub git_commit_batch_secs: u64,
+
+    /// Whether the server writes one file per host or one file per user of
+    /// each host. Files of both layouts are always read.
+    pub storage_key: StorageKey,
+
+    /// When true the server stores every command of a compound command line
+    /// like `cmd1 && cmd2; cmd3` as its own entry.
+    pub split_compound_commands: bool,
+
+    /// How many received messages can wait in the server for being written.
+    /// When the queue is full the shell hooks wait for the server, which
+    /// delays the prompt unless `send_timeout_ms` is set. 0 makes the queue
+    /// unbounded.
+    pub processor_queue_size: usize,
+
+    /// chrono format string for the `tmn` column. If only this is set it is
+    /// also used for entries from today.
+    pub timestamp_format: Option<String>,
+
+    /// chrono format string for the `tmn` column of entries from today.
+    pub timestamp_format_today: Option<String>,
+
+    /// How commands that span multiple lines are printed when
+    /// `--format-command` is not given.
+    pub format_command: CommandFormat,
+
+    /// Hostnames matching a pattern are read as the given hostname. The
+    /// first matching pattern wins.
+    pub merge_hostnames: Vec<MergeHostname>,
+
+    /// How many entries are printed when `--entries-count` is not given.
+    pub default_entries_count: Option<usize>,
+
+    /// How many entries `trim` keeps for every host. The oldest entries are
+    /// removed first.
+    pub max_entries_per_host: Option<usize>,
+
+    /// Leading words like `sudo` that `stats` ignores when grouping
+    /// commands. The stored commands are not changed.
+    pub strip_prefixes: Vec<String>,
+
+    /// Roots of the pwd column that are printed as the given alias, like the
+    /// home directory is printed as `~`. The longest matching root wins.
+    pub pwd_abbreviations: Vec<(PathBuf, String)>,
+
+    /// Exit code that is recorded when the shell hooks pass an exit code
+    /// that can not be parsed.
+    pub invalid_retval: u16,
+
+    /// Exit codes that are recorded as a different exit code, for example
+    /// `141 = 0` to record commands killed by `SIGPIPE` as successful.
+    #[serde(deserialize_with = "deserialize_exit_code_remap")]
+    pub exit_code_remap: HashMap<u16, u16>,
+
+    /// Compiled from `merge_hostnames` when the config is opened.
+    #[serde(skip)]
+    hostname_aliases: HostnameAliases,
+
+    /// Compiled from `ignore_commands` when the config is opened.
+    #[serde(skip)]
+    ignore_patterns: Vec<Regex>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MergeHostname {
+    /// Regex that is matched against the hostname of the log files.
+    pub pattern: String,
+
+    /// Hostname that matching hosts are read as.
+    pub hostname: String,
+}
+
+/// Paths selected together with `--profile`. Paths that are set replace the
+/// defaults but not the paths given on the command line.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default)]
+pub struct Profile {
+    pub data_dir: Option<PathBuf>,
+    pub socket_path: Option<PathBuf>,
+    pub cache_path: Option<PathBuf>,
+}
+
+impl Profile {
+    pub fn data_dir(
+        &self,
+        data_dir: Option<PathBuf>,
+        default: impl FnOnce() -> PathBuf,
+    ) -> PathBuf {
+        data_dir
+            .or_else(|| self.data_dir.clone())
+            .unwrap_or_else(default)
+    }
+
+    pub fn socket_path(
+        &self,
+        socket_path: Option<PathBuf>,
+        default: impl FnOnce() -> PathBuf,
+    ) -> PathBuf {
+        socket_path
+            .or_else(|| self.socket_path.clone())
+            .unwrap_or_else(default)
+    }
+
+    pub fn cache_path(
+        &self,
+        cache_path: Option<PathBuf>,
+        default: impl FnOnce() -> PathBuf,
+    ) -> PathBuf {
+        cache_path
+            .or_else(|| self.cache_path.clone())
+            .unwrap_or_else(default)
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DirectoryMatch {
+    /// Only match entries run in exactly the given directory.
+    Exact,
+
+    /// Match entries run in the given directory and its subdirectories.
+    #[default]
+    Prefix,
+}
+
+/// How the lines of a command that spans multiple lines are printed.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, clap::ArgEnum, Default)]
+pub enum CommandFormat {
+    /// Keep the lines in the table, the plain output prints them as `\n`
+    #[default]
+    Multiline,
+
+    /// Join the lines with ` ↵ ` so every entry stays on one row
+    Arrow,
+
+    /// Join the lines with `; ` so every entry stays on one row
+    Semicolon,
+}
+
+impl DirectoryMatch {
+    /// Resolves whether subdirectories should be excluded. The command line
+    /// flags take precedence over the configured default.
+    pub const fn no_subdirs(self, subdirs: bool, no_subdirs: bool) -> bool {
+        if subdirs {
+            false
+        } else if no_subdirs {
+            true
+        } else {
+            matches!(self, Self::Exact)
+        }
+    }
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
             ignore_space: true,
+            ignore_commands: Vec::new(),
+            sanitize_control_chars: false,
             log_level: LevelFilter::Warn,
             hostname: None,
+            directory_match: DirectoryMatch::default(),
+            socket_recv_buffer_bytes: None,
+            send_timeout_ms: None,
+            send_retries: 2,
+            send_retry_delay_ms: 50,
+            server_address: None,
+            auth_token: None,
+            profiles: HashMap::new(),
+            backend: Backend::default(),
+            git_commit_mode: CommitMode::default(),
+            git_commit_batch_entries: 100,
+            git_commit_batch_secs: 300,
+            storage_key: StorageKey::default(),
+            split_compound_commands: false,
+            processor_queue_size: 10_000,
+            timestamp_format: None,
+            timestamp_format_today: None,
+            format_command: CommandFormat::default(),
+            merge_hostnames: Vec::new(),
+            default_entries_count: None,
+            max_entries_per_host: None,
+            strip_prefixes: Vec::new(),
+            pwd_abbreviations: Vec::new(),
+            invalid_retval: 0,
+            exit_code_remap: HashMap::new(),
+            hostname_aliases: HostnameAliases::default(),
+            ignore_patterns: Vec::new(),
         }
     }
 }
@@ -48,8 +320,236 @@ impl Config {
         }
 
         let config_data = std::fs::read(path).map_err(Error::ReadFile)?;
-        let config = toml::de::from_slice(&config_data).map_err(Error::ParseConfig)?;
+        let mut config: Self = toml::de::from_slice(&config_data).map_err(Error::ParseConfig)?;
+
+        for format in config
+            .timestamp_format
+            .iter()
+            .chain(config.timestamp_format_today.iter())
+        {
+            validate_timestamp_format(format)?;
+        }
+
+        config.hostname_aliases = HostnameAliases::new(
+            config
+                .merge_hostnames
+                .iter()
+                .map(|merge| (merge.pattern.as_str(), merge.hostname.as_str())),
+        )
+        .map_err(Error::InvalidHostnamePattern)?;
+
+        config.ignore_patterns = config
+            .ignore_commands
+            .iter()
+            .map(|pattern| Regex::new(pattern))
+            .collect::<Result<_, _>>()
+            .map_err(Error::InvalidIgnorePattern)?;
+
+        config.server_address = config
+            .server_address
+            .as_deref()
+            .map(crate::transport::parse_tcp_address)
+            .transpose()
+            .map_err(Error::InvalidServerAddress)?;
 
         Ok(config)
     }
+
+    /// Returns the profile with the given name or an empty profile that
+    /// keeps all paths as they are if no name is given.
+    pub fn profile(&self, name: Option<&str>) -> Result<Profile, Error> {
+        name.map_or_else(
+            || Ok(Profile::default()),
+            |name| {
+                self.profiles
+                    .get(name)
+                    .cloned()
+                    .ok_or_else(|| Error::UnknownProfile(name.to_string()))
+            },
+        )
+    }
+
+    /// Returns the hostname that is used instead of the hostname of the
+    /// system. `HISTDB_RS_HOSTNAME` takes precedence over the configured
+    /// `hostname`.
+    pub fn hostname_override(&self) -> Option<String> {
+        self.hostname_override_from(std::env::var("HISTDB_RS_HOSTNAME").ok())
+    }
+
+    /// Like `hostname_override` with the given value of `HISTDB_RS_HOSTNAME`.
+    pub fn hostname_override_from(&self, env_hostname: Option<String>) -> Option<String> {
+        env_hostname
+            .filter(|hostname| !hostname.is_empty())
+            .or_else(|| self.hostname.clone())
+    }
+
+    pub const fn hostname_aliases(&self) -> &HostnameAliases {
+        &self.hostname_aliases
+    }
+
+    /// Returns true when the command matches one of the `ignore_commands`
+    /// patterns and should not be recorded.
+    pub fn ignores_command(&self, command: &str) -> bool {
+        self.ignore_patterns
+            .iter()
+            .any(|pattern| pattern.is_match(command))
+    }
+
+    /// Returns the exit code that should be recorded for the exit code a
+    /// command returned.
+    pub fn remap_exit_code(&self, result: u16) -> u16 {
+        self.exit_code_remap.get(&result).copied().unwrap_or(result)
+    }
+
+    pub fn send_timeout(&self) -> Option<Duration> {
+        // Sockets reject a zero timeout so 0 means no timeout.
+        self.send_timeout_ms
+            .filter(|ms| *ms > 0)
+            .map(Duration::from_millis)
+    }
+
+    pub const fn send_retry_delay(&self) -> Duration {
+        Duration::from_millis(self.send_retry_delay_ms)
+    }
+
+    pub const fn git_commit_batch_interval(&self) -> Duration {
+        Duration::from_secs(self.git_commit_batch_secs)
+    }
+}
+
+/// Keys of TOML tables are always strings so the exit codes are parsed here.
+fn deserialize_exit_code_remap<'de, D>(deserializer: D) -> Result<HashMap<u16, u16>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    HashMap::<String, u16>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(from, to)| {
+            from.parse()
+                .map(|from| (from, to))
+                .map_err(|_| serde::de::Error::custom(format!("invalid exit code {from:?}")))
+        })
+        .collect()
+}
+
+/// Formats a sample timestamp so invalid format strings are reported when the
+/// config is loaded instead of when the first entry is printed.
+fn validate_timestamp_format(format: &str) -> Result<(), Error> {
+    use std::fmt::Write;
+
+    let sample = Utc.ymd(2021, 6, 1).and_hms(12, 0, 0);
+    let mut out = String::new();
+
+    write!(out, "{}", sample.format(format))
+        .map_err(|_| Error::InvalidTimestampFormat(format.to_string()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::Config;
+    use std::{
+        path::PathBuf,
+        time::Duration,
+    };
+
+    #[test]
+    fn profile_selects_data_dir() {
+        let config: Config = toml::from_str(
+            r#"
+            [profiles.work]
+            data_dir = "/tmp/hstdb/work"
+
+            [profiles.personal]
+            data_dir = "/tmp/hstdb/personal"
+            socket_path = "/tmp/hstdb/personal_socket"
+            "#,
+        )
+        .expect("config should parse");
+
+        let default_data_dir = || PathBuf::from("/tmp/hstdb/default");
+
+        let work = config.profile(Some("work")).expect("profile exists");
+        assert_eq!(
+            work.data_dir(None, default_data_dir),
+            PathBuf::from("/tmp/hstdb/work")
+        );
+
+        // A path given on the command line wins over the profile.
+        assert_eq!(
+            work.data_dir(Some(PathBuf::from("/tmp/hstdb/cli")), default_data_dir),
+            PathBuf::from("/tmp/hstdb/cli")
+        );
+
+        let none = config.profile(None).expect("no profile is always valid");
+        assert_eq!(none.data_dir(None, default_data_dir), default_data_dir());
+
+        assert!(config.profile(Some("missing")).is_err());
+    }
+
+    #[test]
+    fn send_timeout() {
+        let config: Config = toml::from_str("send_timeout_ms = 100").expect("config should parse");
+        assert_eq!(config.send_timeout(), Some(Duration::from_millis(100)));
+
+        let config: Config = toml::from_str("send_timeout_ms = 0").expect("config should parse");
+        assert_eq!(config.send_timeout(), None);
+
+        assert_eq!(Config::default().send_timeout(), None);
+    }
+
+    #[test]
+    fn remap_exit_code() {
+        let config: Config = toml::from_str(
+            "
+            [exit_code_remap]
+            141 = 0
+            ",
+        )
+        .expect("config should parse");
+
+        assert_eq!(config.remap_exit_code(141), 0);
+        assert_eq!(config.remap_exit_code(1), 1);
+        assert_eq!(Config::default().remap_exit_code(141), 141);
+    }
+
+    #[test]
+    fn ignores_command() {
+        let config_dir = tempfile::tempdir().expect("can create config dir");
+        let config_path = config_dir.path().join("config.toml");
+
+        std::fs::write(
+            &config_path,
+            "ignore_commands = ['^ls$', '^clear$', 'password']",
+        )
+        .expect("can write config");
+        let config = Config::open(&config_path).expect("can open config");
+
+        assert!(config.ignores_command("ls"));
+        assert!(config.ignores_command("clear"));
+        assert!(config.ignores_command("echo password123"));
+        assert!(!config.ignores_command("ls -la"));
+        assert!(!Config::default().ignores_command("ls"));
+
+        std::fs::write(&config_path, "ignore_commands = ['(']").expect("can write config");
+
+        assert!(matches!(
+            Config::open(&config_path),
+            Err(super::Error::InvalidIgnorePattern(_))
+        ));
+    }
+
+    #[test]
+    fn validate_timestamp_format() {
+        assert!(super::validate_timestamp_format("%Y-%m-%d %H:%M:%S").is_ok());
+        assert!(super::validate_timestamp_format("%Y-%m-%d %Q").is_err());
+
+        let config_dir = tempfile::tempdir().expect("can create config dir");
+        let config_path = config_dir.path().join("config.toml");
+        std::fs::write(&config_path, "timestamp_format = \"%H:%\"").expect("can write config");
+
+        assert!(matches!(
+            Config::open(&config_path),
+            Err(super::Error::InvalidTimestampFormat(_))
+        ));
+    }
 }
@@ -4,16 +4,20 @@ use crate::message::{
 };
 use chrono::{
     DateTime,
+    Duration,
     Utc,
 };
 use serde::{
     Deserialize,
     Serialize,
 };
-use std::path::PathBuf;
+use std::{
+    cmp::Ordering,
+    path::PathBuf,
+};
 use uuid::Uuid;
 
-#[derive(Debug, Serialize, Deserialize, Ord, PartialOrd, PartialEq, Eq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct Entry {
     pub time_finished: DateTime<Utc>,
     pub time_start: DateTime<Utc>,
@@ -23,6 +27,80 @@ pub struct Entry {
     pub result: u16,
     pub session_id: Uuid,
     pub user: String,
+    #[serde(default)]
+    pub hist_event: Option<u64>,
+    #[serde(default)]
+    pub deleted: bool,
+    /// How long the command ran. Missing for entries recorded before it was
+    /// stored, see `Entry::duration`.
+    #[serde(default)]
+    pub duration_ms: Option<i64>,
+    /// Label the command was recorded with, see `HISTDB_RS_TAG`.
+    #[serde(default)]
+    pub tag: Option<String>,
+    /// Assigned by the server in the order the entries are written so
+    /// commands with the same timestamps still have an order. 0 for entries
+    /// that were written before it was stored or imported.
+    #[serde(default)]
+    pub seq: u64,
+    /// When the entry was marked as deleted, see `hstdb delete`. Missing for
+    /// entries that are not deleted or were deleted before it was stored.
+    #[serde(default)]
+    pub time_deleted: Option<DateTime<Utc>>,
+}
+
+/// Entries are sorted by when they finished and started. Entries of different
+/// hosts or sessions that have the same timestamps are ordered by their
+/// session so the entries of one session stay together and then in the order
+/// the server wrote them. The other fields only break ties between entries
+/// that have no sequence number to stay consistent with `Eq`.
+impl Ord for Entry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (
+            self.time_finished,
+            self.time_start,
+            self.session_id,
+            self.seq,
+        )
+            .cmp(&(
+                other.time_finished,
+                other.time_start,
+                other.session_id,
+                other.seq,
+            ))
+            .then_with(|| {
+                (
+                    &self.hostname,
+                    &self.command,
+                    &self.pwd,
+                    self.result,
+                    &self.user,
+                    self.hist_event,
+                    self.deleted,
+                    self.duration_ms,
+                    &self.tag,
+                    self.time_deleted,
+                )
+                    .cmp(&(
+                        &other.hostname,
+                        &other.command,
+                        &other.pwd,
+                        other.result,
+                        &other.user,
+                        other.hist_event,
+                        other.deleted,
+                        other.duration_ms,
+                        &other.tag,
+                        other.time_deleted,
+                    ))
+            })
+    }
+}
+
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
 }
 
 impl Entry {
@@ -47,6 +125,318 @@ impl Entry {
             result: finish.result,
             session_id: start.session_id,
             user,
+            hist_event: start.hist_event,
+            deleted: false,
+            // The clocks of the shells can differ a bit so a command can look
+            // like it finished before it started.
+            duration_ms: Some(finish.reported_duration_ms.unwrap_or_else(|| {
+                (finish.time_stamp - start.time_stamp)
+                    .num_milliseconds()
+                    .max(0)
+            })),
+            tag: start.tag,
+            seq: 0,
+            time_deleted: None,
         }
     }
+
+    /// How long the command ran. Computed from the timestamps for entries
+    /// that were recorded before the duration was stored.
+    pub fn duration(&self) -> Duration {
+        self.duration_ms.map_or_else(
+            || self.time_finished - self.time_start,
+            Duration::milliseconds,
+        )
+    }
+
+    /// How many pipes the command line has, e.g. 2 for `cat file | sort |
+    /// uniq`.
+    pub fn pipes(&self) -> usize {
+        count_pipes(&self.command)
+    }
+
+    /// Splits an entry for a compound command line like `cmd1 && cmd2; cmd3`
+    /// into one entry per command. All entries share the session, timestamps
+    /// and result of the original entry.
+    pub fn split_compound(self) -> Vec<Self> {
+        let commands = split_compound_command(&self.command);
+
+        if commands.len() < 2 {
+            return vec![self];
+        }
+
+        commands
+            .into_iter()
+            .map(|command| Self {
+                command,
+                ..self.clone()
+            })
+            .collect()
+    }
+}
+
+/// Splits a command line on the top level `&&`, `||`, `;` and `|` operators.
+/// Operators in quotes, after a backslash or nested in parentheses or braces
+/// (e.g. `$(cmd1; cmd2)`) do not split the command line.
+fn split_compound_command(command: &str) -> Vec<String> {
+    let mut commands = Vec::new();
+    let mut current = String::new();
+    let mut quote = None;
+    let mut depth = 0_usize;
+
+    let mut split = |current: &mut String| {
+        let command = current.trim();
+        if !command.is_empty() {
+            commands.push(command.to_string());
+        }
+        current.clear();
+    };
+
+    let mut chars = command.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            // Nothing can be escaped in single quotes.
+            (Some('\''), '\'') => quote = None,
+            (Some('\''), _) => {}
+            (_, '\\') => {
+                current.push(c);
+                if let Some(escaped) = chars.next() {
+                    current.push(escaped);
+                }
+
+                continue;
+            }
+            (Some(_), '"') => quote = None,
+            (None, '\'' | '"') => quote = Some(c),
+            (None, '(' | '{') => depth += 1,
+            (None, ')' | '}') => depth = depth.saturating_sub(1),
+            (None, ';') if depth == 0 => {
+                split(&mut current);
+                continue;
+            }
+            (None, '|') if depth == 0 => {
+                // Also consume the second character of `||` and `|&`.
+                if let Some('|' | '&') = chars.peek() {
+                    chars.next();
+                }
+
+                split(&mut current);
+                continue;
+            }
+            (None, '&') if depth == 0 && chars.peek() == Some(&'&') => {
+                chars.next();
+
+                split(&mut current);
+                continue;
+            }
+            _ => {}
+        }
+
+        current.push(c);
+    }
+
+    split(&mut current);
+
+    commands
+}
+
+/// Counts the `|` and `|&` operators of a command line. Pipes in quotes or
+/// after a backslash are part of an argument and `||` is not a pipe. Pipes in
+/// subshells like `$(cmd1 | cmd2)` are counted.
+fn count_pipes(command: &str) -> usize {
+    let mut pipes = 0;
+    let mut quote = None;
+
+    let mut chars = command.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            // Nothing can be escaped in single quotes.
+            (Some('\''), '\'') => quote = None,
+            (Some('\''), _) => {}
+            (_, '\\') => {
+                chars.next();
+            }
+            (Some(_), '"') => quote = None,
+            (None, '\'' | '"') => quote = Some(c),
+            (None, '|') => {
+                if chars.peek() == Some(&'|') {
+                    chars.next();
+                } else {
+                    pipes += 1;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pipes
+}
+
+#[cfg(test)]
+impl Entry {
+    /// Entry that finished now in `/tmp` with a successful result. Tests
+    /// override the fields they care about with struct update syntax.
+    pub fn for_test(hostname: &str, command: &str) -> Self {
+        Self::for_test_at(hostname, command, Utc::now())
+    }
+
+    /// Like `for_test` but the command ran at the given time.
+    pub fn for_test_at(hostname: &str, command: &str, time: DateTime<Utc>) -> Self {
+        Self {
+            time_finished: time,
+            time_start: time,
+            hostname: hostname.to_string(),
+            command: command.to_string(),
+            pwd: PathBuf::from("/tmp"),
+            result: 0,
+            session_id: Uuid::new_v4(),
+            user: "testuser".to_string(),
+            hist_event: None,
+            deleted: false,
+            duration_ms: None,
+            tag: None,
+            seq: 0,
+            time_deleted: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn order_same_timestamps() {
+        let now = chrono::Utc::now();
+        let first = uuid::Uuid::from_u128(1);
+        let second = uuid::Uuid::from_u128(2);
+        let entry = |hostname: &str, command: &str, session_id| super::Entry {
+            session_id,
+            ..super::Entry::for_test_at(hostname, command, now)
+        };
+
+        let mut entries = [
+            entry("alpha", "z", second),
+            entry("beta", "b", first),
+            entry("alpha", "a", second),
+            entry("beta", "y", first),
+        ];
+        let mut reversed = entries.clone();
+        reversed.reverse();
+
+        entries.sort();
+        reversed.sort();
+        assert_eq!(entries, reversed);
+
+        let got: Vec<_> = entries
+            .iter()
+            .map(|entry| (entry.hostname.as_str(), entry.command.as_str()))
+            .collect();
+
+        assert_eq!(
+            got,
+            vec![("beta", "b"), ("beta", "y"), ("alpha", "a"), ("alpha", "z")]
+        );
+    }
+
+    #[test]
+    fn split_compound_command() {
+        let cases = vec![
+            ("ls", vec!["ls"]),
+            ("cmd1 && cmd2; cmd3", vec!["cmd1", "cmd2", "cmd3"]),
+            ("cmd1 || cmd2", vec!["cmd1", "cmd2"]),
+            (
+                "cat file | grep x |& less",
+                vec!["cat file", "grep x", "less"],
+            ),
+            ("echo 'a && b; c | d'", vec!["echo 'a && b; c | d'"]),
+            (r#"echo "a && b" && ls"#, vec![r#"echo "a && b""#, "ls"]),
+            (r#"echo "it's; fine""#, vec![r#"echo "it's; fine""#]),
+            (r"echo a\;b", vec![r"echo a\;b"]),
+            ("find . -exec rm {} \\;", vec!["find . -exec rm {} \\;"]),
+            (
+                "echo $(date; uptime) && ls",
+                vec!["echo $(date; uptime)", "ls"],
+            ),
+            ("make 2>&1 & disown", vec!["make 2>&1 & disown"]),
+            ("cmd1 ;; ; cmd2;", vec!["cmd1", "cmd2"]),
+        ];
+
+        for (command, expected) in cases {
+            assert_eq!(
+                super::split_compound_command(command),
+                expected,
+                "{command}"
+            );
+        }
+    }
+
+    #[test]
+    fn count_pipes() {
+        let cases = vec![
+            ("ls", 0),
+            ("cat file | sort | uniq -c | sort -n", 3),
+            ("make 2>&1 |& tee log", 1),
+            ("cmd1 || cmd2", 0),
+            ("grep 'a|b' file", 0),
+            (r#"grep "a|b" file | wc -l"#, 1),
+            (r"echo a\|b", 0),
+            ("echo $(ls | wc -l)", 1),
+            (r#"echo "it's | fine""#, 0),
+        ];
+
+        for (command, expected) in cases {
+            assert_eq!(super::count_pipes(command), expected, "{command}");
+        }
+    }
+
+    #[test]
+    fn duration() {
+        use crate::message::{
+            CommandFinished,
+            CommandStart,
+        };
+        use chrono::{
+            Duration,
+            Utc,
+        };
+
+        let now = Utc::now();
+        let start = |time_stamp| CommandStart {
+            command: "sleep 2".to_string(),
+            pwd: std::path::PathBuf::from("/tmp"),
+            session_id: uuid::Uuid::nil(),
+            time_stamp,
+            user: "testuser".to_string(),
+            hostname: "testhostname".to_string(),
+            hist_event: None,
+            tag: None,
+        };
+        let finish = CommandFinished {
+            session_id: uuid::Uuid::nil(),
+            time_stamp: now,
+            result: 0,
+            reported_duration_ms: None,
+        };
+
+        let entry = super::Entry::from_messages(start(now - Duration::seconds(2)), &finish);
+        assert_eq!(entry.duration_ms, Some(2000));
+
+        let skewed = super::Entry::from_messages(start(now + Duration::seconds(1)), &finish);
+        assert_eq!(skewed.duration(), Duration::zero());
+
+        let reported = super::Entry::from_messages(
+            start(now - Duration::seconds(2)),
+            &CommandFinished {
+                reported_duration_ms: Some(1500),
+                ..finish.clone()
+            },
+        );
+        assert_eq!(reported.duration_ms, Some(1500));
+
+        let old = super::Entry {
+            duration_ms: None,
+            tag: None,
+            ..entry
+        };
+        assert_eq!(old.duration(), Duration::seconds(2));
+    }
 }
@@ -1,4 +1,16 @@
+#![warn(clippy::pedantic)]
+#![warn(clippy::unwrap_used)]
+#![warn(rust_2018_idioms, unused_lifetimes, missing_debug_implementations)]
+#![allow(
+    clippy::missing_errors_doc,
+    clippy::missing_panics_doc,
+    clippy::must_use_candidate,
+    clippy::return_self_not_must_use
+)]
+#![forbid(unsafe_code)]
+
 pub mod client;
+pub mod clock;
 pub mod config;
 pub mod entry;
 pub mod message;
@@ -6,3 +18,4 @@ pub mod opt;
 pub mod run;
 pub mod server;
 pub mod store;
+pub mod transport;
@@ -4,18 +4,11 @@
 #![forbid(unsafe_code)]
 
 use clap::Parser;
-
-mod client;
-mod config;
-mod entry;
-mod message;
-mod opt;
-mod run;
-mod server;
-mod store;
-
+use hstdb::{
+    opt::Opt,
+    run,
+};
 use log::error;
-use opt::Opt;
 
 fn main() {
     let opt = Opt::from_args();
@@ -26,8 +19,17 @@ fn main() {
             if io_err.kind() == std::io::ErrorKind::BrokenPipe {}
         }
 
+        // Scripts can check the exit code of `hstdb status`.
+        Err(run::Error::ServerNotRunning) => std::process::exit(3),
+
+        // Prompts can tell that there is no previous command.
+        Err(err @ run::Error::NoEntries) => {
+            error!("{}", err);
+            std::process::exit(1)
+        }
+
         Err(err) => error!("{}", err),
 
-        Ok(_) => (),
+        Ok(()) => (),
     }
 }
@@ -2,6 +2,7 @@ use chrono::{
     DateTime,
     Utc,
 };
+use log::debug;
 use serde::{
     Deserialize,
     Serialize,
@@ -13,9 +14,12 @@ use std::{
 use thiserror::Error;
 use uuid::Uuid;
 
-use crate::config::Config;
+use crate::{
+    clock::Clock,
+    config::Config,
+};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum Message {
     Stop,
 
@@ -26,6 +30,73 @@ pub enum Message {
     CommandStart(CommandStart),
 
     CommandFinished(CommandFinished),
+
+    /// Asks the server to send all started but not yet finished commands to
+    /// the socket at the given path.
+    Running(PathBuf),
+
+    /// Sets the start time of the started command of a session to when the
+    /// command actually started executing.
+    CommandStarted(CommandStarted),
+
+    /// Asks the server to send its process id to the socket at the given path.
+    Ping(PathBuf),
+
+    /// Asks the server to commit the entries it has written but not committed
+    /// yet and to reply to the socket at the given path once it is done.
+    Flush(PathBuf),
+}
+
+/// What the client sends to the server. The server only processes the message
+/// if the token matches its `auth_token`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Envelope {
+    pub token: Option<String>,
+    pub message: Message,
+}
+
+impl Envelope {
+    /// Returns the message if the token matches. Without an `auth_token`
+    /// every message is accepted, with or without a token.
+    pub fn open(self, auth_token: Option<&str>) -> Option<Message> {
+        let Some(auth_token) = auth_token else {
+            return Some(self.message);
+        };
+
+        match self.token {
+            Some(token) if tokens_equal(token.as_bytes(), auth_token.as_bytes()) => {
+                Some(self.message)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Compares every byte so the time it takes does not tell how much of a
+/// guessed token is right.
+fn tokens_equal(left: &[u8], right: &[u8]) -> bool {
+    left.len() == right.len()
+        && left
+            .iter()
+            .zip(right)
+            .fold(0, |acc, (left, right)| acc | (left ^ right))
+            == 0
+}
+
+impl Message {
+    /// Returns true for the messages the shell hooks send to record commands.
+    /// The other messages control the server or need a reply on a local
+    /// socket.
+    pub const fn records_commands(&self) -> bool {
+        matches!(
+            self,
+            Self::Disable(_)
+                | Self::Enable(_)
+                | Self::CommandStart(_)
+                | Self::CommandFinished(_)
+                | Self::CommandStarted(_)
+        )
+    }
 }
 
 #[derive(Error, Debug)]
@@ -50,9 +121,6 @@ pub enum Error {
 
     #[error("retval is missing")]
     MissingRetval(std::env::VarError),
-
-    #[error("invalid result: {0}")]
-    InvalidResult(std::num::ParseIntError),
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -63,26 +131,54 @@ pub struct CommandStart {
     pub time_stamp: DateTime<Utc>,
     pub user: String,
     pub hostname: String,
+    pub hist_event: Option<u64>,
+    /// Label given with `HISTDB_RS_TAG`, e.g. `deploy`.
+    pub tag: Option<String>,
 }
 
 impl CommandStart {
-    pub fn from_env(config: &Config, command: String) -> Result<Self, Error> {
+    pub fn from_env(config: &Config, clock: &impl Clock, command: String) -> Result<Self, Error> {
+        Self::from_vars(config, clock, command, |name| env::var(name))
+    }
+
+    /// Like `from_env` but looks up the variables the shell hooks set with
+    /// `var` instead of in the environment of the process.
+    pub fn from_vars(
+        config: &Config,
+        clock: &impl Clock,
+        command: String,
+        var: impl Fn(&str) -> Result<String, env::VarError>,
+    ) -> Result<Self, Error> {
         let pwd = env::current_dir().map_err(Error::GetCurrentDir)?;
 
-        let time_stamp = Utc::now();
+        let time_stamp = clock.now();
 
-        let user = env::var("USER").map_err(Error::GetUser)?;
+        let user = var("USER").map_err(Error::GetUser)?;
 
-        let session_id = session_id_from_env()?;
+        let session_id = session_id_from(var("HISTDB_RS_SESSION_ID"))?;
 
-        let hostname = if let Some(hostname) = config.hostname.clone() {
-            hostname
-        } else {
-            hostname::get()
-                .map_err(Error::GetHostname)?
-                .to_string_lossy()
-                .to_string()
-        };
+        let hist_event = var("HISTDB_RS_HIST_EVENT").ok().and_then(|hist_event| {
+            match hist_event.trim().parse() {
+                Ok(hist_event) => Some(hist_event),
+                Err(err) => {
+                    debug!("can not parse history event {:?}: {}", hist_event, err);
+                    None
+                }
+            }
+        });
+
+        let tag = var("HISTDB_RS_TAG").ok().filter(|tag| !tag.is_empty());
+
+        // HISTDB_RS_HOSTNAME > hostname of the config > hostname of the system
+        let hostname =
+            if let Some(hostname) = config.hostname_override_from(var("HISTDB_RS_HOSTNAME").ok()) {
+                hostname
+            } else {
+                hostname::get()
+                    .map_err(Error::GetHostname)?
+                    .to_string_lossy()
+                    .to_string()
+            };
 
         Ok(Self {
             command,
@@ -91,6 +187,8 @@ impl CommandStart {
             time_stamp,
             user,
             hostname,
+            hist_event,
+            tag,
         })
     }
 }
@@ -100,29 +198,99 @@ pub struct CommandFinished {
     pub session_id: Uuid,
     pub time_stamp: DateTime<Utc>,
     pub result: u16,
+    /// How long the command ran as measured by the shell. Preferred over the
+    /// time between the messages which includes delays of the hooks.
+    pub reported_duration_ms: Option<i64>,
 }
 
 impl CommandFinished {
-    pub fn from_env() -> Result<Self, Error> {
-        let time_stamp = Utc::now();
+    pub fn from_env(config: &Config, clock: &impl Clock) -> Result<Self, Error> {
+        let time_stamp = clock.now();
 
         let session_id = session_id_from_env()?;
 
-        let result = env::var("HISTDB_RS_RETVAL")
-            .map_err(Error::MissingRetval)?
-            .parse()
-            .map_err(Error::InvalidResult)?;
+        let retval = env::var("HISTDB_RS_RETVAL").map_err(Error::MissingRetval)?;
+        let result = parse_retval(&retval).unwrap_or_else(|err| {
+            debug!(
+                "can not parse retval {:?}, using {}: {}",
+                retval, config.invalid_retval, err
+            );
+
+            config.invalid_retval
+        });
+        let result = config.remap_exit_code(result);
+
+        let reported_duration_ms = env::var("HISTDB_RS_DURATION_MS")
+            .ok()
+            .and_then(|duration_ms| match parse_duration_ms(&duration_ms) {
+                Ok(duration_ms) => Some(duration_ms),
+                Err(err) => {
+                    debug!("can not parse duration {:?}: {}", duration_ms, err);
+                    None
+                }
+            });
 
         Ok(Self {
             session_id,
             time_stamp,
             result,
+            reported_duration_ms,
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CommandStarted {
+    pub session_id: Uuid,
+    pub time_stamp: DateTime<Utc>,
+}
+
+impl CommandStarted {
+    pub fn from_env(clock: &impl Clock) -> Result<Self, Error> {
+        let time_stamp = clock.now();
+
+        let session_id = session_id_from_env()?;
+
+        Ok(Self {
+            session_id,
+            time_stamp,
         })
     }
 }
 
+/// Parses the exit code the shell hooks pass in `HISTDB_RS_RETVAL`. Some shells
+/// pass surrounding whitespace or the status of every command of a pipeline
+/// like `0 1 0` in which case the status of the last command is used.
+fn parse_retval(retval: &str) -> Result<u16, std::num::ParseIntError> {
+    retval.split_whitespace().last().unwrap_or("").parse()
+}
+
+/// Parses the duration in milliseconds the shell hooks pass in
+/// `HISTDB_RS_DURATION_MS`.
+fn parse_duration_ms(duration_ms: &str) -> Result<i64, std::num::ParseIntError> {
+    duration_ms
+        .trim()
+        .parse::<u64>()
+        .map(|duration_ms| i64::try_from(duration_ms).unwrap_or(i64::MAX))
+}
+
+/// Returns true when the value of `HISTDB_RS_DISABLE` is set to anything but
+/// an empty string or `0`. Unlike disabling the session on the server this
+/// only applies to the shell that set it and takes effect right away.
+pub fn recording_disabled(var: Result<String, env::VarError>) -> bool {
+    match var {
+        Ok(value) => !value.is_empty() && value != "0",
+        Err(env::VarError::NotPresent) => false,
+        Err(env::VarError::NotUnicode(_)) => true,
+    }
+}
+
 pub fn session_id_from_env() -> Result<Uuid, Error> {
-    match env::var("HISTDB_RS_SESSION_ID") {
+    session_id_from(env::var("HISTDB_RS_SESSION_ID"))
+}
+
+fn session_id_from(var: Result<String, env::VarError>) -> Result<Uuid, Error> {
+    match var {
         Err(err) => match err {
             env::VarError::NotPresent => Err(Error::MissingSessionID),
             env::VarError::NotUnicode(_) => Err(Error::InvalidSessionIDEnvVar(err)),
@@ -131,3 +299,100 @@ pub fn session_id_from_env() -> Result<Uuid, Error> {
         Ok(s) => Uuid::parse_str(&s).map_err(Error::InvalidSessionID),
     }
 }
+
+/// Returns a variable lookup for `CommandStart::from_vars` with the given
+/// variables and the ones the shell hooks always set.
+#[cfg(test)]
+pub fn vars_for_test(vars: &[(&str, &str)]) -> impl Fn(&str) -> Result<String, env::VarError> {
+    let mut vars: std::collections::HashMap<String, String> = vars
+        .iter()
+        .map(|(name, value)| ((*name).to_string(), (*value).to_string()))
+        .collect();
+
+    vars.entry("USER".to_string())
+        .or_insert_with(|| "testuser".to_string());
+    vars.entry("HISTDB_RS_SESSION_ID".to_string())
+        .or_insert_with(|| Uuid::new_v4().to_string());
+
+    move |name| vars.get(name).cloned().ok_or(env::VarError::NotPresent)
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn envelope_open() {
+        let open = |token: Option<&str>, auth_token| {
+            super::Envelope {
+                token: token.map(ToString::to_string),
+                message: super::Message::Stop,
+            }
+            .open(auth_token)
+            .is_some()
+        };
+
+        assert!(open(None, None));
+        assert!(open(Some("secret"), None));
+        assert!(open(Some("secret"), Some("secret")));
+
+        assert!(!open(None, Some("secret")));
+        assert!(!open(Some("wrong"), Some("secret")));
+        assert!(!open(Some("secret2"), Some("secret")));
+        assert!(!open(Some(""), Some("secret")));
+    }
+
+    #[test]
+    fn parse_retval() {
+        assert_eq!(super::parse_retval("0"), Ok(0));
+        assert_eq!(super::parse_retval(" 1\n"), Ok(1));
+        assert_eq!(super::parse_retval("+2"), Ok(2));
+        assert_eq!(super::parse_retval("0 1 130"), Ok(130));
+        assert!(super::parse_retval("").is_err());
+        assert!(super::parse_retval("garbage").is_err());
+        assert!(super::parse_retval("-1").is_err());
+    }
+
+    #[test]
+    fn command_start_hostname() {
+        let clock = crate::clock::FixedClock(chrono::Utc::now());
+        let mut config = crate::config::Config::default();
+        config.hostname = Some("configured".to_string());
+
+        let start = |vars| {
+            super::CommandStart::from_vars(&config, &clock, "ls".to_string(), vars)
+                .expect("can create command start")
+        };
+
+        let vars = super::vars_for_test(&[("HISTDB_RS_HOSTNAME", "container")]);
+        assert_eq!(start(vars).hostname, "container");
+
+        assert_eq!(start(super::vars_for_test(&[])).hostname, "configured");
+    }
+
+    #[test]
+    fn command_start_hist_event() {
+        let clock = crate::clock::FixedClock(chrono::Utc::now());
+        let config = crate::config::Config::default();
+
+        let hist_event = |hist_event| {
+            super::CommandStart::from_vars(
+                &config,
+                &clock,
+                "ls".to_string(),
+                super::vars_for_test(&[("HISTDB_RS_HIST_EVENT", hist_event)]),
+            )
+            .expect("can create command start")
+            .hist_event
+        };
+
+        assert_eq!(hist_event(" 42\n"), Some(42));
+        assert_eq!(hist_event("!42"), None);
+    }
+
+    #[test]
+    fn parse_duration_ms() {
+        assert_eq!(super::parse_duration_ms("1500"), Ok(1500));
+        assert_eq!(super::parse_duration_ms(" 20\n"), Ok(20));
+        assert!(super::parse_duration_ms("-5").is_err());
+        assert!(super::parse_duration_ms("1.5").is_err());
+    }
+}
@@ -1,11 +1,25 @@
-use std::path::PathBuf;
+use std::{
+    io::IsTerminal,
+    path::PathBuf,
+    str::FromStr,
+};
 
+use chrono::{
+    DateTime,
+    Local,
+    NaiveDate,
+    NaiveDateTime,
+    NaiveTime,
+    TimeZone,
+    Utc,
+};
 use clap::{
     AppSettings::{
         ColoredHelp,
         GlobalVersion,
         NextLineHelp,
     },
+    ArgGroup,
     CommandFactory,
     Parser,
     Subcommand,
@@ -14,15 +28,27 @@ use directories::{
     BaseDirs,
     ProjectDirs,
 };
-use log::error;
 use regex::Regex;
 use thiserror::Error;
+use uuid::Uuid;
 
 use crate::{
+    clock::{
+        Clock,
+        SystemClock,
+    },
     config,
+    config::CommandFormat,
+    message::session_id_from_env,
     run,
     run::{
+        Column,
+        Cursor,
         Display,
+        ExportFormat,
+        InitShell,
+        OutputFormat,
+        StatsGroup,
         TableDisplay,
     },
     store::Filter,
@@ -35,6 +61,12 @@ pub enum Error {
 
     #[error("can not get project dirs")]
     ProjectDirs,
+
+    #[error(
+        "can not parse time {0:?}, expected a duration like \"2 days ago\", a date like \
+         \"2024-01-01\" or a time like \"09:00\""
+    )]
+    InvalidTime(String),
 }
 
 fn project_dir() -> ProjectDirs {
@@ -63,6 +95,13 @@ fn default_cache_path() -> PathBuf {
     cache_path
 }
 
+fn default_import_state_path() -> PathBuf {
+    let project_dir = project_dir();
+    let state_path = project_dir.cache_dir().join("import").join("histdb.toml");
+
+    state_path
+}
+
 fn default_histdb_sqlite_path() -> PathBuf {
     let base_dirs = base_directory();
     let home = base_dirs.home_dir();
@@ -75,6 +114,12 @@ fn default_zsh_histfile_path() -> PathBuf {
     home.join(".histfile")
 }
 
+fn default_bash_history_path() -> PathBuf {
+    let base_dirs = base_directory();
+    let home = base_dirs.home_dir();
+    home.join(".bash_history")
+}
+
 fn default_socket_path() -> PathBuf {
     let project_dir = project_dir();
 
@@ -96,27 +141,398 @@ fn default_config_path() -> PathBuf {
     socket_path
 }
 
+/// Styling is disabled when `NO_COLOR` is set to a non empty value, see
+/// <https://no-color.org>.
+fn no_color_from_env() -> bool {
+    std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty())
+}
+
+/// Styles the table only when it is printed to a terminal so no escape codes
+/// end up in files or pipes.
+fn color_display(no_color: bool) -> Display {
+    Display::should_hide(no_color || no_color_from_env() || !std::io::stdout().is_terminal())
+}
+
+/// Time given on the command line. Relative times like `2 days ago` are
+/// resolved against the clock when the command runs.
+#[derive(Debug, Clone)]
+struct TimeArg(String);
+
+impl FromStr for TimeArg {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        // Only checks that the value can be parsed as the time it stands for
+        // depends on the clock.
+        parse_time_at(Local.timestamp(0, 0), value)?;
+
+        Ok(Self(value.to_string()))
+    }
+}
+
+impl TimeArg {
+    fn at(&self, clock: &impl Clock) -> Result<DateTime<Utc>, Error> {
+        parse_time_at(clock.now().with_timezone(&Local), &self.0)
+    }
+}
+
+/// Parses a relative duration like `2 days ago`, a date like `2024-01-01`, a
+/// date with time like `2024-01-01 09:00` or a time of today like `09:00`
+/// relative to `now`. Dates and times are taken in the local timezone.
+fn parse_time_at(now: DateTime<Local>, value: &str) -> Result<DateTime<Utc>, Error> {
+    let value = value.trim();
+    let invalid = || Error::InvalidTime(value.to_string());
+
+    let relative = value.strip_suffix("ago").unwrap_or(value).trim();
+    if let Ok(duration) = humantime::parse_duration(relative) {
+        let duration = chrono::Duration::from_std(duration).map_err(|_| invalid())?;
+
+        return Ok((now - duration).with_timezone(&Utc));
+    }
+
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(time.with_timezone(&Utc));
+    }
+
+    let local = |naive: NaiveDateTime| {
+        Local
+            .from_local_datetime(&naive)
+            .single()
+            .map(|time| time.with_timezone(&Utc))
+            .ok_or_else(invalid)
+    };
+
+    for format in &["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M"] {
+        if let Ok(naive) = NaiveDateTime::parse_from_str(value, format) {
+            return local(naive);
+        }
+    }
+
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return local(date.and_hms(0, 0, 0));
+    }
+
+    for format in &["%H:%M:%S", "%H:%M"] {
+        if let Ok(time) = NaiveTime::parse_from_str(value, format) {
+            return local(now.date().naive_local().and_time(time));
+        }
+    }
+
+    Err(invalid())
+}
+
 #[derive(Parser, Debug)]
 struct ZSHAddHistory {
     #[clap(flatten)]
     socket_path: Socket,
 
-    /// Command to add to history
-    #[clap(index = 1)]
-    command: String,
+    /// Read the command from stdin instead of the argument
+    #[clap(long)]
+    stdin: bool,
+
+    /// Command to add to history. "-" reads the command from stdin
+    #[clap(index = 1, required_unless_present = "stdin", conflicts_with = "stdin")]
+    command: Option<String>,
 }
 
 #[derive(Parser, Debug)]
 struct Server {
-    /// Path to the cachefile used to store entries between restarts
-    #[clap(short, long, default_value_os_t = default_cache_path())]
-    cache_path: PathBuf,
+    /// Path to the cachefile used to store entries between restarts.
+    /// Defaults to the cache path of the profile or the user cache dir
+    #[clap(short, long)]
+    cache_path: Option<PathBuf>,
+
+    #[clap(flatten)]
+    data_dir: DataDir,
+
+    #[clap(flatten)]
+    socket_path: Socket,
+
+    /// Also accept recorded commands on this TCP address, e.g.
+    /// `tcp://0.0.0.0:9999`. Without `auth_token` in the config anyone who can
+    /// reach the address can add entries to the history
+    #[clap(long, parse(try_from_str = crate::transport::parse_tcp_address))]
+    listen: Option<String>,
+}
+
+#[derive(Parser, Debug)]
+struct Gc {
+    #[clap(flatten)]
+    data_dir: DataDir,
+
+    #[clap(flatten)]
+    socket_path: Socket,
+
+    /// Only remove entries that were deleted longer ago than this
+    #[clap(long, default_value = "30days")]
+    older_than: humantime::Duration,
+}
+
+#[derive(Parser, Debug)]
+struct Compress {
+    #[clap(flatten)]
+    data_dir: DataDir,
+
+    #[clap(flatten)]
+    socket_path: Socket,
+
+    /// Only compress history files that did not change for this long
+    #[clap(long, default_value = "90days")]
+    older_than: humantime::Duration,
+
+    /// Commit the compressed history files to git
+    #[clap(long)]
+    commit: bool,
+}
+
+#[derive(Parser, Debug)]
+struct CacheGc {
+    /// Path to the cachefile used to store entries between restarts.
+    /// Defaults to the cache path of the profile or the user cache dir
+    #[clap(short, long)]
+    cache_path: Option<PathBuf>,
+
+    #[clap(flatten)]
+    socket_path: Socket,
+
+    /// Also remove started commands that did not finish for this long
+    #[clap(long)]
+    stale_after: Option<humantime::Duration>,
+}
+
+#[derive(Parser, Debug)]
+struct Usage {
+    #[clap(flatten)]
+    data_dir: DataDir,
+
+    /// Path to the cachefile used to store entries between restarts.
+    /// Defaults to the cache path of the profile or the user cache dir
+    #[clap(short, long)]
+    cache_path: Option<PathBuf>,
+}
+
+#[derive(Parser, Debug)]
+struct Trim {
+    #[clap(flatten)]
+    data_dir: DataDir,
+
+    #[clap(flatten)]
+    socket_path: Socket,
+
+    /// How many entries to keep for every host. Defaults to the configured
+    /// maximum
+    #[clap(long)]
+    max_entries: Option<usize>,
+
+    /// Commit the trimmed history files to git
+    #[clap(long)]
+    commit: bool,
+}
+
+#[derive(Parser, Debug)]
+#[clap(group(
+    ArgGroup::new("limit")
+        .required(true)
+        .multiple(true)
+        .args(&["older-than", "keep-last"])
+))]
+struct Prune {
+    #[clap(flatten)]
+    data_dir: DataDir,
+
+    #[clap(flatten)]
+    socket_path: Socket,
+
+    /// Remove entries that finished longer ago than this
+    #[clap(long)]
+    older_than: Option<humantime::Duration>,
+
+    /// Only keep this many of the newest entries of every host
+    #[clap(long)]
+    keep_last: Option<usize>,
+
+    /// Only print how many entries would be removed from every history file
+    #[clap(long)]
+    dry_run: bool,
+}
+
+#[derive(Parser, Debug)]
+struct Check {
+    #[clap(flatten)]
+    data_dir: DataDir,
 
+    #[clap(flatten)]
+    socket_path: Socket,
+
+    /// Report session ids that appear on more than one host
+    #[clap(long)]
+    sessions: bool,
+
+    /// Report rows of the log files that can not be read as an entry
+    #[clap(long)]
+    rows: bool,
+
+    /// Rewrite the log files without the rows that can not be read. The log
+    /// files are committed first when the data dir is a git repository
+    #[clap(long, conflicts_with = "sessions")]
+    repair: bool,
+}
+
+#[derive(Parser, Debug)]
+struct Scrub {
     #[clap(flatten)]
     data_dir: DataDir,
 
     #[clap(flatten)]
     socket_path: Socket,
+
+    /// Regex of the sensitive data to replace with ***
+    #[clap(long)]
+    pattern: Regex,
+
+    /// Commit the scrubbed history files to git
+    #[clap(long)]
+    commit: bool,
+
+    /// Also remove the sensitive data from all earlier commits with git
+    /// filter-repo, which has to be installed. This rewrites the git history
+    #[clap(long, requires = "commit")]
+    rewrite_history: bool,
+}
+
+#[derive(Parser, Debug)]
+struct VerifyGit {
+    #[clap(flatten)]
+    data_dir: DataDir,
+
+    /// Commit pending changes to reconcile the data directory with git
+    #[clap(long)]
+    commit: bool,
+}
+
+#[derive(Parser, Debug)]
+struct Init {
+    /// Shell for which to print the shell functions
+    #[clap(long, arg_enum, default_value = "zsh")]
+    shell: InitShell,
+
+    /// Also print widgets that bind Ctrl-X Ctrl-R to replace the command line
+    /// with the most recent matching command
+    #[clap(long)]
+    with_widgets: bool,
+}
+
+#[derive(Parser, Debug)]
+struct Stats {
+    #[clap(flatten)]
+    data_dir: DataDir,
+
+    #[clap(flatten)]
+    filter: FilterArgs,
+
+    /// How many of the most used values to print
+    #[clap(short = 'n', long, default_value = "10")]
+    top: usize,
+
+    /// What to count the entries by
+    #[clap(long, arg_enum, default_value = "command")]
+    by: StatsGroup,
+
+    /// Compare the counts of the given last period, e.g. "7d", with the
+    /// period of the same length before it
+    #[clap(long)]
+    compare: Option<humantime::Duration>,
+}
+
+#[derive(Parser, Debug)]
+struct CurrentSession {
+    #[clap(flatten)]
+    data_dir: DataDir,
+
+    #[clap(flatten)]
+    filter: FilterArgs,
+
+    /// Show the commands of the given session instead of the current one
+    #[clap(long, conflicts_with = "session")]
+    session_id: Option<Uuid>,
+
+    /// Print entries as a JSON array, same as --output json
+    #[clap(long)]
+    json: bool,
+
+    /// How to print the entries
+    #[clap(long, arg_enum, default_value = "table", conflicts_with = "json")]
+    output: OutputFormat,
+
+    /// Comma separated columns to print in the given order
+    #[clap(long, value_delimiter = ',', default_value = "time,cmd")]
+    columns: Vec<Column>,
+}
+
+#[derive(Parser, Debug)]
+struct Last {
+    #[clap(flatten)]
+    data_dir: DataDir,
+
+    #[clap(flatten)]
+    filter: FilterArgs,
+
+    /// Print the entry as a JSON object, same as --output json
+    #[clap(long)]
+    json: bool,
+
+    /// How to print the entry. The JSON formats print a single object
+    #[clap(long, arg_enum, default_value = "table", conflicts_with = "json")]
+    output: OutputFormat,
+
+    /// Comma separated columns to print in the given order
+    #[clap(
+        long,
+        value_delimiter = ',',
+        default_value = "time,duration,status,cmd"
+    )]
+    columns: Vec<Column>,
+}
+
+#[derive(Parser, Debug)]
+#[clap(group(
+    ArgGroup::new("match")
+        .required(true)
+        .multiple(true)
+        .args(&["command", "command-text", "search", "session"])
+))]
+struct Delete {
+    #[clap(flatten)]
+    data_dir: DataDir,
+
+    /// Path to the socket for communication with the server. Defaults to the
+    /// socket path of the profile or the user runtime dir
+    #[clap(long, env = "HISTDBRS_SOCKET_PATH")]
+    socket_path: Option<PathBuf>,
+
+    #[clap(flatten)]
+    filter: FilterArgs,
+
+    /// Only print how many entries would be deleted in every history file
+    #[clap(long)]
+    dry_run: bool,
+}
+
+#[derive(Parser, Debug)]
+struct Export {
+    #[clap(flatten)]
+    data_dir: DataDir,
+
+    #[clap(flatten)]
+    filter: FilterArgs,
+
+    /// Format of the exported file
+    #[clap(long, arg_enum, default_value = "csv")]
+    format: ExportFormat,
+
+    /// Path of the file to write the entries to
+    #[clap(short, long)]
+    output: PathBuf,
 }
 
 #[derive(Subcommand, Debug)]
@@ -127,6 +543,15 @@ enum Import {
 
     /// Import entries from existing zsh histfile
     Histfile(ImportHistfile),
+
+    /// Import entries from a bash history file written with HISTTIMEFORMAT set
+    Bash(ImportBash),
+
+    /// Import entries from a bash history file without timestamps
+    BashPlain(ImportBashPlain),
+
+    /// Import entries from a JSON array written by export --format json
+    Json(ImportJson),
 }
 
 #[derive(Parser, Debug)]
@@ -134,9 +559,25 @@ struct ImportHistdb {
     #[clap(flatten)]
     data_dir: DataDir,
 
+    #[clap(flatten)]
+    socket_path: Socket,
+
+    #[clap(flatten)]
+    dedup: Dedup,
+
     /// Path to the existing histdb sqlite file
     #[clap(short, long, default_value_os_t = default_histdb_sqlite_path())]
     import_file: PathBuf,
+
+    /// Only import entries that are newer than the ones from the last
+    /// incremental import
+    #[clap(long)]
+    incremental: bool,
+
+    /// Path to the file in which incremental imports remember what they
+    /// already imported
+    #[clap(long, default_value_os_t = default_import_state_path())]
+    state_path: PathBuf,
 }
 
 #[derive(Parser, Debug)]
@@ -144,16 +585,80 @@ struct ImportHistfile {
     #[clap(flatten)]
     data_dir: DataDir,
 
+    #[clap(flatten)]
+    socket_path: Socket,
+
+    #[clap(flatten)]
+    dedup: Dedup,
+
     /// Path to the existing zsh histfile file
     #[clap(short, long, default_value_os_t = default_zsh_histfile_path())]
     import_file: PathBuf,
+
+    /// Strip the \r of CRLF line endings from the histfile lines
+    #[clap(long, default_value_t = true, parse(try_from_str))]
+    normalize_newlines: bool,
+}
+
+#[derive(Parser, Debug)]
+struct ImportBash {
+    #[clap(flatten)]
+    data_dir: DataDir,
+
+    #[clap(flatten)]
+    socket_path: Socket,
+
+    #[clap(flatten)]
+    dedup: Dedup,
+
+    /// Path to the existing bash history file
+    #[clap(short, long, default_value_os_t = default_bash_history_path())]
+    import_file: PathBuf,
+}
+
+#[derive(Parser, Debug)]
+struct ImportBashPlain {
+    #[clap(flatten)]
+    data_dir: DataDir,
+
+    #[clap(flatten)]
+    socket_path: Socket,
+
+    #[clap(flatten)]
+    dedup: Dedup,
+
+    /// Path to the existing bash history file
+    #[clap(short, long, default_value_os_t = default_bash_history_path())]
+    import_file: PathBuf,
+
+    /// Time of the first command, every following command is one second
+    /// later. Defaults to the last command finishing now
+    #[clap(long)]
+    base_time: Option<TimeArg>,
+}
+
+#[derive(Parser, Debug)]
+struct ImportJson {
+    #[clap(flatten)]
+    data_dir: DataDir,
+
+    #[clap(flatten)]
+    socket_path: Socket,
+
+    #[clap(flatten)]
+    dedup: Dedup,
+
+    /// Path to the JSON file with the entries
+    #[clap(short, long)]
+    import_file: PathBuf,
 }
 
 #[derive(Parser, Debug)]
 struct Socket {
-    /// Path to the socket for communication with the server
-    #[clap(short, long, env = "HISTDBRS_SOCKET_PATH", default_value_os_t = default_socket_path())]
-    socket_path: PathBuf,
+    /// Path to the socket for communication with the server. Defaults to the
+    /// socket path of the profile or the user runtime dir
+    #[clap(short, long, env = "HISTDBRS_SOCKET_PATH")]
+    socket_path: Option<PathBuf>,
 }
 
 #[derive(Parser, Debug)]
@@ -161,29 +666,32 @@ struct Config {
     /// Path to the socket for communication with the server
     #[clap(long, env = "HISTDBRS_CONFIG_PATH", default_value_os_t = default_config_path())]
     config_path: PathBuf,
+
+    /// Use the data dir, socket and cache paths of the given profile from the
+    /// config file
+    #[clap(long, global = true, env = "HISTDB_RS_PROFILE")]
+    profile: Option<String>,
+}
+
+#[derive(Parser, Debug)]
+struct Dedup {
+    /// Skip entries that are already stored with the same finish time,
+    /// command and directory
+    #[clap(long, alias = "dedup-on-import")]
+    dedup: bool,
 }
 
 #[derive(Parser, Debug)]
 struct DataDir {
-    /// Path to folder in which to store the history files
-    #[clap(
-        short,
-        long,
-        default_value_os_t = default_data_dir()
-    )]
-    data_dir: PathBuf,
+    /// Path to folder in which to store the history files. Defaults to the
+    /// data dir of the profile or the user data dir
+    #[clap(short, long)]
+    data_dir: Option<PathBuf>,
 }
 
 #[allow(clippy::struct_excessive_bools)]
 #[derive(Parser, Debug)]
-struct DefaultArgs {
-    #[clap(flatten)]
-    data_dir: DataDir,
-
-    /// How many entries to print
-    #[clap(short, long, default_value = "25")]
-    entries_count: usize,
-
+struct FilterArgs {
     /// Only print entries beginning with the given command
     #[clap(short, long)]
     command: Option<String>,
@@ -192,6 +700,11 @@ struct DefaultArgs {
     #[clap(short = 't', long = "text")]
     command_text: Option<Regex>,
 
+    /// Only print entries containing all words of the given text, ignoring
+    /// case
+    #[clap(short, long)]
+    search: Option<String>,
+
     /// Only print entries not containing the given regex
     #[clap(short = 'T', long = "text_excluded")]
     command_text_excluded: Option<Regex>,
@@ -204,30 +717,175 @@ struct DefaultArgs {
     #[clap(short, long)]
     folder: Option<PathBuf>,
 
-    /// Exclude subdirectories when filtering by folder
+    /// Only print entries that have been executed in the git repository of the
+    /// current directory, including its subdirectories
+    #[clap(long, conflicts_with_all = &["in-current", "folder"])]
+    project: bool,
+
+    /// Do not print entries that have been executed in the given directory.
+    /// Can be given multiple times
     #[clap(long)]
+    exclude_folder: Vec<PathBuf>,
+
+    /// Exclude subdirectories when filtering by folder
+    #[clap(long, conflicts_with = "subdirs")]
     no_subdirs: bool,
 
-    /// Filter by given hostname
+    /// Include subdirectories when filtering by folder
+    #[clap(long)]
+    subdirs: bool,
+
+    /// Filter by given hostname. Can be given multiple times
     #[clap(long, conflicts_with = "all-hosts")]
-    hostname: Option<String>,
+    hostname: Vec<String>,
 
     /// Filter by given session
     #[clap(long)]
     session: Option<Regex>,
 
+    /// Filter by user that ran the command
+    #[clap(long)]
+    user: Option<String>,
+
+    /// Only print commands that were recorded with the given tag, see
+    /// `HISTDB_RS_TAG`
+    #[clap(long)]
+    tag: Option<String>,
+
     /// Print all hosts
     #[clap(long)]
     all_hosts: bool,
 
+    /// Filter out failed commands (return code not 0)
+    #[clap(long)]
+    filter_failed: bool,
+
+    /// Only print failed commands (return code not 0)
+    #[clap(long, conflicts_with = "filter-failed")]
+    only_failed: bool,
+
+    /// Find commands with the given return code
+    #[clap(long)]
+    find_status: Option<u16>,
+
+    /// Also print entries that have been marked as deleted
+    #[clap(long)]
+    include_deleted: bool,
+
+    /// Only print the most recent entry of every command. The number of
+    /// entries to print counts distinct commands
+    #[clap(short, long)]
+    unique: bool,
+
+    /// Only print commands with at least the given number of pipes
+    #[clap(long)]
+    min_pipes: Option<usize>,
+
+    /// Only print entries whose directory still exists
+    #[clap(long, conflicts_with = "missing-dir")]
+    existing_dir: bool,
+
+    /// Only print entries whose directory does not exist anymore
+    #[clap(long)]
+    missing_dir: bool,
+
+    /// Only print entries that finished after the given time, e.g. "2 days
+    /// ago", "2024-01-01" or "09:00"
+    #[clap(long)]
+    since: Option<TimeArg>,
+
+    /// Only print entries that finished before the given time, e.g. "2 days
+    /// ago", "2024-01-01" or "09:00"
+    #[clap(long)]
+    until: Option<TimeArg>,
+}
+
+impl FilterArgs {
+    fn into_filter(
+        self,
+        config: &config::Config,
+        clock: &impl Clock,
+        count: usize,
+    ) -> Result<Filter, run::Error> {
+        let no_subdirs = config
+            .directory_match
+            .no_subdirs(self.subdirs, self.no_subdirs);
+
+        Ok(Filter::default()
+            .directory(self.folder, self.in_current, no_subdirs)?
+            .project(self.project)?
+            .directory_excluded(self.exclude_folder)
+            .hostnames(
+                // The system hostname is used when there is no override.
+                if self.hostname.is_empty() {
+                    config.hostname_override().into_iter().collect()
+                } else {
+                    self.hostname
+                },
+                self.all_hosts,
+            )?
+            .count(count)
+            .command(self.command, self.command_text, self.command_text_excluded)
+            .search(self.search)
+            .session(self.session)
+            .user(self.user)
+            .tag(self.tag)
+            .filter_failed(self.filter_failed)
+            .only_failed(self.only_failed)
+            .find_status(self.find_status)
+            .include_deleted(self.include_deleted)
+            .unique(self.unique)
+            .min_pipes(self.min_pipes)
+            .since(self.since.map(|time| time.at(clock)).transpose()?)
+            .until(self.until.map(|time| time.at(clock)).transpose()?)
+            .hostname_aliases(config.hostname_aliases().clone())
+            .pwd_exists(if self.existing_dir {
+                Some(true)
+            } else if self.missing_dir {
+                Some(false)
+            } else {
+                None
+            }))
+    }
+}
+
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Parser, Debug)]
+struct DefaultArgs {
+    #[clap(flatten)]
+    data_dir: DataDir,
+
+    /// How many entries to print. Defaults to the configured number or 25
+    #[clap(short, long)]
+    entries_count: Option<usize>,
+
+    #[clap(flatten)]
+    filter: FilterArgs,
+
     /// Disable fancy formatting
     #[clap(long)]
     disable_formatting: bool,
 
+    /// Print entries as a JSON array, same as --output json
+    #[clap(long, conflicts_with = "disable-formatting")]
+    json: bool,
+
+    /// How to print the entries
+    #[clap(long, arg_enum, conflicts_with_all = &["json", "disable-formatting"])]
+    output: Option<OutputFormat>,
+
+    /// Write the entries to the given file or named pipe instead of stdout
+    #[clap(long, conflicts_with = "page-size")]
+    output_file: Option<PathBuf>,
+
     /// Print host column
     #[clap(long)]
     show_host: bool,
 
+    /// Show user that ran the command
+    #[clap(long)]
+    show_user: bool,
+
     /// Print returncode of command
     #[clap(long)]
     show_status: bool,
@@ -244,17 +902,104 @@ struct DefaultArgs {
     #[clap(long)]
     show_session: bool,
 
+    /// Show how long ago the command finished
+    #[clap(long)]
+    show_relative: bool,
+
+    /// Show zsh history event number for command
+    #[clap(long)]
+    show_hist_event: bool,
+
+    /// Show how many pipes the command has
+    #[clap(long)]
+    show_pipes: bool,
+
+    /// Show the tag the command was recorded with
+    #[clap(long)]
+    show_tag: bool,
+
     /// Disable printing of header
     #[clap(long)]
     hide_header: bool,
 
-    /// Filter out failed commands (return code not 0)
+    /// Do not style the table. Also disabled when the `NO_COLOR` environment
+    /// variable is set
     #[clap(long)]
-    filter_failed: bool,
+    no_color: bool,
 
-    /// Find commands with the given return code
+    /// Comma separated columns to print in the given order, e.g.
+    /// "time,pwd,cmd". Replaces the --show-* flags
+    #[clap(
+        long,
+        value_delimiter = ',',
+        conflicts_with_all = &[
+            "show-host",
+            "show-user",
+            "show-status",
+            "show-duration",
+            "show-pwd",
+            "show-session",
+            "show-relative",
+            "show-hist-event",
+            "show-pipes",
+            "show-tag",
+        ]
+    )]
+    columns: Vec<Column>,
+
+    /// Separate the entries of different sessions with an empty row. Sessions
+    /// that ran at the same time show up as more than one group
     #[clap(long)]
-    find_status: Option<u16>,
+    group_sessions: bool,
+
+    /// How to print commands that span multiple lines. Defaults to the
+    /// configured format or multiline
+    #[clap(long, arg_enum)]
+    format_command: Option<CommandFormat>,
+
+    /// Separate the columns of the plain output with the given string instead
+    /// of a tab
+    #[clap(long, alias = "separator")]
+    field_separator: Option<String>,
+
+    /// Separate the columns and rows of the plain output with a NUL byte so
+    /// commands with whitespace can be split unambiguously. Commands keep
+    /// their line breaks instead of printing them as \n. The columns are
+    /// separated with --field-separator instead if it is given
+    #[clap(long)]
+    print0: bool,
+
+    /// Print the newest entry first. Still prints the newest entries when
+    /// limiting the count
+    #[clap(short, long)]
+    reverse: bool,
+
+    /// Print the given number of the newest entries as a JSON object together
+    /// with the cursor of the next page
+    #[clap(long, conflicts_with_all = &["output", "disable-formatting", "count-then-confirm"])]
+    page_size: Option<usize>,
+
+    /// Continue paging after the entries of a previous page. Takes the next
+    /// cursor printed with the previous page
+    #[clap(long, requires = "page-size")]
+    cursor: Option<Cursor>,
+
+    /// Only print the number of matching entries. The number of entries to
+    /// print is ignored
+    #[clap(
+        long,
+        conflicts_with_all = &["output", "json", "page-size", "count-then-confirm", "output-file"]
+    )]
+    count_only: bool,
+
+    /// Print the number of matching entries before printing them and ask for
+    /// confirmation when there are more than --confirm-over
+    #[clap(long)]
+    count_then_confirm: bool,
+
+    /// How many matching entries can be printed without confirmation
+    #[clap(long, default_value = "1000")]
+    confirm_over: usize,
 
     #[clap(flatten)]
     config: Config,
@@ -274,6 +1019,11 @@ enum SubCommand {
     #[clap(name = "stop")]
     Stop(Socket),
 
+    /// Wait until the server committed all written entries to the git
+    /// repository of the data dir
+    #[clap(name = "flush")]
+    Flush(Socket),
+
     /// Disable history recording for current session
     #[clap(name = "disable")]
     Disable(Socket),
@@ -282,26 +1032,95 @@ enum SubCommand {
     #[clap(name = "enable")]
     Enable(Socket),
 
+    /// Set start time of command for current session to now
+    #[clap(name = "preexec")]
+    PreExec(Socket),
+
     /// Finish command for current session
     #[clap(name = "precmd")]
     PreCmd(Socket),
 
+    /// Show commands that are currently running
+    #[clap(name = "top")]
+    Top(Socket),
+
+    /// Check if the server is running. Exits with 3 if it is not
+    #[clap(name = "status")]
+    Status(Socket),
+
     /// Get new session id
     #[clap(name = "session_id")]
     SessionID,
 
-    /// Import entries from existing histdb sqlite or zsh histfile
+    /// Import entries from existing histdb sqlite, zsh histfile or JSON export
     #[clap(subcommand, name = "import")]
     Import(Import),
 
     /// Print out shell functions needed by histdb and set current session id
     #[clap(name = "init")]
-    Init,
+    Init(Init),
 
     /// Run benchmark against server
     #[clap(name = "bench")]
     Bench(Socket),
 
+    /// Mark the entries that match the filter as deleted. Needs one of
+    /// --command, --text, --search or --session
+    #[clap(name = "delete")]
+    Delete(Delete),
+
+    /// Permanently remove entries that have been marked as deleted
+    #[clap(name = "gc")]
+    Gc(Gc),
+
+    /// Compress history files that did not change for a while with gzip
+    #[clap(name = "compress")]
+    Compress(Compress),
+
+    /// Compact the server cache and optionally remove stale entries
+    #[clap(name = "cache-gc")]
+    CacheGc(CacheGc),
+
+    /// Show how much disk space the history files, git and the cache use
+    #[clap(name = "usage")]
+    Usage(Usage),
+
+    /// Only keep the most recent entries of every host
+    #[clap(name = "trim")]
+    Trim(Trim),
+
+    /// Remove old entries from the history files and commit the result
+    #[clap(name = "prune")]
+    Prune(Prune),
+
+    /// Check the stored entries for inconsistencies
+    #[clap(name = "check")]
+    Check(Check),
+
+    /// Replace sensitive data in the stored commands with ***
+    #[clap(name = "scrub")]
+    Scrub(Scrub),
+
+    /// Check that the data directory matches what is committed to git
+    #[clap(name = "verify-git")]
+    VerifyGit(VerifyGit),
+
+    /// Print the most used commands, directories, hosts or hours
+    #[clap(name = "stats")]
+    Stats(Stats),
+
+    /// Show the commands of the current session
+    #[clap(name = "session")]
+    Session(CurrentSession),
+
+    /// Print the most recent entry. Fails if no entry matches
+    #[clap(name = "last")]
+    Last(Last),
+
+    /// Write the entries of all hosts into a single file
+    #[clap(name = "export")]
+    Export(Export),
+
     /// Generate autocomplete files for shells
     #[clap(name = "completion")]
     Completion(CompletionOpts),
@@ -326,91 +1145,412 @@ pub struct Opt {
     sub_command: Option<SubCommand>,
 }
 
+impl DefaultArgs {
+    /// Returns the columns given with `--columns` or the default columns
+    /// together with the ones enabled with the `--show-*` flags.
+    fn columns(&self) -> Vec<Column> {
+        if !self.columns.is_empty() {
+            return self.columns.clone();
+        }
+
+        Column::ALL
+            .iter()
+            .copied()
+            .filter(|column| match column {
+                Column::Time | Column::Command => true,
+                Column::Relative => self.show_relative,
+                Column::Host => self.show_host,
+                Column::User => self.show_user,
+                Column::Duration => self.show_duration,
+                Column::Status => self.show_status,
+                Column::Session => self.show_session,
+                Column::HistEvent => self.show_hist_event,
+                Column::Pipes => self.show_pipes,
+                Column::Tag => self.show_tag,
+                Column::Pwd => self.show_pwd,
+            })
+            .collect()
+    }
+}
+
 impl Opt {
+    #[allow(clippy::too_many_lines)]
     pub fn run(self) -> Result<(), run::Error> {
         let sub_command = self.sub_command;
-        let in_current = self.default_args.in_current;
-        let folder = self.default_args.folder;
-        let all_hosts = self.default_args.all_hosts;
-        let hostname = self.default_args.hostname;
-        let data_dir = self.default_args.data_dir.data_dir;
-        let entries_count = self.default_args.entries_count;
-        let command = self.default_args.command;
-        let session_filter = self.default_args.session;
-        let no_subdirs = self.default_args.no_subdirs;
-        let command_text = self.default_args.command_text;
-        let command_text_excluded = self.default_args.command_text_excluded;
-        let filter_failed = self.default_args.filter_failed;
-        let find_status = self.default_args.find_status;
+        let columns = self.default_args.columns();
+        let filter_args = self.default_args.filter;
         let config = config::Config::open(self.default_args.config.config_path)
             .map_err(run::Error::ReadConfig)?;
+        let entries_count = self
+            .default_args
+            .entries_count
+            .or(config.default_entries_count)
+            .unwrap_or(25);
 
-        let format = !self.default_args.disable_formatting;
-        let duration = Display::should_show(self.default_args.show_duration);
+        let profile_name = self.default_args.config.profile;
+        let data_dir = self.default_args.data_dir.data_dir;
+
+        let output = if self.default_args.json {
+            OutputFormat::Json
+        } else if self.default_args.disable_formatting {
+            OutputFormat::Plain
+        } else {
+            self.default_args.output.unwrap_or(OutputFormat::Table)
+        };
         let header = Display::should_hide(self.default_args.hide_header);
-        let host = Display::should_show(self.default_args.show_host);
-        let pwd = Display::should_show(self.default_args.show_pwd);
-        let session = Display::should_show(self.default_args.show_session);
-        let status = Display::should_show(self.default_args.show_status);
+        let color =
+            color_display(self.default_args.no_color || self.default_args.output_file.is_some());
+        let confirm_over = if self.default_args.count_then_confirm {
+            Some(self.default_args.confirm_over)
+        } else {
+            None
+        };
 
         if std::env::var_os("RUST_LOG").is_none() {
             std::env::set_var("RUST_LOG", config.log_level.as_str());
         }
         pretty_env_logger::init();
 
+        let clock = SystemClock;
+
+        let profile = config
+            .profile(profile_name.as_deref())
+            .map_err(run::Error::SelectProfile)?;
+        let data_dir = profile.data_dir(data_dir, default_data_dir);
+
         sub_command.map_or_else(
             || {
-                let filter = Filter::default()
-                    .directory(folder, in_current, no_subdirs)?
-                    .hostname(hostname, all_hosts)?
-                    .count(entries_count)
-                    .command(command, command_text, command_text_excluded)
-                    .session(session_filter)
-                    .filter_failed(filter_failed)
-                    .find_status(find_status);
+                // Count all matching entries, not only the ones that would be
+                // printed.
+                if self.default_args.count_only {
+                    let filter = filter_args.into_filter(&config, &clock, 0)?;
+
+                    return run::count(&filter, data_dir, config.backend);
+                }
+
+                if let Some(page_size) = self.default_args.page_size {
+                    let filter = filter_args.into_filter(&config, &clock, 0)?;
+
+                    return run::page(
+                        &filter,
+                        data_dir,
+                        config.backend,
+                        page_size,
+                        self.default_args.cursor,
+                    );
+                }
+
+                let filter = filter_args.into_filter(&config, &clock, entries_count)?;
 
                 let display = TableDisplay {
-                    format,
+                    output,
+                    reverse: self.default_args.reverse,
 
-                    duration,
                     header,
-                    host,
-                    pwd,
-                    session,
-                    status,
+                    color,
+                    columns,
+                    group_sessions: self.default_args.group_sessions,
+
+                    field_separator: self.default_args.field_separator.unwrap_or_else(|| {
+                        if self.default_args.print0 { "\0" } else { "\t" }.to_string()
+                    }),
+                    record_separator: if self.default_args.print0 { "\0" } else { "\n" }
+                        .to_string(),
+
+                    timestamp_format: config.timestamp_format.clone(),
+                    timestamp_format_today: config.timestamp_format_today.clone(),
+
+                    command_format: self
+                        .default_args
+                        .format_command
+                        .unwrap_or(config.format_command),
+
+                    pwd_abbreviations: config.pwd_abbreviations.clone(),
                 };
 
-                run::default(&filter, &display, data_dir)
+                run::default(
+                    &clock,
+                    &filter,
+                    &display,
+                    data_dir,
+                    config.backend,
+                    confirm_over,
+                    self.default_args.output_file.as_deref(),
+                )
             },
             |sub_command| match sub_command {
-                SubCommand::ZSHAddHistory(o) => {
-                    run::zsh_add_history(&config, o.command, o.socket_path.socket_path)
-                }
-                SubCommand::Server(o) => {
-                    run::server(o.cache_path, o.socket_path.socket_path, o.data_dir.data_dir)
-                }
-                SubCommand::Stop(o) => run::stop(o.socket_path),
-                SubCommand::Disable(o) => run::disable(o.socket_path),
-                SubCommand::Enable(o) => run::enable(o.socket_path),
-                SubCommand::PreCmd(o) => run::precmd(o.socket_path),
+                SubCommand::ZSHAddHistory(o) => run::zsh_add_history(
+                    &config,
+                    &clock,
+                    o.command.filter(|command| command != "-"),
+                    profile.socket_path(o.socket_path.socket_path, default_socket_path),
+                ),
+                SubCommand::Server(o) => run::server(
+                    &config,
+                    profile.cache_path(o.cache_path, default_cache_path),
+                    profile.socket_path(o.socket_path.socket_path, default_socket_path),
+                    profile.data_dir(o.data_dir.data_dir, default_data_dir),
+                    o.listen,
+                ),
+                SubCommand::Stop(o) => run::stop(
+                    &config,
+                    profile.socket_path(o.socket_path, default_socket_path),
+                ),
+                SubCommand::Flush(o) => run::flush(
+                    &config,
+                    profile.socket_path(o.socket_path, default_socket_path),
+                ),
+                SubCommand::Disable(o) => run::disable(
+                    &config,
+                    profile.socket_path(o.socket_path, default_socket_path),
+                ),
+                SubCommand::Enable(o) => run::enable(
+                    &config,
+                    profile.socket_path(o.socket_path, default_socket_path),
+                ),
+                SubCommand::PreExec(o) => run::preexec(
+                    &config,
+                    &clock,
+                    profile.socket_path(o.socket_path, default_socket_path),
+                ),
+                SubCommand::PreCmd(o) => run::precmd(
+                    &config,
+                    &clock,
+                    profile.socket_path(o.socket_path, default_socket_path),
+                ),
+                SubCommand::Top(o) => run::running(
+                    &config,
+                    &clock,
+                    profile.socket_path(o.socket_path, default_socket_path),
+                ),
+                SubCommand::Status(o) => run::status(
+                    &config,
+                    profile.socket_path(o.socket_path, default_socket_path),
+                ),
                 SubCommand::SessionID => {
                     run::session_id();
                     Ok(())
                 }
                 SubCommand::Import(s) => match s {
                     #[cfg(feature = "histdb-import")]
-                    Import::Histdb(o) => run::import::histdb(&o.import_file, o.data_dir.data_dir)
-                        .map_err(run::Error::ImportHistdb),
-                    Import::Histfile(o) => {
-                        run::import::histfile(&o.import_file, o.data_dir.data_dir)
-                            .map_err(run::Error::ImportHistfile)
+                    Import::Histdb(o) => run::import::histdb(
+                        &config,
+                        &o.import_file,
+                        profile.data_dir(o.data_dir.data_dir, default_data_dir),
+                        &profile.socket_path(o.socket_path.socket_path, default_socket_path),
+                        if o.incremental {
+                            Some(o.state_path)
+                        } else {
+                            None
+                        },
+                        o.dedup.dedup,
+                    )
+                    .map_err(run::Error::ImportHistdb),
+                    Import::Histfile(o) => run::import::histfile(
+                        &config,
+                        &o.import_file,
+                        profile.data_dir(o.data_dir.data_dir, default_data_dir),
+                        &profile.socket_path(o.socket_path.socket_path, default_socket_path),
+                        o.normalize_newlines,
+                        o.dedup.dedup,
+                    )
+                    .map_err(run::Error::ImportHistfile),
+                    Import::Bash(o) => run::import::bash(
+                        &config,
+                        &o.import_file,
+                        profile.data_dir(o.data_dir.data_dir, default_data_dir),
+                        &profile.socket_path(o.socket_path.socket_path, default_socket_path),
+                        o.dedup.dedup,
+                    )
+                    .map_err(run::Error::ImportBash),
+                    Import::BashPlain(o) => {
+                        let base_time = o.base_time.map(|time| time.at(&clock)).transpose()?;
+
+                        run::import::bash_plain(
+                            &config,
+                            &clock,
+                            &o.import_file,
+                            profile.data_dir(o.data_dir.data_dir, default_data_dir),
+                            &profile.socket_path(o.socket_path.socket_path, default_socket_path),
+                            base_time,
+                            o.dedup.dedup,
+                        )
+                        .map_err(run::Error::ImportBash)
                     }
+                    Import::Json(o) => run::import::json(
+                        &config,
+                        &o.import_file,
+                        profile.data_dir(o.data_dir.data_dir, default_data_dir),
+                        &profile.socket_path(o.socket_path.socket_path, default_socket_path),
+                        o.dedup.dedup,
+                    )
+                    .map_err(run::Error::ImportJson),
                 },
-                SubCommand::Init => {
-                    run::init();
+                SubCommand::Init(o) => {
+                    run::init(o.shell, o.with_widgets);
                     Ok(())
                 }
-                SubCommand::Bench(s) => run::bench(s.socket_path),
+                SubCommand::Bench(s) => run::bench(
+                    &config,
+                    &clock,
+                    profile.socket_path(s.socket_path, default_socket_path),
+                ),
+                SubCommand::Delete(o) => {
+                    let filter = o.filter.into_filter(&config, &clock, 0)?;
+
+                    run::delete(
+                        &config,
+                        &clock,
+                        &filter,
+                        profile.data_dir(o.data_dir.data_dir, default_data_dir),
+                        &profile.socket_path(o.socket_path, default_socket_path),
+                        o.dry_run,
+                    )
+                }
+                SubCommand::Gc(o) => run::gc(
+                    &config,
+                    &clock,
+                    profile.data_dir(o.data_dir.data_dir, default_data_dir),
+                    &profile.socket_path(o.socket_path.socket_path, default_socket_path),
+                    o.older_than.into(),
+                ),
+                SubCommand::Compress(o) => run::compress(
+                    &config,
+                    &clock,
+                    profile.data_dir(o.data_dir.data_dir, default_data_dir),
+                    &profile.socket_path(o.socket_path.socket_path, default_socket_path),
+                    o.older_than.into(),
+                    o.commit,
+                ),
+                SubCommand::CacheGc(o) => run::cache_gc(
+                    &clock,
+                    profile.cache_path(o.cache_path, default_cache_path),
+                    profile.socket_path(o.socket_path.socket_path, default_socket_path),
+                    o.stale_after.map(Into::into),
+                ),
+                SubCommand::Usage(o) => run::usage(
+                    &config,
+                    profile.data_dir(o.data_dir.data_dir, default_data_dir),
+                    &profile.cache_path(o.cache_path, default_cache_path),
+                ),
+                SubCommand::Stats(o) => {
+                    let filter = o.filter.into_filter(&config, &clock, 0)?;
+
+                    run::stats(
+                        &config,
+                        &clock,
+                        &filter,
+                        profile.data_dir(o.data_dir.data_dir, default_data_dir),
+                        o.by,
+                        o.top,
+                        o.compare.map(Into::into),
+                    )
+                }
+                SubCommand::Session(o) => {
+                    let session_id = o.session_id.map_or_else(session_id_from_env, Ok)?;
+                    let filter = o
+                        .filter
+                        .into_filter(&config, &clock, 0)?
+                        .session_id(session_id);
+
+                    let display = TableDisplay {
+                        output: if o.json { OutputFormat::Json } else { o.output },
+                        color: color_display(false),
+                        columns: o.columns,
+
+                        timestamp_format: config.timestamp_format.clone(),
+                        timestamp_format_today: config.timestamp_format_today.clone(),
+
+                        command_format: config.format_command,
+
+                        pwd_abbreviations: config.pwd_abbreviations.clone(),
+
+                        ..TableDisplay::default()
+                    };
+
+                    run::default(
+                        &clock,
+                        &filter,
+                        &display,
+                        profile.data_dir(o.data_dir.data_dir, default_data_dir),
+                        config.backend,
+                        None,
+                        None,
+                    )
+                }
+                SubCommand::Last(o) => {
+                    let filter = o.filter.into_filter(&config, &clock, 1)?;
+
+                    let display = TableDisplay {
+                        output: if o.json { OutputFormat::Json } else { o.output },
+                        color: color_display(false),
+                        columns: o.columns,
+
+                        timestamp_format: config.timestamp_format.clone(),
+                        timestamp_format_today: config.timestamp_format_today.clone(),
+
+                        command_format: config.format_command,
+
+                        pwd_abbreviations: config.pwd_abbreviations.clone(),
+
+                        ..TableDisplay::default()
+                    };
+
+                    run::last(
+                        &clock,
+                        &filter,
+                        &display,
+                        profile.data_dir(o.data_dir.data_dir, default_data_dir),
+                        config.backend,
+                    )
+                }
+                SubCommand::Export(o) => {
+                    let filter = o.filter.into_filter(&config, &clock, 0)?;
+
+                    run::export(
+                        &filter,
+                        profile.data_dir(o.data_dir.data_dir, default_data_dir),
+                        config.backend,
+                        o.format,
+                        &o.output,
+                    )
+                }
+                SubCommand::Trim(o) => run::trim(
+                    &config,
+                    profile.data_dir(o.data_dir.data_dir, default_data_dir),
+                    &profile.socket_path(o.socket_path.socket_path, default_socket_path),
+                    o.max_entries,
+                    o.commit,
+                ),
+                SubCommand::Prune(o) => run::prune(
+                    &config,
+                    &clock,
+                    profile.data_dir(o.data_dir.data_dir, default_data_dir),
+                    &profile.socket_path(o.socket_path.socket_path, default_socket_path),
+                    o.older_than.map(Into::into),
+                    o.keep_last,
+                    o.dry_run,
+                ),
+                SubCommand::Check(o) => run::check(
+                    &config,
+                    profile.data_dir(o.data_dir.data_dir, default_data_dir),
+                    &profile.socket_path(o.socket_path.socket_path, default_socket_path),
+                    o.sessions,
+                    o.rows,
+                    o.repair,
+                ),
+                SubCommand::Scrub(o) => run::scrub(
+                    &config,
+                    profile.data_dir(o.data_dir.data_dir, default_data_dir),
+                    &profile.socket_path(o.socket_path.socket_path, default_socket_path),
+                    &o.pattern,
+                    o.commit,
+                    o.rewrite_history,
+                ),
+                SubCommand::VerifyGit(o) => run::verify_git(
+                    profile.data_dir(o.data_dir.data_dir, default_data_dir),
+                    o.commit,
+                ),
                 SubCommand::Completion(o) => {
                     let mut cmd = Opt::command();
                     let name = cmd.get_name().to_string();
@@ -423,3 +1563,49 @@ impl Opt {
         )
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::clock::FixedClock;
+    use chrono::{
+        Local,
+        TimeZone,
+        Utc,
+    };
+
+    #[test]
+    fn parse_time_at() {
+        let now = Local.ymd(2024, 3, 10).and_hms(15, 30, 0);
+        let local = |y, m, d, h, min| Local.ymd(y, m, d).and_hms(h, min, 0).with_timezone(&Utc);
+
+        let cases = vec![
+            ("2 days ago", local(2024, 3, 8, 15, 30)),
+            ("1h ago", local(2024, 3, 10, 14, 30)),
+            ("30min", local(2024, 3, 10, 15, 0)),
+            ("2024-01-01", local(2024, 1, 1, 0, 0)),
+            ("2024-01-01 08:15", local(2024, 1, 1, 8, 15)),
+            ("09:00", local(2024, 3, 10, 9, 0)),
+            ("2024-01-01T00:00:00Z", Utc.ymd(2024, 1, 1).and_hms(0, 0, 0)),
+        ];
+
+        for (value, expected) in cases {
+            let got = super::parse_time_at(now, value).expect("can parse time");
+            assert_eq!(got, expected, "{value}");
+        }
+
+        assert!(super::parse_time_at(now, "yesterday-ish").is_err());
+    }
+
+    #[test]
+    fn time_arg() {
+        let clock = FixedClock(Utc.ymd(2024, 3, 10).and_hms(15, 30, 0));
+
+        let time: super::TimeArg = "2 days ago".parse().expect("can parse time");
+        assert_eq!(
+            time.at(&clock).expect("can resolve time"),
+            Utc.ymd(2024, 3, 8).and_hms(15, 30, 0)
+        );
+
+        assert!("yesterday-ish".parse::<super::TimeArg>().is_err());
+    }
+}
@@ -1,5 +1,7 @@
 use crate::{
     client,
+    clock::Clock,
+    config::Config,
     message,
     server,
     store,
@@ -16,6 +18,10 @@ use rusqlite::params;
 #[cfg(feature = "histdb-import")]
 use std::convert::TryInto;
 use std::{
+    collections::{
+        HashMap,
+        HashSet,
+    },
     io::BufRead,
     path::{
         Path,
@@ -42,6 +48,12 @@ pub enum Error {
     #[error("can not get hostname: {0}")]
     GetHostname(std::io::Error),
 
+    #[error(
+        "server is running on {0:?}, stop it first so it does not write entries while the \
+         imported log files are sorted"
+    )]
+    ServerRunning(PathBuf),
+
     #[cfg(feature = "histdb-import")]
     #[error("can not open sqlite database: {0}")]
     OpenSqliteDatabase(rusqlite::Error),
@@ -65,6 +77,15 @@ pub enum Error {
     #[error("can not open histfile: {0}")]
     OpenHistfile(std::io::Error),
 
+    #[error("can not open json file: {0}")]
+    OpenJsonFile(std::io::Error),
+
+    #[error("can not parse json file, expected an array of entries: {0}")]
+    ParseJson(serde_json::Error),
+
+    #[error("can not parse entry {1} of json file: {0}")]
+    ParseJsonEntry(serde_json::Error, usize),
+
     #[error("accumulator fortime finished is none")]
     TimeFinishedAccumulatorNone,
 
@@ -91,10 +112,154 @@ pub enum Error {
 
     #[error("can not get current user: {0}")]
     GetUser(std::env::VarError),
+
+    #[cfg(feature = "histdb-import")]
+    #[error("can not get absolute path of import file: {0}")]
+    CanonicalizeImportFile(std::io::Error),
+
+    #[cfg(feature = "histdb-import")]
+    #[error("can not read import state file: {0}")]
+    ReadImportState(std::io::Error),
+
+    #[cfg(feature = "histdb-import")]
+    #[error("can not parse import state file: {0}")]
+    ParseImportState(toml::de::Error),
+
+    #[cfg(feature = "histdb-import")]
+    #[error("can not serialize import state: {0}")]
+    SerializeImportState(toml::ser::Error),
+
+    #[cfg(feature = "histdb-import")]
+    #[error("can not create import state folder: {0}")]
+    CreateImportStateFolder(std::io::Error),
+
+    #[cfg(feature = "histdb-import")]
+    #[error("can not write import state file: {0}")]
+    WriteImportState(std::io::Error),
 }
 
+/// Remembers up to which `start_time` each histdb file has been imported so
+/// incremental imports only have to look at newer rows.
 #[cfg(feature = "histdb-import")]
-pub fn histdb(import_file: impl AsRef<Path>, data_dir: PathBuf) -> Result<(), Error> {
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+struct ImportState {
+    last_start_time: std::collections::BTreeMap<String, i64>,
+}
+
+#[cfg(feature = "histdb-import")]
+impl ImportState {
+    fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+        match std::fs::read_to_string(path) {
+            Ok(content) => toml::from_str(&content).map_err(Error::ParseImportState),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(Error::ReadImportState(err)),
+        }
+    }
+
+    fn save(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let path = path.as_ref();
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(Error::CreateImportStateFolder)?;
+        }
+
+        let content = toml::to_string(self).map_err(Error::SerializeImportState)?;
+        std::fs::write(path, content).map_err(Error::WriteImportState)
+    }
+}
+
+/// Skips entries that are already in the store so running an import again does
+/// not add them a second time. The stored entries of a host are only read once
+/// when the first entry of that host is imported.
+#[derive(Debug, Default)]
+struct Dedup {
+    existing: HashMap<String, HashSet<(DateTime<Utc>, String, PathBuf)>>,
+    skipped: usize,
+}
+
+impl Dedup {
+    fn is_duplicate(
+        &mut self,
+        store: &dyn store::StoreBackend,
+        entry: &crate::entry::Entry,
+    ) -> Result<bool, Error> {
+        if !self.existing.contains_key(&entry.hostname) {
+            let filter = store::Filter::default()
+                .hostnames(vec![entry.hostname.clone()], false)
+                .map_err(store::Error::from)?
+                .include_deleted(true);
+
+            // Reading a host that has no log file yet fails, there is nothing
+            // stored for it that could be a duplicate.
+            let entries = match store.get_entries(&filter) {
+                Err(store::Error::OpenLogFile(_, err))
+                    if err.kind() == std::io::ErrorKind::NotFound =>
+                {
+                    Vec::new()
+                }
+                entries => entries?,
+            };
+
+            let existing = entries
+                .into_iter()
+                .map(|entry| (entry.time_finished, entry.command, entry.pwd))
+                .collect();
+
+            self.existing.insert(entry.hostname.clone(), existing);
+        }
+
+        let duplicate = self.existing[&entry.hostname].contains(&(
+            entry.time_finished,
+            entry.command.clone(),
+            entry.pwd.clone(),
+        ));
+
+        if duplicate {
+            self.skipped += 1;
+        }
+
+        Ok(duplicate)
+    }
+
+    fn report(&self) {
+        println!("skipped {} duplicate entries", self.skipped);
+    }
+}
+
+/// Opens the store the entries are imported into. The csv backend sorts the
+/// log files after the import by replacing them so entries the server appends
+/// in the meantime would be lost.
+fn open_store(
+    config: &Config,
+    data_dir: PathBuf,
+    socket_path: &Path,
+) -> Result<Box<dyn store::StoreBackend>, Error> {
+    if config.backend == store::Backend::Csv
+        && client::new(socket_path.to_path_buf()).server_reachable()
+    {
+        return Err(Error::ServerRunning(socket_path.to_path_buf()));
+    }
+
+    Ok(store::open_import(
+        data_dir,
+        config.backend,
+        config.storage_key,
+    )?)
+}
+
+#[cfg(feature = "histdb-import")]
+/// Imports all entries from the histdb sqlite file. With `state_path` the
+/// import is incremental: only rows that started at or after the newest row of
+/// the last import are read and rows already in the store are skipped.
+#[allow(clippy::too_many_lines)]
+pub fn histdb(
+    config: &Config,
+    import_file: impl AsRef<Path>,
+    data_dir: PathBuf,
+    socket_path: &Path,
+    state_path: Option<PathBuf>,
+    dedup: bool,
+) -> Result<(), Error> {
     #[derive(Debug, Ord, PartialOrd, Eq, PartialEq)]
     struct DBEntry {
         session: i64,
@@ -106,17 +271,31 @@ pub fn histdb(import_file: impl AsRef<Path>, data_dir: PathBuf) -> Result<(), Er
         command: String,
     }
 
+    let import_key = import_file
+        .as_ref()
+        .canonicalize()
+        .map_err(Error::CanonicalizeImportFile)?
+        .to_string_lossy()
+        .to_string();
+
+    let mut state = state_path.as_ref().map(ImportState::open).transpose()?;
+
+    let since = state
+        .as_ref()
+        .and_then(|state| state.last_start_time.get(&import_key).copied());
+
     let db = rusqlite::Connection::open(&import_file).map_err(Error::OpenSqliteDatabase)?;
 
     let mut stmt = db
         .prepare(
             "select * from history left join places on places.id=history.place_id
-    left join commands on history.command_id=commands.id",
+    left join commands on history.command_id=commands.id
+    where history.start_time >= ?1",
         )
         .map_err(Error::PrepareSqliteQuery)?;
 
     let entries = stmt
-        .query_map(params![], |row| {
+        .query_map(params![since.unwrap_or(i64::MIN)], |row| {
             Ok(DBEntry {
                 session: row.get(1)?,
                 exit_status: row.get(4)?,
@@ -135,7 +314,23 @@ pub fn histdb(import_file: impl AsRef<Path>, data_dir: PathBuf) -> Result<(), Er
 
     let mut session_ids = std::collections::HashMap::new();
 
-    let store = crate::store::new(data_dir);
+    let store = open_store(config, data_dir, socket_path)?;
+
+    // Rows that started in the same second as the newest row of the last
+    // import are read again, so skip the ones that are already stored.
+    let existing: std::collections::HashSet<_> = match since {
+        Some(since) => store
+            .get_entries(&crate::store::Filter::default().include_deleted(true))?
+            .into_iter()
+            .filter(|entry| entry.time_start.timestamp() >= since)
+            .map(|entry| (entry.hostname, entry.time_start, entry.command))
+            .collect(),
+        None => std::collections::HashSet::new(),
+    };
+
+    let last_start_time = entries.iter().map(|entry| entry.start_time).max();
+
+    let mut dedup = dedup.then(Dedup::default);
 
     for entry in entries {
         if entry.duration.is_none()
@@ -187,26 +382,368 @@ pub fn histdb(import_file: impl AsRef<Path>, data_dir: PathBuf) -> Result<(), Er
             session_id: *session_id,
             user,
             command,
+            hist_event: None,
+            deleted: false,
+            duration_ms: None,
+            tag: None,
+            seq: 0,
+            time_deleted: None,
         };
 
-        store.add_entry(&entry)?;
+        if existing.contains(&(
+            entry.hostname.clone(),
+            entry.time_start,
+            entry.command.clone(),
+        )) {
+            continue;
+        }
+
+        if let Some(dedup) = &mut dedup {
+            if dedup.is_duplicate(store.as_ref(), &entry)? {
+                continue;
+            }
+        }
+
+        store.add(&entry)?;
+    }
+
+    store.finish_import()?;
+
+    if let Some(dedup) = &dedup {
+        dedup.report();
+    }
+
+    if let (Some(state), Some(state_path)) = (state.as_mut(), state_path) {
+        if let Some(last_start_time) = last_start_time {
+            state.last_start_time.insert(import_key, last_start_time);
+        }
+
+        state.save(state_path)?;
     }
 
     Ok(())
 }
 
-#[allow(clippy::too_many_lines)]
-pub fn histfile(import_file: impl AsRef<Path>, data_dir: PathBuf) -> Result<(), Error> {
-    #[derive(Debug)]
-    struct HistfileEntry {
-        time_finished: DateTime<Utc>,
-        result: u16,
-        command: String,
-    }
+#[derive(Debug)]
+struct HistfileEntry {
+    time_finished: DateTime<Utc>,
+    result: u16,
+    command: String,
+}
 
+pub fn histfile(
+    config: &Config,
+    import_file: impl AsRef<Path>,
+    data_dir: PathBuf,
+    socket_path: &Path,
+    normalize_newlines: bool,
+    dedup: bool,
+) -> Result<(), Error> {
     let histfile = std::fs::File::open(import_file).map_err(Error::OpenHistfile)?;
     let reader = std::io::BufReader::new(histfile);
 
+    let entries = parse_histfile(reader, normalize_newlines)?;
+
+    add_histfile_entries(config, entries, data_dir, socket_path, dedup)
+}
+
+/// Imports a bash history file written with `HISTTIMEFORMAT` set. Bash does
+/// not record the exit status so every entry is imported as successful.
+pub fn bash(
+    config: &Config,
+    import_file: impl AsRef<Path>,
+    data_dir: PathBuf,
+    socket_path: &Path,
+    dedup: bool,
+) -> Result<(), Error> {
+    let histfile = std::fs::File::open(import_file).map_err(Error::OpenHistfile)?;
+    let reader = std::io::BufReader::new(histfile);
+
+    let entries = parse_bash_history(reader);
+
+    add_histfile_entries(config, entries, data_dir, socket_path, dedup)
+}
+
+/// Imports a bash history file without timestamps. As there is no way to tell
+/// when the commands ran they get one second apart from each other starting at
+/// `base_time` so they keep their order. Without `base_time` the last command
+/// is imported as having finished now.
+pub fn bash_plain(
+    config: &Config,
+    clock: &impl Clock,
+    import_file: impl AsRef<Path>,
+    data_dir: PathBuf,
+    socket_path: &Path,
+    base_time: Option<DateTime<Utc>>,
+    dedup: bool,
+) -> Result<(), Error> {
+    let histfile = std::fs::File::open(import_file).map_err(Error::OpenHistfile)?;
+    let reader = std::io::BufReader::new(histfile);
+
+    let entries = parse_plain_bash_history(clock, reader, base_time);
+
+    add_histfile_entries(config, entries, data_dir, socket_path, dedup)
+}
+
+/// Host, user and directory the entries of a histfile are recorded with as the
+/// histfile does not record any of them.
+struct HistfileOrigin {
+    hostname: String,
+    user: String,
+    pwd: PathBuf,
+}
+
+/// Returns the host imported entries are recorded for. That is the configured
+/// hostname override if there is one so the entries show up in the default
+/// listing.
+fn import_hostname(config: &Config) -> Result<String, Error> {
+    match config.hostname_override() {
+        Some(hostname) => Ok(hostname),
+        None => Ok(hostname::get()
+            .map_err(Error::GetHostname)?
+            .to_string_lossy()
+            .to_string()),
+    }
+}
+
+/// Adds the entries of a histfile to the store as one session of the current
+/// user and host that ran in the home directory.
+fn add_histfile_entries(
+    config: &Config,
+    entries: Vec<HistfileEntry>,
+    data_dir: PathBuf,
+    socket_path: &Path,
+    dedup: bool,
+) -> Result<(), Error> {
+    let base_dirs = directories::BaseDirs::new().ok_or(Error::BaseDirectory)?;
+    let origin = HistfileOrigin {
+        hostname: import_hostname(config)?,
+        user: std::env::var("USER").map_err(Error::GetUser)?,
+        pwd: base_dirs.home_dir().to_path_buf(),
+    };
+
+    let store = open_store(config, data_dir, socket_path)?;
+
+    store_histfile_entries(store.as_ref(), &origin, entries, dedup)
+}
+
+fn store_histfile_entries(
+    store: &dyn store::StoreBackend,
+    origin: &HistfileOrigin,
+    entries: Vec<HistfileEntry>,
+    dedup: bool,
+) -> Result<(), Error> {
+    let mut dedup = dedup.then(Dedup::default);
+    let session_id = Uuid::new_v4();
+
+    for histfile_entry in entries {
+        let time_finished = histfile_entry.time_finished;
+        let time_start = histfile_entry.time_finished;
+        let result = histfile_entry.result;
+        let command = histfile_entry.command;
+        let hostname = origin.hostname.clone();
+        let pwd = origin.pwd.clone();
+        let user = origin.user.clone();
+
+        let entry = crate::entry::Entry {
+            time_finished,
+            time_start,
+            hostname,
+            command,
+            pwd,
+            result,
+            session_id,
+            user,
+            hist_event: None,
+            deleted: false,
+            duration_ms: None,
+            tag: None,
+            seq: 0,
+            time_deleted: None,
+        };
+
+        if let Some(dedup) = &mut dedup {
+            if dedup.is_duplicate(store, &entry)? {
+                continue;
+            }
+        }
+
+        store.add(&entry)?;
+    }
+
+    store.finish_import()?;
+
+    if let Some(dedup) = &dedup {
+        dedup.report();
+    }
+
+    Ok(())
+}
+
+/// Imports the entries of a JSON array like the one written by `export --format
+/// json`. Entries with an empty command are skipped.
+pub fn json(
+    config: &Config,
+    import_file: impl AsRef<Path>,
+    data_dir: PathBuf,
+    socket_path: &Path,
+    dedup: bool,
+) -> Result<(), Error> {
+    let file = std::fs::File::open(import_file).map_err(Error::OpenJsonFile)?;
+    let reader = std::io::BufReader::new(file);
+
+    let values: Vec<serde_json::Value> =
+        serde_json::from_reader(reader).map_err(Error::ParseJson)?;
+
+    let store = open_store(config, data_dir, socket_path)?;
+    let mut dedup = dedup.then(Dedup::default);
+
+    for (index, value) in values.into_iter().enumerate() {
+        let entry: crate::entry::Entry =
+            serde_json::from_value(value).map_err(|err| Error::ParseJsonEntry(err, index))?;
+
+        if entry.command.trim().is_empty() {
+            continue;
+        }
+
+        if let Some(dedup) = &mut dedup {
+            if dedup.is_duplicate(store.as_ref(), &entry)? {
+                continue;
+            }
+        }
+
+        store.add(&entry)?;
+    }
+
+    store.finish_import()?;
+
+    if let Some(dedup) = &dedup {
+        dedup.report();
+    }
+
+    Ok(())
+}
+
+/// Parses the lines of a bash history file. With `HISTTIMEFORMAT` bash writes
+/// a `#<timestamp>` line before every command, commands spanning multiple
+/// lines continue until the next timestamp. Commands without a timestamp are
+/// skipped as there is no way to tell when they ran.
+fn parse_bash_history(reader: impl BufRead) -> Vec<HistfileEntry> {
+    let mut entries = Vec::new();
+    let mut time_finished: Option<DateTime<Utc>> = None;
+    let mut command: Option<String> = None;
+
+    let mut push = |time_finished: Option<DateTime<Utc>>, command: Option<String>| {
+        if let (Some(time_finished), Some(command)) = (time_finished, command) {
+            entries.push(HistfileEntry {
+                time_finished,
+                result: 0,
+                command,
+            });
+        }
+    };
+
+    for (index, line) in reader.lines().enumerate() {
+        let line_number = index + 1;
+
+        let line = match line {
+            Err(err) => {
+                warn!("can not read line {}: {}", line_number, err);
+
+                continue;
+            }
+            Ok(line) => line,
+        };
+
+        let timestamp = line
+            .strip_prefix('#')
+            .filter(|timestamp| {
+                !timestamp.is_empty() && timestamp.chars().all(|c| c.is_ascii_digit())
+            })
+            .and_then(|timestamp| timestamp.parse().ok());
+
+        if let Some(timestamp) = timestamp {
+            push(time_finished, command.take());
+
+            time_finished = Some(chrono::DateTime::<Utc>::from_utc(
+                chrono::NaiveDateTime::from_timestamp(timestamp, 0),
+                Utc,
+            ));
+
+            continue;
+        }
+
+        if time_finished.is_none() {
+            warn!(
+                "skipping line {} without a timestamp, is HISTTIMEFORMAT set?",
+                line_number
+            );
+
+            continue;
+        }
+
+        match &mut command {
+            Some(command) => {
+                command.push('\n');
+                command.push_str(&line);
+            }
+            None => command = Some(line),
+        }
+    }
+
+    push(time_finished, command);
+
+    entries
+}
+
+/// Parses the lines of a bash history file written without `HISTTIMEFORMAT`
+/// where every non blank line is one command.
+fn parse_plain_bash_history(
+    clock: &impl Clock,
+    reader: impl BufRead,
+    base_time: Option<DateTime<Utc>>,
+) -> Vec<HistfileEntry> {
+    let mut commands = Vec::new();
+
+    for (index, line) in reader.lines().enumerate() {
+        let line = match line {
+            Err(err) => {
+                warn!("can not read line {}: {}", index + 1, err);
+
+                continue;
+            }
+            Ok(line) => line,
+        };
+
+        let command = line.trim_end();
+
+        if command.is_empty() {
+            continue;
+        }
+
+        commands.push(command.to_string());
+    }
+
+    let base_time = base_time.unwrap_or_else(|| {
+        let last_offset = i64::try_from(commands.len()).unwrap_or(i64::MAX) - 1;
+
+        clock.now() - chrono::Duration::seconds(last_offset.max(0))
+    });
+
+    (0_i64..)
+        .zip(commands)
+        .map(|(offset, command)| HistfileEntry {
+            time_finished: base_time + chrono::Duration::seconds(offset),
+            result: 0,
+            command,
+        })
+        .collect()
+}
+
+fn parse_histfile(
+    reader: impl BufRead,
+    normalize_newlines: bool,
+) -> Result<Vec<HistfileEntry>, Error> {
     let mut acc_time_finished: Option<DateTime<Utc>> = None;
     let mut acc_result: Option<u16> = None;
     let mut acc_command: Option<String> = None;
@@ -226,6 +763,15 @@ pub fn histfile(import_file: impl AsRef<Path>, data_dir: PathBuf) -> Result<(),
             Ok(line) => line,
         };
 
+        // BufRead::lines only removes a single \r before the \n. Histfiles
+        // that were converted to CRLF more than once still end with a \r
+        // which would end up in the stored command.
+        let line = if normalize_newlines {
+            line.trim_end_matches('\r').to_string()
+        } else {
+            line
+        };
+
         // End of multiline command
         if line.starts_with(':') && multiline_command {
             let time_finished = acc_time_finished.ok_or(Error::TimeFinishedAccumulatorNone)?;
@@ -301,40 +847,380 @@ pub fn histfile(import_file: impl AsRef<Path>, data_dir: PathBuf) -> Result<(),
         });
     }
 
-    let store = crate::store::new(data_dir);
+    Ok(entries)
+}
 
-    let hostname = hostname::get()
-        .map_err(Error::GetHostname)?
-        .to_string_lossy()
-        .to_string();
+#[cfg(test)]
+mod test {
 
-    let base_dirs = directories::BaseDirs::new().ok_or(Error::BaseDirectory)?;
-    let pwd = base_dirs.home_dir().to_path_buf();
-    let user = std::env::var("USER").map_err(Error::GetUser)?;
-    let session_id = Uuid::new_v4();
+    #[cfg(feature = "histdb-import")]
+    #[test]
+    fn histdb_incremental() {
+        let source_dir = tempfile::tempdir().expect("can create source dir");
+        let data_dir = tempfile::tempdir().expect("can create data dir");
+        let state_dir = tempfile::tempdir().expect("can create state dir");
+
+        let import_file = source_dir.path().join("zsh-history.db");
+        let state_path = state_dir.path().join("histdb.toml");
+
+        let db = rusqlite::Connection::open(&import_file).expect("can open histdb");
+        db.execute_batch(
+            "create table commands (id integer primary key autoincrement, argv text, unique(argv) \
+             on conflict ignore);
+            create table places (id integer primary key autoincrement, host text, dir text, \
+             unique(host, dir) on conflict ignore);
+            create table history (id integer primary key autoincrement, session int, command_id \
+             int references commands (id), place_id int references places (id), exit_status int, \
+             start_time int, duration int);
+            insert into places (host, dir) values ('testhostname', '/tmp');
+            insert into commands (argv) values ('ls'), ('pwd');
+            insert into history (session, command_id, place_id, exit_status, start_time, duration) \
+             values (1, 1, 1, 0, 1000, 1);",
+        )
+        .expect("can create histdb");
+
+        let import = || {
+            super::histdb(
+                &crate::config::Config::default(),
+                &import_file,
+                data_dir.path().to_path_buf(),
+                &data_dir.path().join("server_socket"),
+                Some(state_path.clone()),
+                false,
+            )
+            .expect("can import histdb");
+        };
 
-    for histfile_entry in entries {
-        let time_finished = histfile_entry.time_finished;
-        let time_start = histfile_entry.time_finished;
-        let result = histfile_entry.result;
-        let command = histfile_entry.command;
-        let hostname = hostname.clone();
-        let pwd = pwd.clone();
-        let user = user.clone();
+        let commands = || {
+            crate::store::new(data_dir.path().to_path_buf())
+                .get_entries(&crate::store::Filter::default())
+                .expect("can get entries")
+                .into_iter()
+                .map(|entry| entry.command)
+                .collect::<Vec<_>>()
+        };
 
-        let entry = crate::entry::Entry {
-            time_finished,
-            time_start,
-            hostname,
-            command,
-            pwd,
-            result,
-            session_id,
-            user,
+        import();
+        assert_eq!(commands(), vec!["ls"]);
+
+        db.execute(
+            "insert into history (session, command_id, place_id, exit_status, start_time, \
+             duration) values (1, 2, 1, 0, 2000, 1)",
+            [],
+        )
+        .expect("can add row to histdb");
+
+        import();
+        assert_eq!(commands(), vec!["ls", "pwd"]);
+
+        let state = std::fs::read_to_string(&state_path).expect("can read state file");
+        assert!(state.contains("2000"), "{}", state);
+
+        import();
+        assert_eq!(commands(), vec!["ls", "pwd"]);
+    }
+
+    #[test]
+    fn json() {
+        let source_dir = tempfile::tempdir().expect("can create source dir");
+        let data_dir = tempfile::tempdir().expect("can create data dir");
+        let export_file = source_dir.path().join("export.json");
+
+        let entry = |hostname: &str, command: &str| crate::entry::Entry {
+            hist_event: Some(1),
+            duration_ms: Some(0),
+            ..crate::entry::Entry::for_test(hostname, command)
         };
 
-        store.add_entry(&entry)?;
+        let source = crate::store::new(source_dir.path().to_path_buf());
+        for entry in &[
+            entry("laptop", "ls"),
+            entry("server", "pwd"),
+            entry("laptop", " "),
+            entry("laptop", "cargo build"),
+        ] {
+            source.add_entry(entry).expect("can add entry");
+        }
+
+        let filter = crate::store::Filter::default()
+            .hostnames(Vec::new(), true)
+            .expect("can set hostnames");
+
+        crate::run::export(
+            &filter,
+            source_dir.path().to_path_buf(),
+            crate::store::Backend::Csv,
+            crate::run::ExportFormat::Json,
+            &export_file,
+        )
+        .expect("can export entries");
+
+        super::json(
+            &crate::config::Config::default(),
+            &export_file,
+            data_dir.path().to_path_buf(),
+            &data_dir.path().join("server_socket"),
+            false,
+        )
+        .expect("can import json");
+
+        let expected: Vec<_> = source
+            .get_entries(&filter)
+            .expect("can get entries")
+            .into_iter()
+            .filter(|entry| !entry.command.trim().is_empty())
+            .collect();
+
+        let imported = crate::store::new(data_dir.path().to_path_buf())
+            .get_entries(&filter)
+            .expect("can get entries");
+
+        assert_eq!(imported.len(), 3);
+        assert_eq!(imported, expected);
     }
 
-    Ok(())
+    #[test]
+    fn json_dedup() {
+        let source_dir = tempfile::tempdir().expect("can create source dir");
+        let data_dir = tempfile::tempdir().expect("can create data dir");
+        let export_file = source_dir.path().join("export.json");
+
+        let entry = |hostname: &str, command: &str| crate::entry::Entry {
+            hist_event: Some(1),
+            duration_ms: Some(0),
+            ..crate::entry::Entry::for_test(hostname, command)
+        };
+
+        let entries = vec![entry("laptop", "ls"), entry("server", "pwd")];
+        std::fs::write(
+            &export_file,
+            serde_json::to_string(&entries).expect("can serialize entries"),
+        )
+        .expect("can write export file");
+
+        let import = |dedup| {
+            super::json(
+                &crate::config::Config::default(),
+                &export_file,
+                data_dir.path().to_path_buf(),
+                &data_dir.path().join("server_socket"),
+                dedup,
+            )
+            .expect("can import json");
+
+            crate::store::new(data_dir.path().to_path_buf())
+                .get_entries(
+                    &crate::store::Filter::default()
+                        .hostnames(Vec::new(), true)
+                        .expect("can set hostnames"),
+                )
+                .expect("can get entries")
+                .len()
+        };
+
+        assert_eq!(import(true), 2);
+        assert_eq!(import(true), 2);
+        assert_eq!(import(false), 4);
+    }
+
+    #[cfg(feature = "sqlite-backend")]
+    #[test]
+    fn json_sqlite() {
+        let source_dir = tempfile::tempdir().expect("can create source dir");
+        let data_dir = tempfile::tempdir().expect("can create data dir");
+        let export_file = source_dir.path().join("export.json");
+
+        let mut config = crate::config::Config::default();
+        config.backend = crate::store::Backend::Sqlite;
+
+        let entries = vec![
+            crate::entry::Entry::for_test("laptop", "ls"),
+            crate::entry::Entry::for_test("server", "pwd"),
+        ];
+        std::fs::write(
+            &export_file,
+            serde_json::to_string(&entries).expect("can serialize entries"),
+        )
+        .expect("can write export file");
+
+        super::json(
+            &config,
+            &export_file,
+            data_dir.path().to_path_buf(),
+            &data_dir.path().join("server_socket"),
+            true,
+        )
+        .expect("can import json");
+        super::json(
+            &config,
+            &export_file,
+            data_dir.path().to_path_buf(),
+            &data_dir.path().join("server_socket"),
+            true,
+        )
+        .expect("can import json");
+
+        let imported = crate::store::open(
+            data_dir.path().to_path_buf(),
+            config.backend,
+            config.storage_key,
+        )
+        .expect("can open store")
+        .get_entries(
+            &crate::store::Filter::default()
+                .hostnames(Vec::new(), true)
+                .expect("can set hostnames"),
+        )
+        .expect("can get entries");
+
+        assert_eq!(imported.len(), 2);
+        assert!(!data_dir.path().join("laptop.csv").exists());
+    }
+
+    #[test]
+    fn histfile_hostname_override() {
+        let source_dir = tempfile::tempdir().expect("can create source dir");
+        let data_dir = tempfile::tempdir().expect("can create data dir");
+        let mut config = crate::config::Config::default();
+        config.hostname = Some("imported-host".to_string());
+
+        let origin = super::HistfileOrigin {
+            hostname: super::import_hostname(&config).expect("can get hostname"),
+            user: "testuser".to_string(),
+            pwd: source_dir.path().to_path_buf(),
+        };
+        let clock = crate::clock::FixedClock(chrono::Utc::now());
+        let store = crate::store::open_import(
+            data_dir.path().to_path_buf(),
+            config.backend,
+            config.storage_key,
+        )
+        .expect("can open store");
+
+        let import = || {
+            super::store_histfile_entries(
+                store.as_ref(),
+                &origin,
+                super::parse_plain_bash_history(&clock, "ls\npwd\n".as_bytes(), None),
+                true,
+            )
+            .expect("can import bash history");
+        };
+        import();
+        // The dedup lookup has to check the same host or everything would be
+        // imported again.
+        import();
+
+        let imported = crate::store::new(data_dir.path().to_path_buf())
+            .get_entries(
+                &crate::store::Filter::default()
+                    .hostnames(vec!["imported-host".to_string()], false)
+                    .expect("can set hostnames"),
+            )
+            .expect("can get entries");
+
+        let commands: Vec<_> = imported
+            .iter()
+            .map(|entry| (entry.hostname.as_str(), entry.command.as_str()))
+            .collect();
+        assert_eq!(
+            commands,
+            vec![("imported-host", "ls"), ("imported-host", "pwd")]
+        );
+    }
+
+    #[test]
+    fn parse_histfile_crlf() {
+        let commands = |histfile: &str, normalize_newlines| {
+            super::parse_histfile(histfile.as_bytes(), normalize_newlines)
+                .expect("can parse histfile")
+                .into_iter()
+                .map(|entry| entry.command)
+                .collect::<Vec<_>>()
+        };
+
+        let crlf =
+            ": 1600000000:0;ls -la\r\n: 1600000001:0;echo a \\\r\nb\r\n: 1600000002:1;false\r\n";
+        assert_eq!(
+            commands(crlf, true),
+            vec!["ls -la", "echo a \nb\n", "false"]
+        );
+
+        let double_crlf = ": 1600000000:0;ls -la\r\r\n: 1600000001:0;pwd\r\r\n";
+        assert_eq!(commands(double_crlf, true), vec!["ls -la", "pwd"]);
+        assert_eq!(commands(double_crlf, false), vec!["ls -la\r", "pwd\r"]);
+    }
+
+    #[test]
+    fn parse_bash_history() {
+        let history = "ls -la
+#1622541600
+cd /tmp
+#1622541660
+for file in *; do
+    echo $file
+done
+#not a timestamp
+#1622541720
+#1622541780
+git status
+";
+
+        let entries = super::parse_bash_history(history.as_bytes());
+        let commands: Vec<_> = entries
+            .iter()
+            .map(|entry| (entry.time_finished.timestamp(), entry.command.as_str()))
+            .collect();
+
+        assert_eq!(
+            commands,
+            vec![
+                (1_622_541_600, "cd /tmp"),
+                (
+                    1_622_541_660,
+                    "for file in *; do\n    echo $file\ndone\n#not a timestamp"
+                ),
+                (1_622_541_780, "git status"),
+            ]
+        );
+        assert!(entries.iter().all(|entry| entry.result == 0));
+    }
+
+    #[test]
+    fn parse_plain_bash_history() {
+        let base_time = chrono::DateTime::<chrono::Utc>::from_utc(
+            chrono::NaiveDateTime::from_timestamp(1_622_541_600, 0),
+            chrono::Utc,
+        );
+
+        let history = "ls -la  \n\ncd /tmp\n   \ngit status\t\n";
+
+        let clock = crate::clock::FixedClock(base_time + chrono::Duration::hours(1));
+
+        let entries = super::parse_plain_bash_history(&clock, history.as_bytes(), Some(base_time));
+        let commands: Vec<_> = entries
+            .iter()
+            .map(|entry| (entry.time_finished.timestamp(), entry.command.as_str()))
+            .collect();
+
+        assert_eq!(
+            commands,
+            vec![
+                (1_622_541_600, "ls -la"),
+                (1_622_541_601, "cd /tmp"),
+                (1_622_541_602, "git status"),
+            ]
+        );
+        assert!(entries.iter().all(|entry| entry.result == 0));
+
+        // Without a base time the last command finished at the time of the
+        // clock.
+        let entries = super::parse_plain_bash_history(&clock, history.as_bytes(), None);
+        let times: Vec<_> = entries
+            .iter()
+            .map(|entry| entry.time_finished.timestamp())
+            .collect();
+
+        assert_eq!(times, vec![1_622_545_198, 1_622_545_199, 1_622_545_200]);
+    }
 }
@@ -2,39 +2,52 @@ pub mod import;
 
 use crate::{
     client,
+    clock::Clock,
     config,
     entry::Entry,
     message,
     message::{
+        recording_disabled,
         session_id_from_env,
         CommandFinished,
         CommandStart,
+        CommandStarted,
         Message,
     },
     server,
     store,
     store::{
         filter,
+        Backend,
         Filter,
+        StorageKey,
     },
 };
 use chrono::{
     DateTime,
     Local,
+    TimeZone,
     Utc,
 };
 use comfy_table::{
     Attribute,
     Cell,
+    Color,
     Table,
 };
 use log::{
     debug,
     warn,
 };
+use regex::Regex;
 use std::{
+    collections::HashMap,
     convert::TryInto,
-    io::Write,
+    io::{
+        BufRead,
+        IsTerminal,
+        Write,
+    },
     path::{
         Path,
         PathBuf,
@@ -63,6 +76,9 @@ pub enum Error {
     #[error("{0}")]
     Filter(#[from] filter::Error),
 
+    #[error("{0}")]
+    Time(#[from] crate::opt::Error),
+
     #[error("can not get base directories")]
     GetBaseDirectories,
 
@@ -72,9 +88,24 @@ pub enum Error {
     #[error("can not write to stdout: {0}")]
     WriteStdout(std::io::Error),
 
+    #[error("can not serialize entry to json: {0}")]
+    SerializeJson(serde_json::Error),
+
+    #[error("can not ask for confirmation: {0}")]
+    Confirm(std::io::Error),
+
     #[error("can not read configuration file: {0}")]
     ReadConfig(config::Error),
 
+    #[error("can not select profile: {0}")]
+    SelectProfile(config::Error),
+
+    #[error("no maximum number of entries given, set max_entries_per_host or pass --max-entries")]
+    MissingMaxEntries,
+
+    #[error("duration is too large")]
+    DurationOutOfRange,
+
     #[error("encountered negative duration when trying to format duration")]
     NegativeDuration,
 
@@ -85,49 +116,282 @@ pub enum Error {
     #[error("can not import from histfile: {0}")]
     ImportHistfile(import::Error),
 
+    #[error("can not import from bash history: {0}")]
+    ImportBash(import::Error),
+
+    #[error("can not import from json: {0}")]
+    ImportJson(import::Error),
+
+    #[error("can not create export file {0}: {1}")]
+    CreateExportFile(PathBuf, std::io::Error),
+
+    #[error("can not write export file {0}: {1}")]
+    WriteExportFile(PathBuf, std::io::Error),
+
+    #[error("can not read command from stdin: {0}")]
+    ReadCommand(std::io::Error),
+
+    #[error("can not create output file {0}: {1}")]
+    CreateOutputFile(PathBuf, std::io::Error),
+
+    #[error("can not write output file {0}: {1}")]
+    WriteOutputFile(PathBuf, std::io::Error),
+
+    #[error("can not write entry to csv export: {0}")]
+    SerializeCsv(csv::Error),
+
+    #[error("server is not running")]
+    ServerNotRunning,
+
+    #[error("no entry matches the filter")]
+    NoEntries,
+
+    #[error("can not get size of {0}: {1}")]
+    ReadSize(PathBuf, std::io::Error),
+
+    #[error("invalid cursor {0:?}, expected a cursor printed as next_cursor")]
+    InvalidCursor(String),
+
+    #[error("{0} only works on csv files and is not supported for the sqlite backend")]
+    NotSupportedForSqlite(&'static str),
+
+    #[error(
+        "server is running on {0:?}, stop it first so it does not write entries while the log \
+         files are rewritten"
+    )]
+    ServerRunning(PathBuf),
+
     #[error("can not format entry: {0}\nentry: {1:?}")]
-    FormatEntry(Box<Error>, Entry),
+    FormatEntry(Box<Error>, Box<Entry>),
+}
+
+/// What the `stats` subcommand counts entries by.
+#[derive(Debug, Clone, Copy, clap::ArgEnum)]
+pub enum StatsGroup {
+    /// First word of the command
+    Command,
+
+    /// Directory the command was run in
+    Pwd,
+
+    /// Host the command was run on
+    Hostname,
+
+    /// Local hour the command finished in
+    Hour,
+}
+
+impl StatsGroup {
+    const fn header(self) -> &'static str {
+        match self {
+            Self::Command => "cmd",
+            Self::Pwd => "pwd",
+            Self::Hostname => "host",
+            Self::Hour => "hour",
+        }
+    }
+}
+
+/// How the entries are printed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ArgEnum)]
+pub enum OutputFormat {
+    /// Aligned table with fancy formatting
+    Table,
+
+    /// Tab separated values
+    Plain,
+
+    /// JSON array of all entries
+    Json,
+
+    /// One JSON object per entry and line
+    Ndjson,
+
+    /// Like ndjson but every line is flushed as soon as it is written
+    NdjsonStream,
+}
+
+/// Format of the file written by `export`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ArgEnum)]
+pub enum ExportFormat {
+    /// Same columns as the history files with one header for all entries
+    Csv,
+
+    /// JSON array of all entries
+    Json,
+
+    /// One JSON object per entry and line
+    Ndjson,
+}
+
+/// Shell for which `init` prints the hooks.
+#[derive(Debug, Clone, Copy, clap::ArgEnum)]
+pub enum InitShell {
+    Zsh,
+    Bash,
+    Fish,
 }
 
 #[derive(Debug)]
 pub struct TableDisplay {
-    pub format: bool,
+    pub output: OutputFormat,
+
+    /// Print the newest entry first.
+    pub reverse: bool,
 
-    pub duration: Display,
     pub header: Display,
-    pub host: Display,
-    pub pwd: Display,
-    pub session: Display,
-    pub status: Display,
+
+    /// Print the header in bold.
+    pub color: Display,
+
+    /// Columns to print in the given order.
+    pub columns: Vec<Column>,
+
+    /// Separate the entries of consecutive sessions with an empty row.
+    pub group_sessions: bool,
+
+    /// Printed between the columns of the plain output.
+    pub field_separator: String,
+
+    /// Printed after every row of the plain output.
+    pub record_separator: String,
+
+    pub timestamp_format: Option<String>,
+    pub timestamp_format_today: Option<String>,
+
+    /// How commands that span multiple lines are printed.
+    pub command_format: config::CommandFormat,
+
+    /// Roots of the pwd column that are printed as the given alias.
+    pub pwd_abbreviations: Vec<(PathBuf, String)>,
 }
 
 impl Default for TableDisplay {
     fn default() -> Self {
         Self {
-            format: true,
+            output: OutputFormat::Table,
+
+            reverse: false,
 
-            duration: Display::Hide,
             header: Display::Show,
-            host: Display::Hide,
-            pwd: Display::Hide,
-            session: Display::Hide,
-            status: Display::Hide,
+
+            color: Display::Show,
+
+            columns: vec![Column::Time, Column::Command],
+
+            group_sessions: false,
+
+            field_separator: "\t".to_string(),
+            record_separator: "\n".to_string(),
+
+            timestamp_format: None,
+            timestamp_format_today: None,
+
+            command_format: config::CommandFormat::default(),
+
+            pwd_abbreviations: Vec::new(),
         }
     }
 }
 
-#[derive(Debug)]
-pub enum Display {
-    Hide,
-    Show,
+/// A column of the table and plain output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Column {
+    Time,
+    Relative,
+    Host,
+    User,
+    Duration,
+    Status,
+    Session,
+    HistEvent,
+    Pipes,
+    Tag,
+    Pwd,
+    Command,
 }
 
-impl Default for Display {
-    fn default() -> Self {
-        Self::Hide
+impl Column {
+    /// Every column in the order they are printed with the `--show-*` flags.
+    pub const ALL: [Self; 12] = [
+        Self::Time,
+        Self::Relative,
+        Self::Host,
+        Self::User,
+        Self::Duration,
+        Self::Status,
+        Self::Session,
+        Self::HistEvent,
+        Self::Pipes,
+        Self::Tag,
+        Self::Pwd,
+        Self::Command,
+    ];
+
+    /// Name of the column for `--columns`.
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Time => "time",
+            Self::Relative => "relative",
+            Self::Host => "host",
+            Self::User => "user",
+            Self::Duration => "duration",
+            Self::Status => "status",
+            Self::Session => "session",
+            Self::HistEvent => "hist_event",
+            Self::Pipes => "pipes",
+            Self::Tag => "tag",
+            Self::Pwd => "pwd",
+            Self::Command => "cmd",
+        }
+    }
+
+    const fn header(self) -> &'static str {
+        match self {
+            Self::Time => "tmn",
+            Self::Relative => "rel",
+            Self::Host => "host",
+            Self::User => "user",
+            Self::Duration => "duration",
+            Self::Status => "res",
+            Self::Session => "ses",
+            Self::HistEvent => "evt",
+            Self::Pipes => "pip",
+            Self::Tag => "tag",
+            Self::Pwd => "pwd",
+            Self::Command => "cmd",
+        }
+    }
+}
+
+impl std::str::FromStr for Column {
+    type Err = String;
+
+    /// Accepts the name of a column as well as its header.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|column| column.name() == s || column.header() == s)
+            .ok_or_else(|| {
+                let names: Vec<_> = Self::ALL.iter().map(|column| column.name()).collect();
+
+                format!(
+                    "unknown column {:?}, valid columns are: {}",
+                    s,
+                    names.join(", ")
+                )
+            })
     }
 }
 
+#[derive(Debug, Default)]
+pub enum Display {
+    #[default]
+    Hide,
+    Show,
+}
+
 impl Display {
     const fn is_show(&self) -> bool {
         match self {
@@ -153,278 +417,1395 @@ impl Display {
     }
 }
 
-pub fn default(filter: &Filter, display: &TableDisplay, data_dir: PathBuf) -> Result<(), Error> {
-    let entries = store::new(data_dir).get_entries(filter)?;
-
-    if display.format {
-        default_format(display, entries);
+pub fn default(
+    clock: &impl Clock,
+    filter: &Filter,
+    display: &TableDisplay,
+    data_dir: PathBuf,
+    backend: Backend,
+    confirm_over: Option<usize>,
+    output_file: Option<&Path>,
+) -> Result<(), Error> {
+    let entries = store::open(data_dir, backend, StorageKey::default())?.get_entries(filter)?;
+
+    if let Some(confirm_over) = confirm_over {
+        let interactive = std::io::stdin().is_terminal() && std::io::stderr().is_terminal();
+        let stdin = std::io::stdin();
+
+        if !confirm_entries(
+            entries.len(),
+            confirm_over,
+            interactive,
+            stdin.lock(),
+            std::io::stderr(),
+        )? {
+            return Ok(());
+        }
+    }
 
-        Ok(())
-    } else {
-        default_no_format(display, entries)
+    if let Some(output_file) = output_file {
+        return write_output_file(clock, display, &entries, output_file);
     }
+
+    let stdout = std::io::stdout();
+    let mut handle = std::io::BufWriter::new(stdout.lock());
+
+    render_entries(&mut handle, clock, display, &entries)?;
+    handle.flush().map_err(Error::WriteStdout)?;
+
+    Ok(())
 }
 
-pub fn default_no_format(display: &TableDisplay, entries: Vec<Entry>) -> Result<(), Error> {
-    let mut header = vec!["tmn"];
+/// Renders the entries into the file, which can also be a named pipe, instead
+/// of stdout. Write errors are reported even for a closed pipe as only a
+/// closed stdout is expected.
+fn write_output_file(
+    clock: &impl Clock,
+    display: &TableDisplay,
+    entries: &[Entry],
+    output_file: &Path,
+) -> Result<(), Error> {
+    let file = std::fs::File::create(output_file)
+        .map_err(|err| Error::CreateOutputFile(output_file.to_path_buf(), err))?;
+    let mut handle = std::io::BufWriter::new(file);
 
-    if display.host.is_show() {
-        header.push("host");
-    };
+    render_entries(&mut handle, clock, display, entries).map_err(|err| match err {
+        Error::WriteStdout(err) => Error::WriteOutputFile(output_file.to_path_buf(), err),
+        err => err,
+    })?;
 
-    if display.duration.is_show() {
-        header.push("duration");
-    };
+    handle
+        .flush()
+        .map_err(|err| Error::WriteOutputFile(output_file.to_path_buf(), err))
+}
 
-    if display.status.is_show() {
-        header.push("res");
-    };
+/// Position in the entries ordered from newest to oldest. A page continues
+/// with the entries that come after the last entry of the previous page.
+/// Entries can share the key, for example imported entries without a
+/// sequence number, so the cursor also stores how many of the entries with
+/// its key were already returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cursor {
+    time_finished: DateTime<Utc>,
+    session_id: Uuid,
+    seq: u64,
+    returned: usize,
+}
+
+/// Orders entries by what a cursor stores about them and the entries with the
+/// same key by all of their fields.
+fn page_key(entry: &Entry) -> (DateTime<Utc>, Uuid, u64) {
+    (entry.time_finished, entry.session_id, entry.seq)
+}
+
+impl Cursor {
+    fn key(&self) -> (DateTime<Utc>, Uuid, u64) {
+        (self.time_finished, self.session_id, self.seq)
+    }
+}
 
-    if display.session.is_show() {
-        header.push("ses");
+impl std::fmt::Display for Cursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}.{:09}:{}:{}:{}",
+            self.time_finished.timestamp(),
+            self.time_finished.timestamp_subsec_nanos(),
+            self.session_id,
+            self.seq,
+            self.returned
+        )
     }
+}
+
+impl std::str::FromStr for Cursor {
+    type Err = Error;
+
+    fn from_str(cursor: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::InvalidCursor(cursor.to_string());
+
+        let mut parts = cursor.split(':');
+        let mut next = || parts.next().ok_or_else(invalid);
+
+        let (seconds, nanoseconds) = next()?.split_once('.').ok_or_else(invalid)?;
+        let time_finished = Utc
+            .timestamp_opt(
+                seconds.parse().map_err(|_| invalid())?,
+                nanoseconds.parse().map_err(|_| invalid())?,
+            )
+            .single()
+            .ok_or_else(invalid)?;
+        let session_id = Uuid::parse_str(next()?).map_err(|_| invalid())?;
+        let seq = next()?.parse().map_err(|_| invalid())?;
+        let returned = next()?.parse().map_err(|_| invalid())?;
+
+        if parts.next().is_some() {
+            return Err(invalid());
+        }
 
-    if display.pwd.is_show() {
-        header.push("pwd");
+        Ok(Self {
+            time_finished,
+            session_id,
+            seq,
+            returned,
+        })
     }
+}
+
+#[derive(Debug, serde::Serialize)]
+struct Page {
+    entries: Vec<Entry>,
+    next_cursor: Option<String>,
+}
 
-    header.push("cmd");
+/// Prints the number of entries that match the filter.
+pub fn count(filter: &Filter, data_dir: PathBuf, backend: Backend) -> Result<(), Error> {
+    let entries = store::open(data_dir, backend, StorageKey::default())?.get_entries(filter)?;
 
     let stdout = std::io::stdout();
     let mut handle = stdout.lock();
 
-    if display.header.is_show() {
-        handle
-            .write_all(header.join("\t").as_bytes())
-            .map_err(Error::WriteStdout)?;
+    writeln!(handle, "{}", entries.len()).map_err(Error::WriteStdout)
+}
 
-        handle.write_all(b"\n").map_err(Error::WriteStdout)?;
-    }
+/// Prints the newest entry that matches the filter. The JSON formats print the
+/// entry as a single object instead of an array.
+pub fn last(
+    clock: &impl Clock,
+    filter: &Filter,
+    display: &TableDisplay,
+    data_dir: PathBuf,
+    backend: Backend,
+) -> Result<(), Error> {
+    let entries = store::open(data_dir, backend, StorageKey::default())?.get_entries(filter)?;
 
-    for entry in entries {
-        if let Err(err) = default_no_format_entry(&mut handle, display, &entry) {
-            warn!("{}", Error::FormatEntry(Box::new(err), entry));
+    let stdout = std::io::stdout();
+    let mut handle = std::io::BufWriter::new(stdout.lock());
+
+    render_last(&mut handle, clock, display, &entries)?;
+    handle.flush().map_err(Error::WriteStdout)?;
+
+    Ok(())
+}
+
+fn render_last(
+    handle: &mut impl Write,
+    clock: &impl Clock,
+    display: &TableDisplay,
+    entries: &[Entry],
+) -> Result<(), Error> {
+    let entry = entries.last().ok_or(Error::NoEntries)?;
+
+    match display.output {
+        OutputFormat::Table | OutputFormat::Plain => {
+            render_entries(handle, clock, display, std::slice::from_ref(entry))
+        }
+
+        OutputFormat::Json | OutputFormat::Ndjson | OutputFormat::NdjsonStream => {
+            write_json_entry(handle, entry)?;
+            handle.write_all(b"\n").map_err(Error::WriteStdout)
         }
     }
+}
+
+/// Prints the newest `page_size` entries that come after the cursor as a JSON
+/// object together with the cursor of the next page, which is null on the
+/// last page.
+pub fn page(
+    filter: &Filter,
+    data_dir: PathBuf,
+    backend: Backend,
+    page_size: usize,
+    cursor: Option<Cursor>,
+) -> Result<(), Error> {
+    let entries = store::open(data_dir, backend, StorageKey::default())?.get_entries(filter)?;
+    let page = page_entries(entries, page_size, cursor);
+
+    let stdout = std::io::stdout();
+    let mut handle = std::io::BufWriter::new(stdout.lock());
+
+    write_json(&mut handle, &page)?;
+    handle.write_all(b"\n").map_err(Error::WriteStdout)?;
+    handle.flush().map_err(Error::WriteStdout)?;
 
     Ok(())
 }
 
-fn default_no_format_entry<T>(
-    handle: &mut T,
-    display: &TableDisplay,
-    entry: &Entry,
-) -> Result<(), Error>
-where
-    T: Write,
-{
-    let mut row = vec![format_timestamp(entry.time_finished)];
+fn page_entries(mut entries: Vec<Entry>, page_size: usize, cursor: Option<Cursor>) -> Page {
+    entries.sort_by(|a, b| (page_key(b), b).cmp(&(page_key(a), a)));
+
+    // Entries with the key of the cursor that were not returned yet come
+    // after the ones that were.
+    let start = cursor.map_or(0, |cursor| {
+        let newer = entries
+            .iter()
+            .take_while(|entry| page_key(entry) > cursor.key())
+            .count();
+        let returned = entries[newer..]
+            .iter()
+            .take(cursor.returned)
+            .take_while(|entry| page_key(entry) == cursor.key())
+            .count();
+
+        newer + returned
+    });
+
+    let end = entries.len().min(start + page_size);
+
+    let next_cursor = if end < entries.len() {
+        entries[..end].last().map(|last| {
+            let (time_finished, session_id, seq) = page_key(last);
+            let returned = entries[..end]
+                .iter()
+                .rev()
+                .take_while(|entry| page_key(entry) == page_key(last))
+                .count();
+
+            Cursor {
+                time_finished,
+                session_id,
+                seq,
+                returned,
+            }
+            .to_string()
+        })
+    } else {
+        None
+    };
+
+    entries.truncate(end);
+    entries.drain(..start);
 
-    if display.host.is_show() {
-        row.push(entry.hostname.clone());
+    Page {
+        entries,
+        next_cursor,
     }
+}
+
+/// Writes the entries to the writer in the output format of the display.
+pub fn render_entries(
+    handle: &mut impl Write,
+    clock: &impl Clock,
+    display: &TableDisplay,
+    entries: &[Entry],
+) -> Result<(), Error> {
+    let reversed: Vec<_>;
+    let entries = if display.reverse {
+        reversed = entries.iter().rev().cloned().collect();
+        &reversed
+    } else {
+        entries
+    };
 
-    if display.duration.is_show() {
-        row.push(format_duration(entry.time_start, entry.time_finished)?);
+    match display.output {
+        OutputFormat::Table => write_table(handle, clock, display, entries),
+        OutputFormat::Plain => write_plain(handle, clock, display, entries),
+        OutputFormat::Json => write_json_entries(handle, entries),
+        OutputFormat::Ndjson => write_ndjson_entries(handle, entries),
+        OutputFormat::NdjsonStream => write_ndjson_stream(handle, entries),
     }
+}
 
-    if display.status.is_show() {
-        row.push(format!("{}", entry.result));
+/// Prints how many entries matched and, if there are more than `confirm_over`,
+/// asks whether they should be printed. Without a terminal there is nobody to
+/// ask so the entries are always printed.
+fn confirm_entries(
+    count: usize,
+    confirm_over: usize,
+    interactive: bool,
+    mut input: impl BufRead,
+    mut output: impl Write,
+) -> Result<bool, Error> {
+    writeln!(output, "{count} matching entries").map_err(Error::Confirm)?;
+
+    if !interactive || count <= confirm_over {
+        return Ok(true);
     }
 
-    if display.session.is_show() {
-        row.push(format_uuid(entry.session_id));
+    write!(output, "print all {count} entries? [y/N] ").map_err(Error::Confirm)?;
+    output.flush().map_err(Error::Confirm)?;
+
+    let mut answer = String::new();
+    input.read_line(&mut answer).map_err(Error::Confirm)?;
+
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+fn write_plain(
+    handle: &mut impl Write,
+    clock: &impl Clock,
+    display: &TableDisplay,
+    entries: &[Entry],
+) -> Result<(), Error> {
+    if display.header.is_show() {
+        let header: Vec<_> = display
+            .columns
+            .iter()
+            .map(|column| column.header())
+            .collect();
+
+        handle
+            .write_all(header.join(&display.field_separator).as_bytes())
+            .map_err(Error::WriteStdout)?;
+
+        handle
+            .write_all(display.record_separator.as_bytes())
+            .map_err(Error::WriteStdout)?;
     }
 
-    if display.pwd.is_show() {
-        row.push(format_pwd(&entry.pwd)?);
+    for (index, entry) in entries.iter().enumerate() {
+        if display.group_sessions && starts_session(entries, index) {
+            handle
+                .write_all(display.record_separator.as_bytes())
+                .map_err(Error::WriteStdout)?;
+        }
+
+        if let Err(err) = write_plain_entry(handle, clock, display, entry) {
+            warn!(
+                "{}",
+                Error::FormatEntry(Box::new(err), Box::new(entry.clone()))
+            );
+        }
     }
 
-    row.push(format_command(&entry.command, display.format));
+    Ok(())
+}
+
+/// Returns true when the entry at the index belongs to another session than
+/// the entry before it. Entries are sorted by time so a session that ran at
+/// the same time as another one shows up as more than one group.
+fn starts_session(entries: &[Entry], index: usize) -> bool {
+    index > 0 && entries[index - 1].session_id != entries[index].session_id
+}
+
+fn write_plain_entry(
+    handle: &mut impl Write,
+    clock: &impl Clock,
+    display: &TableDisplay,
+    entry: &Entry,
+) -> Result<(), Error> {
+    let row = format_row(clock, display, entry)?;
 
     handle
-        .write_all(row.join("\t").as_bytes())
+        .write_all(row.join(&display.field_separator).as_bytes())
         .map_err(Error::WriteStdout)?;
 
-    handle.write_all(b"\n").map_err(Error::WriteStdout)?;
+    handle
+        .write_all(display.record_separator.as_bytes())
+        .map_err(Error::WriteStdout)?;
 
     Ok(())
 }
 
-pub fn default_format(display: &TableDisplay, entries: Vec<Entry>) {
+fn write_table(
+    handle: &mut impl Write,
+    clock: &impl Clock,
+    display: &TableDisplay,
+    entries: &[Entry],
+) -> Result<(), Error> {
     let mut table = Table::new();
     table.load_preset("                   ");
     table.set_content_arrangement(comfy_table::ContentArrangement::Dynamic);
 
-    let mut header = vec![Cell::new("tmn").add_attribute(Attribute::Bold)];
+    if display.header.is_show() {
+        table.set_header(display.columns.iter().map(|column| {
+            let cell = Cell::new(column.header());
+
+            if display.color.is_show() {
+                cell.add_attribute(Attribute::Bold)
+            } else {
+                cell
+            }
+        }));
+    }
 
-    if display.host.is_show() {
-        header.push(Cell::new("host").add_attribute(Attribute::Bold));
-    };
+    for (index, entry) in entries.iter().enumerate() {
+        if display.group_sessions && starts_session(entries, index) {
+            table.add_row(vec![""; display.columns.len()]);
+        }
 
-    if display.duration.is_show() {
-        header.push(Cell::new("duration").add_attribute(Attribute::Bold));
-    };
+        match format_row(clock, display, entry) {
+            Ok(row) => {
+                table.add_row(
+                    row.into_iter()
+                        .zip(&display.columns)
+                        .map(|(value, column)| {
+                            let cell = Cell::new(value);
+
+                            if display.color.is_show() && *column == Column::Status {
+                                cell.fg(status_color(entry.result))
+                            } else {
+                                cell
+                            }
+                        }),
+                );
+            }
+
+            Err(err) => warn!(
+                "{}",
+                Error::FormatEntry(Box::new(err), Box::new(entry.clone()))
+            ),
+        }
+    }
 
-    if display.status.is_show() {
-        header.push(Cell::new("res").add_attribute(Attribute::Bold));
-    };
+    writeln!(handle, "{table}").map_err(Error::WriteStdout)
+}
 
-    if display.session.is_show() {
-        header.push(Cell::new("ses").add_attribute(Attribute::Bold));
-    }
+/// Writes the entries as a JSON array with every entry on its own line.
+fn write_json_entries(handle: &mut impl Write, entries: &[Entry]) -> Result<(), Error> {
+    handle.write_all(b"[").map_err(Error::WriteStdout)?;
+
+    for (index, entry) in entries.iter().enumerate() {
+        if index > 0 {
+            handle.write_all(b",").map_err(Error::WriteStdout)?;
+        }
 
-    if display.pwd.is_show() {
-        header.push(Cell::new("pwd").add_attribute(Attribute::Bold));
+        handle.write_all(b"\n").map_err(Error::WriteStdout)?;
+        write_json_entry(handle, entry)?;
     }
 
-    header.push(Cell::new("cmd").add_attribute(Attribute::Bold));
+    handle.write_all(b"\n]\n").map_err(Error::WriteStdout)?;
 
-    if display.header.is_show() {
-        table.set_header(header);
-    }
+    Ok(())
+}
 
+/// Writes every entry as a JSON object on its own line.
+fn write_ndjson_entries(handle: &mut impl Write, entries: &[Entry]) -> Result<(), Error> {
     for entry in entries {
-        if let Err(err) = default_format_entry(&mut table, display, &entry) {
-            warn!("{}", Error::FormatEntry(Box::new(err), entry));
-        }
+        write_json_entry(handle, entry)?;
+        handle.write_all(b"\n").map_err(Error::WriteStdout)?;
     }
 
-    println!("{}", table);
+    Ok(())
 }
 
-fn default_format_entry(
-    table: &mut Table,
-    display: &TableDisplay,
-    entry: &Entry,
-) -> Result<(), Error> {
-    let mut row = vec![format_timestamp(entry.time_finished)];
-
-    if display.host.is_show() {
-        row.push(entry.hostname.clone());
+/// Writes every entry as a JSON object on its own line and flushes after every
+/// line so a reader on the other end of a pipe gets the entries right away.
+fn write_ndjson_stream(handle: &mut impl Write, entries: &[Entry]) -> Result<(), Error> {
+    for entry in entries {
+        write_json_entry(handle, entry)?;
+        handle.write_all(b"\n").map_err(Error::WriteStdout)?;
+        handle.flush().map_err(Error::WriteStdout)?;
     }
 
-    if display.duration.is_show() {
-        row.push(format_duration(entry.time_start, entry.time_finished)?);
-    }
+    Ok(())
+}
 
-    if display.status.is_show() {
-        row.push(format!("{}", entry.result));
-    }
+fn write_json_entry(handle: &mut impl Write, entry: &Entry) -> Result<(), Error> {
+    write_json(handle, entry)
+}
 
-    if display.session.is_show() {
-        row.push(format_uuid(entry.session_id));
-    }
-    if display.pwd.is_show() {
-        row.push(format_pwd(&entry.pwd)?);
-    }
+fn write_json(handle: &mut impl Write, value: &impl serde::Serialize) -> Result<(), Error> {
+    serde_json::to_writer(handle, value).map_err(|err| {
+        // Keep io errors as WriteStdout so a closed pipe is not reported.
+        if err.is_io() {
+            Error::WriteStdout(err.into())
+        } else {
+            Error::SerializeJson(err)
+        }
+    })
+}
 
-    row.push(format_command(&entry.command, display.format));
+/// Failed commands are printed in red so they stand out.
+const fn status_color(result: u16) -> Color {
+    if result == 0 {
+        Color::Green
+    } else {
+        Color::Red
+    }
+}
 
-    table.add_row(row);
+fn format_row(
+    clock: &impl Clock,
+    display: &TableDisplay,
+    entry: &Entry,
+) -> Result<Vec<String>, Error> {
+    display
+        .columns
+        .iter()
+        .map(|column| format_column(clock, display, entry, *column))
+        .collect()
+}
 
-    Ok(())
+fn format_column(
+    clock: &impl Clock,
+    display: &TableDisplay,
+    entry: &Entry,
+    column: Column,
+) -> Result<String, Error> {
+    Ok(match column {
+        Column::Time => format_timestamp(clock, display, entry.time_finished),
+        Column::Relative => format_relative(clock, entry.time_finished),
+        Column::Host => entry.hostname.clone(),
+        Column::User => entry.user.clone(),
+        Column::Duration => format_duration(entry.duration())?,
+        Column::Status => format!("{}", entry.result),
+        Column::Session => format_uuid(entry.session_id),
+        Column::HistEvent => format_hist_event(entry.hist_event),
+        Column::Pipes => entry.pipes().to_string(),
+        Column::Tag => entry.tag.clone().unwrap_or_default(),
+        Column::Pwd => format_pwd(&entry.pwd, &display.pwd_abbreviations)?,
+        // The plain output only has to escape line breaks when they would
+        // be mistaken for the end of a row, e.g. not with --print0.
+        Column::Command => format_command(
+            &entry.command,
+            display.output == OutputFormat::Table
+                || !(display.record_separator.contains('\n')
+                    || display.field_separator.contains('\n')),
+            display.command_format,
+        ),
+    })
 }
 
+/// Records the start of the command. Without a command the command is read
+/// from stdin which avoids quoting problems and the argument length limit.
 pub fn zsh_add_history(
     config: &config::Config,
+    clock: &impl Clock,
+    command: Option<String>,
+    socket_path: PathBuf,
+) -> Result<(), Error> {
+    let command = match command {
+        Some(command) => command,
+        None => read_command(std::io::stdin().lock())?,
+    };
+
+    add_history(config, clock, command, socket_path, |name| {
+        std::env::var(name)
+    })
+}
+
+/// Records the start of the command with the variables the shell hooks set
+/// looked up with `var`.
+fn add_history(
+    config: &config::Config,
+    clock: &impl Clock,
     command: String,
     socket_path: PathBuf,
+    var: impl Fn(&str) -> Result<String, std::env::VarError>,
 ) -> Result<(), Error> {
-    if config.ignore_space && command.starts_with(' ') {
+    if recording_disabled(var("HISTDB_RS_DISABLE")) {
+        debug!("not recording as HISTDB_RS_DISABLE is set");
+    } else if config.ignore_space && command.starts_with(' ') {
         debug!("not recording a command starting with a space");
+    } else if config.ignores_command(&command) {
+        debug!("not recording a command matching ignore_commands");
     } else {
-        let data = CommandStart::from_env(config, command)?;
-        client::new(socket_path).send(&Message::CommandStart(data))?;
+        let command = if config.sanitize_control_chars {
+            strip_control_chars(&command)
+        } else {
+            command
+        };
+
+        let data = CommandStart::from_vars(config, clock, command, var)?;
+        recording_client(config, socket_path)
+            .timeout(config.send_timeout())
+            .retries(config.send_retries, config.send_retry_delay())
+            .send(&Message::CommandStart(data))?;
     }
 
     Ok(())
 }
 
-pub fn server(cache_dir: PathBuf, socket: PathBuf, data_dir: PathBuf) -> Result<(), Error> {
+pub fn server(
+    config: &config::Config,
+    cache_dir: PathBuf,
+    socket: PathBuf,
+    data_dir: PathBuf,
+    listen: Option<String>,
+) -> Result<(), Error> {
     server::builder(cache_dir, data_dir, socket, true)
+        .socket_recv_buffer_bytes(config.socket_recv_buffer_bytes)
+        .backend(config.backend)
+        .storage_key(config.storage_key)
+        .split_compound_commands(config.split_compound_commands)
+        .processor_queue_size(config.processor_queue_size)
+        .git_commit_mode(config.git_commit_mode)
+        .git_commit_batch(
+            config.git_commit_batch_entries,
+            config.git_commit_batch_interval(),
+        )
+        .listen(listen)
+        .auth_token(config.auth_token.clone())
         .build()?
         .run()?;
 
     Ok(())
 }
 
-pub fn stop(socket_path: PathBuf) -> Result<(), Error> {
-    client::new(socket_path).send(&Message::Stop)?;
+pub fn stop(config: &config::Config, socket_path: PathBuf) -> Result<(), Error> {
+    local_client(config, socket_path).send(&Message::Stop)?;
 
     Ok(())
 }
 
-pub fn disable(socket_path: PathBuf) -> Result<(), Error> {
-    let session_id = session_id_from_env()?;
-    client::new(socket_path).send(&Message::Disable(session_id))?;
+/// Waits until the server committed every entry it has written so far, e.g.
+/// before pushing the git repository of the data dir.
+pub fn flush(config: &config::Config, socket_path: PathBuf) -> Result<(), Error> {
+    local_client(config, socket_path).flush(std::time::Duration::from_secs(30))?;
 
     Ok(())
 }
 
-pub fn enable(socket_path: PathBuf) -> Result<(), Error> {
+pub fn disable(config: &config::Config, socket_path: PathBuf) -> Result<(), Error> {
     let session_id = session_id_from_env()?;
-    client::new(socket_path).send(&Message::Enable(session_id))?;
+    recording_client(config, socket_path).send(&Message::Disable(session_id))?;
 
     Ok(())
 }
 
-pub fn precmd(socket_path: PathBuf) -> Result<(), Error> {
-    let data = CommandFinished::from_env()?;
-
-    client::new(socket_path).send(&Message::CommandFinished(data))?;
+pub fn enable(config: &config::Config, socket_path: PathBuf) -> Result<(), Error> {
+    let session_id = session_id_from_env()?;
+    recording_client(config, socket_path).send(&Message::Enable(session_id))?;
 
     Ok(())
 }
 
-pub fn session_id() {
-    println!("{}", Uuid::new_v4());
+/// Returns the client for the messages that record commands. They go to the
+/// configured server address if there is one, everything else always talks
+/// to the local server.
+fn recording_client(config: &config::Config, socket_path: PathBuf) -> client::Client {
+    local_client(config, socket_path).server_address(config.server_address.clone())
 }
 
-pub fn init() {
-    println!("{}", include_str!("../../resources/init.zsh"));
+/// Returns the client for the local server which sends the configured
+/// `auth_token` with every message.
+fn local_client(config: &config::Config, socket_path: PathBuf) -> client::Client {
+    client::new(socket_path).auth_token(config.auth_token.clone())
 }
 
-pub fn bench(socket_path: PathBuf) -> Result<(), Error> {
-    let client = client::new(socket_path);
+pub fn preexec(
+    config: &config::Config,
+    clock: &impl Clock,
+    socket_path: PathBuf,
+) -> Result<(), Error> {
+    let data = CommandStarted::from_env(clock)?;
 
-    let mut start = CommandStart {
-        command: "test".to_string(),
-        hostname: "test_hostname".to_string(),
-        pwd: PathBuf::from("/tmp/test_pwd"),
-        session_id: Uuid::new_v4(),
-        time_stamp: Utc::now(),
-        user: "test_user".to_string(),
-    };
+    recording_client(config, socket_path)
+        .timeout(config.send_timeout())
+        .retries(config.send_retries, config.send_retry_delay())
+        .send(&Message::CommandStarted(data))?;
 
-    let mut finished = CommandFinished {
-        session_id: start.session_id,
-        time_stamp: Utc::now(),
-        result: 0,
-    };
+    Ok(())
+}
+
+pub fn precmd(
+    config: &config::Config,
+    clock: &impl Clock,
+    socket_path: PathBuf,
+) -> Result<(), Error> {
+    let data = CommandFinished::from_env(config, clock)?;
+
+    recording_client(config, socket_path)
+        .timeout(config.send_timeout())
+        .retries(config.send_retries, config.send_retry_delay())
+        .send(&Message::CommandFinished(data))?;
+
+    Ok(())
+}
+
+/// Returns the store of the csv files in the data dir for the commands that
+/// work on the files directly.
+fn csv_store(
+    config: &config::Config,
+    data_dir: PathBuf,
+    command: &'static str,
+) -> Result<store::Store, Error> {
+    match config.backend {
+        Backend::Csv => Ok(store::new(data_dir).storage_key(config.storage_key)),
+        Backend::Sqlite => Err(Error::NotSupportedForSqlite(command)),
+    }
+}
+
+/// Returns the store of the csv files in the data dir for the commands that
+/// rewrite the files. A file is rewritten by replacing it with a new one so
+/// entries the server appends in the meantime would be lost.
+fn rewrite_store(
+    config: &config::Config,
+    data_dir: PathBuf,
+    socket_path: &Path,
+    command: &'static str,
+) -> Result<store::Store, Error> {
+    let store = csv_store(config, data_dir, command)?;
+
+    ensure_server_stopped(config, socket_path)?;
+
+    Ok(store)
+}
+
+fn ensure_server_stopped(config: &config::Config, socket_path: &Path) -> Result<(), Error> {
+    if local_client(config, socket_path.to_path_buf()).server_reachable() {
+        return Err(Error::ServerRunning(socket_path.to_path_buf()));
+    }
+
+    Ok(())
+}
+
+/// Marks the entries that match the filter as deleted. They are hidden from
+/// now on and removed by `gc` once they were deleted for long enough.
+pub fn delete(
+    config: &config::Config,
+    clock: &impl Clock,
+    filter: &Filter,
+    data_dir: PathBuf,
+    socket_path: &Path,
+    dry_run: bool,
+) -> Result<(), Error> {
+    let store = if dry_run {
+        csv_store(config, data_dir, "delete")?
+    } else {
+        rewrite_store(config, data_dir, socket_path, "delete")?
+    };
+
+    let deleted = store.delete(filter, clock.now(), dry_run)?;
+
+    for (path, count) in &deleted {
+        if dry_run {
+            println!("would delete {} entries in {}", count, path.display());
+        } else {
+            println!("deleted {} entries in {}", count, path.display());
+        }
+    }
+
+    let total: usize = deleted.iter().map(|(_, count)| count).sum();
+    if dry_run {
+        println!("would delete {total} entries");
+    } else {
+        println!("deleted {total} entries");
+    }
+
+    Ok(())
+}
+
+pub fn gc(
+    config: &config::Config,
+    clock: &impl Clock,
+    data_dir: PathBuf,
+    socket_path: &Path,
+    older_than: std::time::Duration,
+) -> Result<(), Error> {
+    let older_than =
+        chrono::Duration::from_std(older_than).map_err(|_| Error::DurationOutOfRange)?;
+    let removed =
+        rewrite_store(config, data_dir, socket_path, "gc")?.gc(clock.now() - older_than)?;
+
+    for (path, count) in removed {
+        println!("removed {} deleted entries from {}", count, path.display());
+    }
+
+    Ok(())
+}
+
+pub fn cache_gc(
+    clock: &impl Clock,
+    cache_dir: PathBuf,
+    socket_path: PathBuf,
+    stale_after: Option<std::time::Duration>,
+) -> Result<(), Error> {
+    if client::new(socket_path).server_reachable() {
+        warn!("server seems to be running, stop it before compacting the cache");
+    }
+
+    let db = server::db::new(cache_dir).map_err(server::Error::Db)?;
+    let size_before = db.size_on_disk().map_err(server::Error::Db)?;
+
+    if let Some(stale_after) = stale_after {
+        let stale_after =
+            chrono::Duration::from_std(stale_after).map_err(|_| Error::DurationOutOfRange)?;
+
+        let removed = db
+            .remove_stale_entries(clock.now() - stale_after)
+            .map_err(server::Error::Db)?;
+
+        println!("removed {removed} stale entries");
+    }
+
+    db.flush().map_err(server::Error::Db)?;
+    let size_after = db.size_on_disk().map_err(server::Error::Db)?;
+
+    println!("cache size before: {size_before} bytes");
+    println!("cache size after: {size_after} bytes");
+
+    Ok(())
+}
+
+/// Prints how much disk space the history files of every host, the git
+/// repository of the data dir and the server cache take up.
+pub fn usage(config: &config::Config, data_dir: PathBuf, cache_dir: &Path) -> Result<(), Error> {
+    let git_dir = data_dir.join(".git");
+    let usage = csv_store(config, data_dir, "usage")?.usage()?;
+
+    let mut table = Table::new();
+    table.load_preset("                   ");
+    table.set_content_arrangement(comfy_table::ContentArrangement::Dynamic);
+
+    table.set_header(vec![
+        Cell::new("bytes").add_attribute(Attribute::Bold),
+        Cell::new("entries").add_attribute(Attribute::Bold),
+        Cell::new("host").add_attribute(Attribute::Bold),
+        Cell::new("file").add_attribute(Attribute::Bold),
+    ]);
+
+    for file in &usage {
+        table.add_row(vec![
+            Cell::new(file.bytes),
+            Cell::new(file.entries),
+            Cell::new(&file.hostname),
+            Cell::new(file.path.display()),
+        ]);
+    }
+
+    let history_bytes: u64 = usage.iter().map(|file| file.bytes).sum();
+    let history_entries: usize = usage.iter().map(|file| file.entries).sum();
+    let git_bytes = dir_size(&git_dir)?;
+    let cache_bytes = dir_size(cache_dir)?;
+
+    table.add_row(vec![
+        Cell::new(history_bytes),
+        Cell::new(history_entries),
+        Cell::new("history files"),
+        Cell::new(""),
+    ]);
+    table.add_row(vec![
+        Cell::new(git_bytes),
+        Cell::new(""),
+        Cell::new("git"),
+        Cell::new(git_dir.display()),
+    ]);
+    table.add_row(vec![
+        Cell::new(cache_bytes),
+        Cell::new(""),
+        Cell::new("cache"),
+        Cell::new(cache_dir.display()),
+    ]);
+    table.add_row(vec![
+        Cell::new(history_bytes + git_bytes + cache_bytes).add_attribute(Attribute::Bold),
+        Cell::new(""),
+        Cell::new("total").add_attribute(Attribute::Bold),
+        Cell::new(""),
+    ]);
+
+    println!("{table}");
+
+    Ok(())
+}
+
+/// Returns the size of all files below the path. A path that does not exist
+/// takes up no space.
+fn dir_size(path: &Path) -> Result<u64, Error> {
+    let metadata = match std::fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(err) => return Err(Error::ReadSize(path.to_path_buf(), err)),
+    };
+
+    if !metadata.is_dir() {
+        return Ok(metadata.len());
+    }
+
+    let mut size = 0;
+    for dir_entry in
+        std::fs::read_dir(path).map_err(|err| Error::ReadSize(path.to_path_buf(), err))?
+    {
+        let dir_entry = dir_entry.map_err(|err| Error::ReadSize(path.to_path_buf(), err))?;
+        size += dir_size(&dir_entry.path())?;
+    }
+
+    Ok(size)
+}
+
+pub fn trim(
+    config: &config::Config,
+    data_dir: PathBuf,
+    socket_path: &Path,
+    max_entries: Option<usize>,
+    commit: bool,
+) -> Result<(), Error> {
+    let max_entries = max_entries
+        .or(config.max_entries_per_host)
+        .ok_or(Error::MissingMaxEntries)?;
+
+    let store = rewrite_store(config, data_dir, socket_path, "trim")?;
+    let removed = store.trim(max_entries)?;
+
+    for (path, count) in &removed {
+        println!("removed {} entries from {}", count, path.display());
+    }
+
+    if commit && !removed.is_empty() {
+        store.commit(&format!("Trim history to {max_entries} entries per host"))?;
+
+        println!("committed trimmed history files");
+    }
+
+    Ok(())
+}
+
+/// Compresses the history files that did not change for `older_than`. They
+/// are still read but new entries are written to a new plain file.
+pub fn compress(
+    config: &config::Config,
+    clock: &impl Clock,
+    data_dir: PathBuf,
+    socket_path: &Path,
+    older_than: std::time::Duration,
+    commit: bool,
+) -> Result<(), Error> {
+    let older_than =
+        chrono::Duration::from_std(older_than).map_err(|_| Error::DurationOutOfRange)?;
+
+    let store = rewrite_store(config, data_dir, socket_path, "compress")?;
+    let compressed = store.compress(clock.now() - older_than)?;
+
+    for path in &compressed {
+        println!("compressed {}", path.display());
+    }
+
+    if commit && !compressed.is_empty() {
+        store.commit("Compress old history files")?;
+
+        println!("committed compressed history files");
+    }
+
+    Ok(())
+}
+
+/// Removes entries that finished longer ago than `older_than` and all but the
+/// newest `keep_last` entries of every host. The changes are committed when
+/// the data dir is a git repository.
+pub fn prune(
+    config: &config::Config,
+    clock: &impl Clock,
+    data_dir: PathBuf,
+    socket_path: &Path,
+    older_than: Option<std::time::Duration>,
+    keep_last: Option<usize>,
+    dry_run: bool,
+) -> Result<(), Error> {
+    let before = older_than
+        .map(|older_than| {
+            chrono::Duration::from_std(older_than)
+                .map(|older_than| clock.now() - older_than)
+                .map_err(|_| Error::DurationOutOfRange)
+        })
+        .transpose()?;
+
+    // A dry run only reads the files.
+    let store = if dry_run {
+        csv_store(config, data_dir, "prune")?
+    } else {
+        rewrite_store(config, data_dir, socket_path, "prune")?
+    };
+    let removed = store.prune(before, keep_last, dry_run)?;
+
+    for (path, count) in &removed {
+        if dry_run {
+            println!("would remove {} entries from {}", count, path.display());
+        } else {
+            println!("removed {} entries from {}", count, path.display());
+        }
+    }
+
+    if dry_run || removed.is_empty() {
+        return Ok(());
+    }
+
+    if store.is_git_repository() {
+        store.commit("Prune history")?;
+
+        println!("committed pruned history files");
+    } else {
+        warn!("data dir is not a git repository, not committing pruned history files");
+    }
+
+    Ok(())
+}
+
+/// Replaces the matches of the pattern in all stored commands with `***`.
+/// With `rewrite_history` the matches are also removed from the commits of the
+/// git repository of the data dir.
+pub fn scrub(
+    config: &config::Config,
+    data_dir: PathBuf,
+    socket_path: &Path,
+    pattern: &Regex,
+    commit: bool,
+    rewrite_history: bool,
+) -> Result<(), Error> {
+    // The sqlite backend changes the commands in place.
+    if config.backend == Backend::Csv {
+        ensure_server_stopped(config, socket_path)?;
+    }
+
+    let scrubbed =
+        store::open(data_dir.clone(), config.backend, config.storage_key)?.scrub(pattern)?;
+
+    // Committing and rewriting the history works on the whole data dir no
+    // matter which backend wrote the files.
+    let store = store::new(data_dir);
+
+    for (path, count) in &scrubbed {
+        println!("scrubbed {} entries in {}", count, path.display());
+    }
+
+    let total: usize = scrubbed.iter().map(|(_, count)| count).sum();
+    println!("scrubbed {total} entries");
+
+    // The pattern is not part of the message as it most likely is the secret.
+    if commit && !scrubbed.is_empty() {
+        store.commit("Scrub sensitive data from history")?;
+
+        println!("committed scrubbed history files");
+    }
+
+    if rewrite_history {
+        store.scrub_history(pattern)?;
+
+        println!("rewrote git history of the data dir");
+    }
+
+    Ok(())
+}
+
+pub fn verify_git(data_dir: PathBuf, commit: bool) -> Result<(), Error> {
+    let store = store::new(data_dir);
+    let discrepancies = store.verify_git()?;
+
+    if discrepancies.is_empty() {
+        println!("data directory is consistent with git");
+
+        return Ok(());
+    }
+
+    for discrepancy in &discrepancies {
+        println!("{discrepancy}");
+    }
+
+    if commit {
+        store.commit("Commit pending history changes")?;
+
+        println!("committed pending changes");
+    }
+
+    Ok(())
+}
+
+/// Runs the selected consistency checks on the stored entries. Runs all
+/// checks if none is selected. With `repair` rows that can not be read are
+/// removed from the log files.
+pub fn check(
+    config: &config::Config,
+    data_dir: PathBuf,
+    socket_path: &Path,
+    sessions: bool,
+    rows: bool,
+    repair: bool,
+) -> Result<(), Error> {
+    let all = !sessions && !rows;
+
+    // The rows of the sqlite database are checked by sqlite itself.
+    if rows || (all && config.backend == Backend::Csv) {
+        let store = if repair {
+            rewrite_store(config, data_dir.clone(), socket_path, "check --repair")?
+        } else {
+            csv_store(config, data_dir.clone(), "check --rows")?
+        };
+
+        check_rows(&store, repair)?;
+    }
+
+    if !sessions && !all {
+        return Ok(());
+    }
+
+    let filter = Filter::default()
+        .include_deleted(true)
+        .hostname_aliases(config.hostname_aliases().clone());
+
+    let entries =
+        store::open(data_dir, config.backend, config.storage_key)?.get_entries(&filter)?;
+
+    let duplicates = duplicate_sessions(&entries);
+
+    if duplicates.is_empty() {
+        println!("every session belongs to one host");
+    }
+
+    for (session_id, hostnames) in duplicates {
+        println!(
+            "session {} appears on multiple hosts: {}",
+            session_id,
+            hostnames.join(", ")
+        );
+    }
+
+    Ok(())
+}
+
+/// Prints the rows of the log files that can not be read and how many entries
+/// every log file has.
+fn check_rows(store: &store::Store, repair: bool) -> Result<(), Error> {
+    let found = store.check_rows(false)?;
+
+    let invalid = found.iter().any(|check| !check.invalid.is_empty());
+
+    // The files are committed before repairing them so the dropped rows can
+    // still be recovered from the git history.
+    if repair && invalid && store.is_git_repository() && store.has_uncommitted_changes()? {
+        store.commit("Commit history files before removing invalid rows")?;
+
+        println!("committed history files before repairing them");
+    }
+
+    let checks = if repair && invalid {
+        store.check_rows(true)?
+    } else {
+        found
+    };
+
+    for check in &checks {
+        for row in &check.invalid {
+            println!(
+                "{}: line {} is not valid: {}",
+                check.path.display(),
+                row.line,
+                row.error
+            );
+        }
+    }
+
+    for check in &checks {
+        if repair {
+            println!(
+                "{}: kept {} entries, dropped {} rows",
+                check.hostname,
+                check.entries,
+                check.invalid.len()
+            );
+        } else {
+            println!(
+                "{}: {} entries, {} invalid rows",
+                check.hostname,
+                check.entries,
+                check.invalid.len()
+            );
+        }
+    }
+
+    if repair && invalid && store.is_git_repository() {
+        store.commit("Remove invalid rows from history files")?;
+
+        println!("committed repaired history files");
+    }
+
+    Ok(())
+}
+
+/// Returns the sessions that have entries from more than one host together
+/// with those hosts. A session is started by one shell so all of its entries
+/// should come from the same host.
+fn duplicate_sessions(entries: &[Entry]) -> Vec<(Uuid, Vec<String>)> {
+    let mut sessions: HashMap<Uuid, Vec<&str>> = HashMap::new();
+
+    for entry in entries {
+        let hostnames = sessions.entry(entry.session_id).or_default();
+
+        if !hostnames.contains(&entry.hostname.as_str()) {
+            hostnames.push(&entry.hostname);
+        }
+    }
+
+    let mut duplicates: Vec<_> = sessions
+        .into_iter()
+        .filter(|(_, hostnames)| hostnames.len() > 1)
+        .map(|(session_id, hostnames)| {
+            let mut hostnames: Vec<_> = hostnames.into_iter().map(str::to_string).collect();
+            hostnames.sort();
+
+            (session_id, hostnames)
+        })
+        .collect();
+
+    duplicates.sort();
+
+    duplicates
+}
+
+pub fn session_id() {
+    println!("{}", Uuid::new_v4());
+}
+
+/// Prints whether a server answers on the socket. Returns
+/// `Error::ServerNotRunning` if it does not so the exit code can tell.
+pub fn status(config: &config::Config, socket_path: PathBuf) -> Result<(), Error> {
+    match local_client(config, socket_path).ping(std::time::Duration::from_secs(1)) {
+        Ok(pid) => {
+            println!("running (pid {pid})");
+
+            Ok(())
+        }
+
+        Err(err) => {
+            debug!("can not ping server: {}", err);
+            println!("not running");
+
+            Err(Error::ServerNotRunning)
+        }
+    }
+}
+
+/// Prints the commands that were started but did not finish yet.
+pub fn running(
+    config: &config::Config,
+    clock: &impl Clock,
+    socket_path: PathBuf,
+) -> Result<(), Error> {
+    let mut running =
+        local_client(config, socket_path).running(std::time::Duration::from_secs(5))?;
+    running.sort_by_key(|entry| entry.time_stamp);
+
+    let mut table = Table::new();
+    table.load_preset("                   ");
+    table.set_content_arrangement(comfy_table::ContentArrangement::Dynamic);
+
+    table.set_header(vec![
+        Cell::new("ses").add_attribute(Attribute::Bold),
+        Cell::new("running").add_attribute(Attribute::Bold),
+        Cell::new("pwd").add_attribute(Attribute::Bold),
+        Cell::new("cmd").add_attribute(Attribute::Bold),
+    ]);
+
+    let now = clock.now();
+    for entry in running {
+        // Clocks of the shells and the server can differ a bit so a command
+        // can look like it started in the future.
+        let duration = format_duration(now - entry.time_stamp.min(now))?;
+
+        table.add_row(vec![
+            Cell::new(format_uuid(entry.session_id)),
+            Cell::new(duration),
+            Cell::new(format_pwd(&entry.pwd, &[])?),
+            Cell::new(format_command(
+                &entry.command,
+                true,
+                config::CommandFormat::Multiline,
+            )),
+        ]);
+    }
+
+    println!("{table}");
+
+    Ok(())
+}
+
+pub fn init(shell: InitShell, with_widgets: bool) {
+    println!("{}", init_script(shell, with_widgets));
+}
+
+fn init_script(shell: InitShell, with_widgets: bool) -> String {
+    let (script, widgets) = match shell {
+        InitShell::Zsh => (
+            include_str!("../../resources/init.zsh"),
+            Some(include_str!("../../resources/widgets.zsh")),
+        ),
+        InitShell::Bash => (include_str!("../../resources/init.bash"), None),
+        InitShell::Fish => (
+            include_str!("../../resources/init.fish"),
+            Some(include_str!("../../resources/widgets.fish")),
+        ),
+    };
+
+    let mut script = script.to_string();
+
+    if with_widgets {
+        if let Some(widgets) = widgets {
+            script.push_str(widgets);
+        } else {
+            warn!("there are no widgets for {:?}", shell);
+        }
+    }
+
+    script
+}
+
+pub fn bench(
+    config: &config::Config,
+    clock: &impl Clock,
+    socket_path: PathBuf,
+) -> Result<(), Error> {
+    let client = local_client(config, socket_path);
+
+    let mut start = CommandStart {
+        command: "test".to_string(),
+        hostname: "test_hostname".to_string(),
+        pwd: PathBuf::from("/tmp/test_pwd"),
+        session_id: Uuid::new_v4(),
+        time_stamp: clock.now(),
+        user: "test_user".to_string(),
+        hist_event: None,
+        tag: None,
+    };
+
+    let mut finished = CommandFinished {
+        session_id: start.session_id,
+        time_stamp: clock.now(),
+        result: 0,
+        reported_duration_ms: None,
+    };
 
     loop {
-        start.time_stamp = Utc::now();
+        start.time_stamp = clock.now();
         let message = Message::CommandStart(start.clone());
 
         client.send(&message).expect("ignore");
 
-        finished.time_stamp = Utc::now();
+        finished.time_stamp = clock.now();
         let message = Message::CommandFinished(finished.clone());
 
         client.send(&message).expect("ignore");
     }
 }
 
-fn format_timestamp(timestamp: DateTime<Utc>) -> String {
-    let today = Local::now().date();
+fn format_timestamp(
+    clock: &impl Clock,
+    display: &TableDisplay,
+    timestamp: DateTime<Utc>,
+) -> String {
+    let today = clock.now().with_timezone(&Local).date();
     let local = timestamp.with_timezone(&chrono::offset::Local);
     let date = local.date().with_timezone(&chrono::offset::Local);
 
-    if date == today {
-        local.format("%H:%M").to_string()
+    let format = if date == today {
+        display
+            .timestamp_format_today
+            .as_deref()
+            .or(display.timestamp_format.as_deref())
+            .unwrap_or("%H:%M")
     } else {
-        local.date().format("%Y-%m-%d").to_string()
+        display.timestamp_format.as_deref().unwrap_or("%Y-%m-%d")
+    };
+
+    local.format(format).to_string()
+}
+
+/// Formats how long ago the timestamp was with the two most significant units,
+/// e.g. `3h20m ago`. Timestamps in the future, which happen with clock skew
+/// between hosts, are formatted as `in 2m`.
+fn format_relative(clock: &impl Clock, timestamp: DateTime<Utc>) -> String {
+    let since = clock.now() - timestamp;
+    let in_future = since < chrono::Duration::zero();
+
+    let seconds = since.num_seconds().unsigned_abs();
+    let formatted = humantime::format_duration(std::time::Duration::from_secs(seconds))
+        .to_string()
+        .split(' ')
+        .take(2)
+        .collect::<String>();
+
+    if in_future {
+        format!("in {formatted}")
+    } else {
+        format!("{formatted} ago")
     }
 }
 
@@ -436,28 +1817,259 @@ fn format_uuid(uuid: uuid::Uuid) -> String {
         .collect()
 }
 
-fn format_pwd(pwd: impl AsRef<Path>) -> Result<String, Error> {
-    let base_dirs = directories::BaseDirs::new().ok_or(Error::GetBaseDirectories)?;
-    let home = base_dirs.home_dir();
-
-    if pwd.as_ref().starts_with(home) {
-        let mut without_home = PathBuf::from("~");
+fn format_hist_event(hist_event: Option<u64>) -> String {
+    hist_event.map_or_else(String::new, |hist_event| hist_event.to_string())
+}
 
-        let pwd_components = pwd.as_ref().components().skip(3);
+pub fn stats(
+    config: &config::Config,
+    clock: &impl Clock,
+    filter: &Filter,
+    data_dir: PathBuf,
+    group: StatsGroup,
+    top: usize,
+    compare: Option<std::time::Duration>,
+) -> Result<(), Error> {
+    let entries =
+        store::open(data_dir, config.backend, StorageKey::default())?.get_entries(filter)?;
+    let strip_prefixes = &config.strip_prefixes;
 
-        pwd_components.for_each(|component| without_home.push(component));
+    let mut table = Table::new();
+    table.load_preset("                   ");
+    table.set_content_arrangement(comfy_table::ContentArrangement::Dynamic);
 
-        Ok(without_home.to_string_lossy().to_string())
+    if let Some(compare) = compare {
+        let period = chrono::Duration::from_std(compare).map_err(|_| Error::DurationOutOfRange)?;
+        let counts = stats_compare(&entries, group, strip_prefixes, clock.now(), period)?;
+
+        table.set_header(vec![
+            Cell::new("count").add_attribute(Attribute::Bold),
+            Cell::new("delta").add_attribute(Attribute::Bold),
+            Cell::new(group.header()).add_attribute(Attribute::Bold),
+        ]);
+
+        for (key, count, delta) in counts.into_iter().take(top) {
+            table.add_row(vec![
+                Cell::new(count),
+                Cell::new(format!("{delta:+}")),
+                Cell::new(key),
+            ]);
+        }
     } else {
-        Ok(pwd.as_ref().to_string_lossy().to_string())
+        let total = entries.len();
+        let counts = stats_counts(&entries, group, strip_prefixes)?;
+
+        table.set_header(vec![
+            Cell::new("count").add_attribute(Attribute::Bold),
+            Cell::new("%").add_attribute(Attribute::Bold),
+            Cell::new(group.header()).add_attribute(Attribute::Bold),
+        ]);
+
+        for (key, count) in counts.into_iter().take(top) {
+            #[allow(clippy::cast_precision_loss)]
+            let share = count as f64 * 100.0 / total as f64;
+
+            table.add_row(vec![
+                Cell::new(count),
+                Cell::new(format!("{share:.1}")),
+                Cell::new(key),
+            ]);
+        }
     }
+
+    println!("{table}");
+
+    Ok(())
 }
 
-fn format_duration(
-    time_start: DateTime<Utc>,
-    time_finished: DateTime<Utc>,
-) -> Result<String, Error> {
-    let duration = time_finished - time_start;
+/// Counts the entries that finished in the last period and in the period of
+/// the same length before it. Returns the counts of the last period sorted
+/// like `stats_counts` together with how much they changed. Keys that only
+/// appear in the period before come last with a count of 0.
+fn stats_compare(
+    entries: &[Entry],
+    group: StatsGroup,
+    strip_prefixes: &[String],
+    now: DateTime<Utc>,
+    period: chrono::Duration,
+) -> Result<Vec<(String, usize, i64)>, Error> {
+    let current_start = now - period;
+    let previous_start = current_start - period;
+
+    let (current, previous): (Vec<_>, Vec<_>) = entries
+        .iter()
+        .filter(|entry| entry.time_finished > previous_start && entry.time_finished <= now)
+        .cloned()
+        .partition(|entry| entry.time_finished > current_start);
+
+    let mut previous: HashMap<_, _> = stats_counts(&previous, group, strip_prefixes)?
+        .into_iter()
+        .collect();
+
+    #[allow(clippy::cast_possible_wrap)]
+    let delta = |current: usize, previous: usize| current as i64 - previous as i64;
+
+    let mut counts: Vec<_> = stats_counts(&current, group, strip_prefixes)?
+        .into_iter()
+        .map(|(key, count)| {
+            let before = previous.remove(&key).unwrap_or_default();
+            (key, count, delta(count, before))
+        })
+        .collect();
+
+    let mut dropped: Vec<_> = previous
+        .into_iter()
+        .map(|(key, before)| (key, 0, delta(0, before)))
+        .collect();
+    dropped.sort_by(|(key_a, _, delta_a), (key_b, _, delta_b)| {
+        delta_a.cmp(delta_b).then_with(|| key_a.cmp(key_b))
+    });
+
+    counts.append(&mut dropped);
+
+    Ok(counts)
+}
+
+/// Writes all entries matching the filter from all history files into one
+/// file, sorted by the time they finished.
+pub fn export(
+    filter: &Filter,
+    data_dir: PathBuf,
+    backend: Backend,
+    format: ExportFormat,
+    output: &Path,
+) -> Result<(), Error> {
+    let entries = store::open(data_dir, backend, StorageKey::default())?.get_entries(filter)?;
+
+    let file = std::fs::File::create(output)
+        .map_err(|err| Error::CreateExportFile(output.to_path_buf(), err))?;
+    let mut handle = std::io::BufWriter::new(file);
+
+    write_export(&mut handle, format, &entries).map_err(|err| match err {
+        Error::WriteStdout(err) => Error::WriteExportFile(output.to_path_buf(), err),
+        err => err,
+    })?;
+
+    handle
+        .flush()
+        .map_err(|err| Error::WriteExportFile(output.to_path_buf(), err))?;
+
+    debug!("exported {} entries to {}", entries.len(), output.display());
+
+    Ok(())
+}
+
+fn write_export(
+    handle: &mut impl Write,
+    format: ExportFormat,
+    entries: &[Entry],
+) -> Result<(), Error> {
+    match format {
+        ExportFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(handle);
+
+            for entry in entries {
+                writer.serialize(entry).map_err(Error::SerializeCsv)?;
+            }
+
+            writer.flush().map_err(Error::WriteStdout)
+        }
+        ExportFormat::Json => write_json_entries(handle, entries),
+        ExportFormat::Ndjson => write_ndjson_entries(handle, entries),
+    }
+}
+
+/// Counts the entries by the given group and returns the counts sorted from
+/// most to least used. Ties are sorted by key.
+fn stats_counts(
+    entries: &[Entry],
+    group: StatsGroup,
+    strip_prefixes: &[String],
+) -> Result<Vec<(String, usize)>, Error> {
+    let mut counts: HashMap<String, usize> = HashMap::new();
+
+    for entry in entries {
+        let key = match group {
+            StatsGroup::Command => strip_command_prefixes(&entry.command, strip_prefixes)
+                .split_whitespace()
+                .next()
+                .unwrap_or_default()
+                .to_string(),
+            StatsGroup::Pwd => format_pwd(&entry.pwd, &[])?,
+            StatsGroup::Hostname => entry.hostname.clone(),
+            StatsGroup::Hour => entry
+                .time_finished
+                .with_timezone(&Local)
+                .format("%H")
+                .to_string(),
+        };
+
+        *counts.entry(key).or_default() += 1;
+    }
+
+    let mut counts: Vec<_> = counts.into_iter().collect();
+    counts.sort_by(|(key_a, count_a), (key_b, count_b)| {
+        count_b.cmp(count_a).then_with(|| key_a.cmp(key_b))
+    });
+
+    Ok(counts)
+}
+
+/// Removes the leading words of the command that match one of the prefixes,
+/// like `sudo` or `time`, so the command is grouped with the unprefixed
+/// command. Prefixes are removed until none of them matches.
+fn strip_command_prefixes<'a>(command: &'a str, prefixes: &[String]) -> &'a str {
+    let mut command = command.trim_start();
+
+    while let Some(rest) = prefixes.iter().find_map(|prefix| {
+        command
+            .strip_prefix(prefix.trim())
+            .filter(|rest| rest.starts_with(char::is_whitespace))
+    }) {
+        command = rest.trim_start();
+    }
+
+    command
+}
+
+fn format_pwd(pwd: impl AsRef<Path>, abbreviations: &[(PathBuf, String)]) -> Result<String, Error> {
+    let base_dirs = directories::BaseDirs::new().ok_or(Error::GetBaseDirectories)?;
+
+    Ok(abbreviate_pwd(
+        pwd.as_ref(),
+        base_dirs.home_dir(),
+        abbreviations,
+    ))
+}
+
+/// Replaces the longest matching root of the abbreviations or the home
+/// directory at the start of the path with its alias or `~`.
+fn abbreviate_pwd(pwd: &Path, home: &Path, abbreviations: &[(PathBuf, String)]) -> String {
+    let abbreviation = abbreviations
+        .iter()
+        .map(|(root, alias)| (root.as_path(), alias.as_str()))
+        .chain(std::iter::once((home, "~")))
+        .filter(|(root, _)| pwd.starts_with(root))
+        .max_by_key(|(root, _)| root.components().count());
+
+    match abbreviation {
+        Some((root, alias)) => {
+            let mut abbreviated = PathBuf::from(alias);
+
+            // Pushing an empty path would add a trailing slash.
+            match pwd.strip_prefix(root) {
+                Ok(rest) if !rest.as_os_str().is_empty() => abbreviated.push(rest),
+                _ => {}
+            }
+
+            abbreviated.to_string_lossy().to_string()
+        }
+
+        None => pwd.to_string_lossy().to_string(),
+    }
+}
+
+fn format_duration(duration: chrono::Duration) -> Result<String, Error> {
     let duration_ms = duration.num_milliseconds();
 
     if duration_ms < 0 {
@@ -472,10 +2084,1054 @@ fn format_duration(
         .replace(' ', ""))
 }
 
-fn format_command(command: &str, format: bool) -> String {
-    if format {
-        command.trim().to_string()
-    } else {
-        command.trim().replace('\n', "\\n")
+fn format_command(command: &str, format: bool, command_format: config::CommandFormat) -> String {
+    let separator = match command_format {
+        config::CommandFormat::Multiline => {
+            let command = escape_control_chars(command.trim());
+
+            return if format {
+                command
+            } else {
+                command.replace('\n', "\\n")
+            };
+        }
+        config::CommandFormat::Arrow => " ↵ ",
+        config::CommandFormat::Semicolon => "; ",
+    };
+
+    let lines: Vec<_> = command
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect();
+
+    escape_control_chars(&lines.join(separator))
+}
+
+/// Control characters like escape sequences of a pasted terminal recording
+/// would be interpreted by the terminal when printing the history so they are
+/// printed as `\x1b` instead. Newlines and tabs are kept.
+fn escape_control_chars(command: &str) -> String {
+    let escape = |c: char| c.is_control() && c != '\n' && c != '\t';
+
+    if !command.chars().any(escape) {
+        return command.to_string();
+    }
+
+    command
+        .chars()
+        .map(|c| {
+            if escape(c) {
+                format!("\\x{:02x}", u32::from(c))
+            } else {
+                c.to_string()
+            }
+        })
+        .collect()
+}
+
+fn read_command(mut reader: impl std::io::Read) -> Result<String, Error> {
+    let mut command = String::new();
+    reader
+        .read_to_string(&mut command)
+        .map_err(Error::ReadCommand)?;
+
+    Ok(command)
+}
+
+/// Removes control characters other than newlines and tabs from the command.
+fn strip_control_chars(command: &str) -> String {
+    command
+        .chars()
+        .filter(|c| !c.is_control() || *c == '\n' || *c == '\t')
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::StatsGroup;
+    use crate::{
+        clock::FixedClock,
+        config::CommandFormat,
+        entry::Entry,
+    };
+    use chrono::{
+        Duration,
+        Local,
+        TimeZone,
+        Utc,
+    };
+
+    #[test]
+    fn format_command_escapes_control_chars() {
+        let command = "printf '\x1b[31mred\x1b[0m'\r\n\tls";
+
+        assert_eq!(
+            super::format_command(command, true, CommandFormat::Multiline),
+            "printf '\\x1b[31mred\\x1b[0m'\\x0d\n\tls"
+        );
+        assert_eq!(
+            super::format_command(command, false, CommandFormat::Multiline),
+            "printf '\\x1b[31mred\\x1b[0m'\\x0d\\n\tls"
+        );
+        assert_eq!(
+            super::strip_control_chars(command),
+            "printf '[31mred[0m'\n\tls"
+        );
+    }
+
+    #[test]
+    fn format_command_single_line() {
+        let command = "for file in *; do\n    echo $file\r\n\ndone\n";
+
+        assert_eq!(
+            super::format_command(command, true, CommandFormat::Arrow),
+            "for file in *; do ↵ echo $file ↵ done"
+        );
+        assert_eq!(
+            super::format_command(command, false, CommandFormat::Semicolon),
+            "for file in *; do; echo $file; done"
+        );
+
+        let clock = FixedClock(Utc.ymd(2021, 6, 1).and_hms(12, 0, 0));
+        let entry = Entry::for_test_at("testhostname", command, clock.0);
+
+        let display = super::TableDisplay {
+            command_format: CommandFormat::Arrow,
+            ..super::TableDisplay::default()
+        };
+
+        let mut output = Vec::new();
+        super::render_entries(&mut output, &clock, &display, &[entry]).expect("can render entries");
+
+        // The header and one row.
+        assert_eq!(String::from_utf8_lossy(&output).lines().count(), 2);
+    }
+
+    #[test]
+    fn read_command() {
+        let command = " echo 'a b' \"$HOME\" | grep -v \\$\n";
+
+        assert_eq!(
+            super::read_command(command.as_bytes()).expect("can read command"),
+            command
+        );
+    }
+
+    #[test]
+    fn parse_column() {
+        use super::Column;
+
+        assert_eq!("time".parse::<Column>(), Ok(Column::Time));
+        assert_eq!("tmn".parse::<Column>(), Ok(Column::Time));
+        assert_eq!("res".parse::<Column>(), Ok(Column::Status));
+        assert_eq!("hist_event".parse::<Column>(), Ok(Column::HistEvent));
+
+        let err = "bogus".parse::<Column>().expect_err("unknown column");
+        assert!(err.contains("valid columns are: time, relative"), "{}", err);
+    }
+
+    #[test]
+    fn abbreviate_pwd() {
+        use std::path::{
+            Path,
+            PathBuf,
+        };
+
+        let abbreviate = |pwd: &str, home: &str, abbreviations: &[(PathBuf, String)]| {
+            super::abbreviate_pwd(Path::new(pwd), Path::new(home), abbreviations)
+        };
+
+        // Home directories that are not /home/<user>.
+        assert_eq!(abbreviate("/Users/name/src", "/Users/name", &[]), "~/src");
+        assert_eq!(abbreviate("/Users/name", "/Users/name", &[]), "~");
+        assert_eq!(
+            abbreviate("/Users/other", "/Users/name", &[]),
+            "/Users/other"
+        );
+        assert_eq!(
+            abbreviate(
+                "/srv/users/team/name/src/hstdb",
+                "/srv/users/team/name",
+                &[]
+            ),
+            "~/src/hstdb"
+        );
+        assert_eq!(
+            abbreviate("/srv/users/team", "/srv/users/team/name", &[]),
+            "/srv/users/team"
+        );
+
+        let abbreviations = vec![
+            (PathBuf::from("/home/user/projects"), "proj".to_string()),
+            (PathBuf::from("/mnt/data"), "data".to_string()),
+        ];
+
+        assert_eq!(
+            abbreviate(
+                "/home/user/projects/hstdb/src",
+                "/home/user",
+                &abbreviations
+            ),
+            "proj/hstdb/src"
+        );
+        assert_eq!(
+            abbreviate("/home/user/notes", "/home/user", &abbreviations),
+            "~/notes"
+        );
+        assert_eq!(
+            abbreviate("/mnt/data/x", "/home/user", &abbreviations),
+            "data/x"
+        );
+    }
+
+    #[test]
+    fn format_timestamp() {
+        let clock = FixedClock(Utc.ymd(2021, 6, 1).and_hms(12, 0, 0));
+        let display = super::TableDisplay::default();
+
+        let earlier_today = clock.0 - Duration::minutes(1);
+        assert_eq!(
+            super::format_timestamp(&clock, &display, earlier_today),
+            earlier_today
+                .with_timezone(&Local)
+                .format("%H:%M")
+                .to_string()
+        );
+
+        let days_ago = clock.0 - Duration::days(3);
+        assert_eq!(
+            super::format_timestamp(&clock, &display, days_ago),
+            days_ago
+                .with_timezone(&Local)
+                .format("%Y-%m-%d")
+                .to_string()
+        );
+
+        let display = super::TableDisplay {
+            timestamp_format: Some("%Y-%m-%d %H:%M:%S".to_string()),
+            ..super::TableDisplay::default()
+        };
+        assert_eq!(
+            super::format_timestamp(&clock, &display, earlier_today),
+            earlier_today
+                .with_timezone(&Local)
+                .format("%Y-%m-%d %H:%M:%S")
+                .to_string()
+        );
+    }
+
+    #[test]
+    fn format_relative() {
+        let clock = FixedClock(Utc.ymd(2021, 6, 1).and_hms(12, 0, 0));
+
+        let cases = vec![
+            (clock.0, "0s ago"),
+            (clock.0 - Duration::minutes(5), "5m ago"),
+            (
+                clock.0 - Duration::seconds(3 * 3600 + 20 * 60 + 5),
+                "3h20m ago",
+            ),
+            (clock.0 - Duration::days(3), "3days ago"),
+            (clock.0 + Duration::minutes(2), "in 2m"),
+        ];
+
+        for (timestamp, expected) in cases {
+            assert_eq!(super::format_relative(&clock, timestamp), expected);
+        }
+    }
+
+    #[test]
+    fn stats_counts() {
+        let entry = |command: &str, hostname: &str| Entry::for_test(hostname, command);
+
+        let entries = vec![
+            entry("git status", "a"),
+            entry("ls -la", "b"),
+            entry("git commit", "a"),
+            entry("cargo build", "b"),
+            entry("git push", "a"),
+            entry("ls", "a"),
+        ];
+
+        let got =
+            super::stats_counts(&entries, StatsGroup::Command, &[]).expect("can count entries");
+        assert_eq!(
+            got,
+            vec![
+                ("git".to_string(), 3),
+                ("ls".to_string(), 2),
+                ("cargo".to_string(), 1),
+            ]
+        );
+
+        let got =
+            super::stats_counts(&entries, StatsGroup::Hostname, &[]).expect("can count entries");
+        assert_eq!(got, vec![("a".to_string(), 4), ("b".to_string(), 2)]);
+    }
+
+    #[test]
+    fn stats_counts_strip_prefixes() {
+        let entry = |command: &str| Entry::for_test("testhostname", command);
+
+        let entries = vec![
+            entry("sudo apt update"),
+            entry("apt upgrade"),
+            entry("sudo time apt install"),
+            entry("sudoedit /etc/hosts"),
+            entry("sudo"),
+        ];
+
+        let prefixes = vec!["sudo".to_string(), "time ".to_string()];
+        let got = super::stats_counts(&entries, StatsGroup::Command, &prefixes)
+            .expect("can count entries");
+
+        assert_eq!(
+            got,
+            vec![
+                ("apt".to_string(), 3),
+                ("sudo".to_string(), 1),
+                ("sudoedit".to_string(), 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn stats_compare() {
+        let now = Utc.ymd(2021, 6, 8).and_hms(12, 0, 0);
+        let entry = |command: &str, days_ago| {
+            Entry::for_test_at(
+                "testhostname",
+                command,
+                now - Duration::days(days_ago) + Duration::hours(1),
+            )
+        };
+
+        let entries = vec![
+            entry("git status", 20),
+            entry("git log", 10),
+            entry("ls", 9),
+            entry("make", 8),
+            entry("git commit", 3),
+            entry("git push", 2),
+            entry("cargo build", 1),
+            entry("ls -la", 1),
+        ];
+
+        let got = super::stats_compare(&entries, StatsGroup::Command, &[], now, Duration::days(7))
+            .expect("can compare entries");
+
+        assert_eq!(
+            got,
+            vec![
+                ("git".to_string(), 2, 1),
+                ("cargo".to_string(), 1, 1),
+                ("ls".to_string(), 1, 0),
+                ("make".to_string(), 0, -1),
+            ]
+        );
+    }
+
+    #[test]
+    fn confirm_entries_non_tty() {
+        let mut output = Vec::new();
+        let confirmed = super::confirm_entries(50_000, 1000, false, std::io::empty(), &mut output)
+            .expect("can confirm entries");
+
+        assert!(confirmed);
+        assert_eq!(String::from_utf8_lossy(&output), "50000 matching entries\n");
+
+        let mut output = Vec::new();
+        let confirmed = super::confirm_entries(50_000, 1000, true, &b"n\n"[..], &mut output)
+            .expect("can confirm entries");
+
+        assert!(!confirmed);
+    }
+
+    #[test]
+    fn write_json_entries() {
+        let entry = Entry {
+            time_finished: Utc.ymd(2021, 6, 1).and_hms(12, 0, 1),
+            time_start: Utc.ymd(2021, 6, 1).and_hms(12, 0, 0),
+            session_id: uuid::Uuid::nil(),
+            hist_event: Some(42),
+            ..Entry::for_test("testhostname", "echo \"test\"")
+        };
+
+        let mut output = Vec::new();
+        super::write_json_entries(&mut output, &[entry.clone(), entry])
+            .expect("can write json entries");
+
+        let got: serde_json::Value = serde_json::from_slice(&output).expect("output is valid json");
+        let entries = got.as_array().expect("output is a json array");
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0]["time_finished"], "2021-06-01T12:00:01Z");
+        assert_eq!(
+            entries[0]["session_id"],
+            "00000000-0000-0000-0000-000000000000"
+        );
+        assert_eq!(entries[0]["command"], "echo \"test\"");
+        assert_eq!(entries[0]["hist_event"], 42);
+
+        let mut output = Vec::new();
+        super::write_json_entries(&mut output, &[]).expect("can write json entries");
+        assert_eq!(String::from_utf8_lossy(&output), "[\n]\n");
+    }
+
+    #[test]
+    fn write_ndjson_entries() {
+        let entry = |command: &str| Entry {
+            time_finished: Utc.ymd(2021, 6, 1).and_hms(12, 0, 1),
+            time_start: Utc.ymd(2021, 6, 1).and_hms(12, 0, 0),
+            session_id: uuid::Uuid::nil(),
+            ..Entry::for_test("testhostname", command)
+        };
+
+        let mut output = Vec::new();
+        super::write_ndjson_entries(&mut output, &[entry("ls"), entry("echo 'a\nb'")])
+            .expect("can write ndjson entries");
+
+        let output = String::from_utf8_lossy(&output);
+        let lines: Vec<_> = output.lines().collect();
+        assert_eq!(lines.len(), 2);
+
+        for (line, command) in lines.into_iter().zip(&["ls", "echo 'a\nb'"]) {
+            let got: serde_json::Value = serde_json::from_str(line).expect("line is valid json");
+
+            assert_eq!(got["command"], *command);
+            assert_eq!(got["time_start"], "2021-06-01T12:00:00Z");
+            assert_eq!(got["session_id"], "00000000-0000-0000-0000-000000000000");
+        }
+    }
+
+    #[test]
+    fn render_entries() {
+        let clock = FixedClock(Utc.ymd(2021, 6, 1).and_hms(12, 0, 0));
+        let entry = Entry {
+            session_id: uuid::Uuid::nil(),
+            ..Entry::for_test_at("testhostname", "echo 'a\nb'", clock.0 - Duration::days(3))
+        };
+        let tmn = entry
+            .time_finished
+            .with_timezone(&Local)
+            .format("%Y-%m-%d")
+            .to_string();
+
+        let display = super::TableDisplay {
+            output: super::OutputFormat::Plain,
+            columns: vec![
+                super::Column::Time,
+                super::Column::Host,
+                super::Column::Command,
+            ],
+            ..super::TableDisplay::default()
+        };
+
+        let mut output = Vec::new();
+        super::render_entries(&mut output, &clock, &display, std::slice::from_ref(&entry))
+            .expect("can render entries");
+
+        assert_eq!(
+            String::from_utf8_lossy(&output),
+            format!("tmn\thost\tcmd\n{tmn}\ttesthostname\techo 'a\\nb'\n")
+        );
+
+        let mut output = Vec::new();
+        super::render_entries(
+            &mut output,
+            &clock,
+            &super::TableDisplay::default(),
+            &[entry],
+        )
+        .expect("can render entries");
+
+        let output = String::from_utf8_lossy(&output);
+        assert!(output.contains("tmn"), "{}", output);
+        assert!(output.contains(&tmn), "{}", output);
+        assert!(output.contains("echo 'a"), "{}", output);
+    }
+
+    #[test]
+    fn init_script() {
+        use super::InitShell;
+
+        let zsh = super::init_script(InitShell::Zsh, true);
+        assert!(zsh.contains("add-zsh-hook precmd hstdb-precmd"));
+        assert!(zsh.contains("zle -N hstdb-search-widget"));
+        assert!(zsh.contains("bindkey '^X^R' hstdb-search-widget"));
+
+        let fish = super::init_script(InitShell::Fish, true);
+        assert!(fish.contains("bind \\cx\\cr hstdb-search-widget"));
+
+        let zsh = super::init_script(InitShell::Zsh, false);
+        assert!(!zsh.contains("hstdb-search-widget"));
+    }
+
+    #[test]
+    fn duplicate_sessions() {
+        let entry = |hostname: &str, session_id| Entry {
+            session_id,
+            ..Entry::for_test(hostname, "ls")
+        };
+
+        let shared = uuid::Uuid::new_v4();
+        let single = uuid::Uuid::new_v4();
+
+        let entries = vec![
+            entry("laptop", shared),
+            entry("laptop", single),
+            entry("server", shared),
+            entry("laptop", single),
+            entry("laptop", shared),
+        ];
+
+        assert_eq!(
+            super::duplicate_sessions(&entries),
+            vec![(shared, vec!["laptop".to_string(), "server".to_string()])]
+        );
+    }
+
+    #[test]
+    fn render_entries_reverse() {
+        let clock = FixedClock(Utc.ymd(2021, 6, 1).and_hms(12, 0, 0));
+        let entry = |command: &str, days_ago| Entry {
+            session_id: uuid::Uuid::nil(),
+            ..Entry::for_test_at("testhostname", command, clock.0 - Duration::days(days_ago))
+        };
+
+        let display = super::TableDisplay {
+            output: super::OutputFormat::Ndjson,
+            reverse: true,
+            ..super::TableDisplay::default()
+        };
+
+        let mut output = Vec::new();
+        super::render_entries(
+            &mut output,
+            &clock,
+            &display,
+            &[entry("older", 2), entry("newer", 1)],
+        )
+        .expect("can render entries");
+
+        let commands: Vec<String> = String::from_utf8_lossy(&output)
+            .lines()
+            .map(|line| {
+                serde_json::from_str::<Entry>(line)
+                    .expect("valid entry")
+                    .command
+            })
+            .collect();
+
+        assert_eq!(commands, vec!["newer", "older"]);
+    }
+
+    #[test]
+    fn render_entries_group_sessions() {
+        let clock = FixedClock(Utc.ymd(2021, 6, 1).and_hms(12, 0, 0));
+        let first = uuid::Uuid::new_v4();
+        let second = uuid::Uuid::new_v4();
+        let entry = |command: &str, session_id, minutes_ago| Entry {
+            session_id,
+            ..Entry::for_test_at(
+                "testhostname",
+                command,
+                clock.0 - Duration::minutes(minutes_ago),
+            )
+        };
+
+        let display = super::TableDisplay {
+            output: super::OutputFormat::Plain,
+            header: super::Display::Hide,
+            columns: vec![super::Column::Command],
+            group_sessions: true,
+            ..super::TableDisplay::default()
+        };
+
+        let mut output = Vec::new();
+        super::render_entries(
+            &mut output,
+            &clock,
+            &display,
+            &[
+                entry("a", first, 4),
+                entry("b", first, 3),
+                entry("c", second, 2),
+                entry("d", first, 1),
+            ],
+        )
+        .expect("can render entries");
+
+        assert_eq!(String::from_utf8_lossy(&output), "a\nb\n\nc\n\nd\n");
+    }
+
+    #[test]
+    fn render_entries_separators() {
+        let clock = FixedClock(Utc.ymd(2021, 6, 1).and_hms(12, 0, 0));
+        let entry = |command: &str| Entry::for_test_at("testhostname", command, clock.0);
+
+        let render = |field_separator: &str, record_separator: &str| {
+            let display = super::TableDisplay {
+                output: super::OutputFormat::Plain,
+                columns: vec![super::Column::Host, super::Column::Command],
+                field_separator: field_separator.to_string(),
+                record_separator: record_separator.to_string(),
+                ..super::TableDisplay::default()
+            };
+
+            let mut output = Vec::new();
+            super::render_entries(
+                &mut output,
+                &clock,
+                &display,
+                &[entry("ls\t-l"), entry("echo foo")],
+            )
+            .expect("can render entries");
+
+            String::from_utf8(output).expect("output is utf8")
+        };
+
+        assert_eq!(
+            render(",", "\n"),
+            "host,cmd\ntesthostname,ls\t-l\ntesthostname,echo foo\n"
+        );
+        assert_eq!(
+            render("\0", "\0"),
+            "host\0cmd\0testhostname\0ls\t-l\0testhostname\0echo foo\0"
+        );
+
+        // Line breaks are only escaped when they separate the rows.
+        let render = |record_separator: &str| {
+            let display = super::TableDisplay {
+                output: super::OutputFormat::Plain,
+                columns: vec![super::Column::Command],
+                record_separator: record_separator.to_string(),
+                ..super::TableDisplay::default()
+            };
+
+            let mut output = Vec::new();
+            super::render_entries(&mut output, &clock, &display, &[entry("echo a\necho b")])
+                .expect("can render entries");
+
+            String::from_utf8(output).expect("output is utf8")
+        };
+
+        assert_eq!(render("\n"), "cmd\necho a\\necho b\n");
+        assert_eq!(render("\0"), "cmd\0echo a\necho b\0");
+    }
+
+    #[test]
+    fn default_output_file() {
+        let data_dir = tempfile::tempdir().expect("can create data dir");
+        let output_dir = tempfile::tempdir().expect("can create output dir");
+        let output = output_dir.path().join("last.json");
+        let store = crate::store::new(data_dir.path().to_path_buf());
+        let clock = FixedClock(Utc::now());
+
+        store
+            .add_entry(&Entry::for_test_at("laptop", "cargo test", clock.0))
+            .expect("can add entry");
+
+        let filter = crate::store::Filter::default()
+            .hostnames(Vec::new(), true)
+            .expect("can set hostnames");
+        let display = super::TableDisplay {
+            output: super::OutputFormat::Json,
+            ..super::TableDisplay::default()
+        };
+
+        super::default(
+            &clock,
+            &filter,
+            &display,
+            data_dir.path().to_path_buf(),
+            crate::store::Backend::Csv,
+            None,
+            Some(&output),
+        )
+        .expect("can write output file");
+
+        let written: Vec<Entry> =
+            serde_json::from_str(&std::fs::read_to_string(&output).expect("can read output"))
+                .expect("output is a json array");
+
+        assert_eq!(written.len(), 1);
+        assert_eq!(written[0].command, "cargo test");
+    }
+
+    #[test]
+    fn export_csv() {
+        let data_dir = tempfile::tempdir().expect("can create data dir");
+        let output = data_dir.path().join("export.csv");
+        let store = crate::store::new(data_dir.path().to_path_buf());
+
+        let now = Utc::now();
+        let entry = |hostname: &str, command: &str, minutes_ago| {
+            Entry::for_test_at(hostname, command, now - Duration::minutes(minutes_ago))
+        };
+
+        store
+            .add_entry(&entry("laptop", "first", 3))
+            .expect("can add entry");
+        store
+            .add_entry(&entry("laptop", "third", 1))
+            .expect("can add entry");
+        store
+            .add_entry(&entry("server", "second", 2))
+            .expect("can add entry");
+
+        let filter = crate::store::Filter::default()
+            .hostnames(Vec::new(), true)
+            .expect("can set hostnames");
+
+        super::export(
+            &filter,
+            data_dir.path().to_path_buf(),
+            crate::store::Backend::Csv,
+            super::ExportFormat::Csv,
+            &output,
+        )
+        .expect("can export entries");
+
+        let exported = std::fs::read_to_string(&output).expect("can read export");
+        assert_eq!(
+            exported
+                .lines()
+                .filter(|line| line.starts_with("time_finished"))
+                .count(),
+            1
+        );
+
+        let commands: Vec<String> = csv::Reader::from_path(&output)
+            .expect("can open export")
+            .deserialize::<Entry>()
+            .map(|entry| entry.expect("valid entry").command)
+            .collect();
+
+        assert_eq!(commands, vec!["first", "second", "third"]);
+    }
+
+    #[test]
+    fn render_entries_ndjson_stream() {
+        /// Remembers what was written up to every flush.
+        #[derive(Default)]
+        struct FlushRecorder {
+            written: Vec<u8>,
+            flushed: Vec<String>,
+        }
+
+        impl std::io::Write for FlushRecorder {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.written.extend_from_slice(buf);
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                self.flushed
+                    .push(String::from_utf8_lossy(&self.written).to_string());
+                Ok(())
+            }
+        }
+
+        let clock = FixedClock(Utc.ymd(2021, 6, 1).and_hms(12, 0, 0));
+        let entry = |command: &str| Entry {
+            session_id: uuid::Uuid::nil(),
+            ..Entry::for_test_at("testhostname", command, clock.0)
+        };
+
+        let display = super::TableDisplay {
+            output: super::OutputFormat::NdjsonStream,
+            ..super::TableDisplay::default()
+        };
+
+        let mut recorder = FlushRecorder::default();
+        {
+            // Lines have to get through a buffer like the one around stdout.
+            let mut handle = std::io::BufWriter::new(&mut recorder);
+            super::render_entries(&mut handle, &clock, &display, &[entry("a"), entry("b")])
+                .expect("can render entries");
+
+            assert!(handle.buffer().is_empty());
+        }
+
+        let lines: Vec<Vec<String>> = recorder
+            .flushed
+            .iter()
+            .map(|flushed| {
+                flushed
+                    .lines()
+                    .map(|line| {
+                        serde_json::from_str::<Entry>(line)
+                            .expect("valid entry")
+                            .command
+                    })
+                    .collect()
+            })
+            .collect();
+
+        assert_eq!(lines, vec![vec!["a"], vec!["a", "b"]]);
+    }
+
+    #[test]
+    fn page_entries() {
+        let now = Utc.ymd(2021, 6, 1).and_hms(12, 0, 0);
+        let session_id = uuid::Uuid::new_v4();
+        let entry = |command: &str, minutes_ago| Entry {
+            session_id,
+            ..Entry::for_test_at(
+                "testhostname",
+                command,
+                now - Duration::minutes(minutes_ago),
+            )
+        };
+
+        let mut entries = vec![
+            entry("a", 5),
+            entry("b", 4),
+            entry("c", 3),
+            entry("d", 2),
+            entry("e", 1),
+        ];
+
+        // Entries of different sessions can finish at the same time.
+        let mut tie = entry("f", 2);
+        tie.session_id = uuid::Uuid::nil();
+        entries.push(tie);
+
+        let commands = |page: &super::Page| {
+            page.entries
+                .iter()
+                .map(|entry| entry.command.clone())
+                .collect::<Vec<_>>()
+        };
+
+        let first = super::page_entries(entries.clone(), 3, None);
+        assert_eq!(commands(&first), vec!["e", "d", "f"]);
+
+        let cursor: super::Cursor = first
+            .next_cursor
+            .as_deref()
+            .expect("has next cursor")
+            .parse()
+            .expect("valid cursor");
+
+        let second = super::page_entries(entries, 3, Some(cursor));
+        assert_eq!(commands(&second), vec!["c", "b", "a"]);
+        assert_eq!(second.next_cursor, None);
+
+        assert!("not a cursor".parse::<super::Cursor>().is_err());
+    }
+
+    #[test]
+    fn page_entries_same_key() {
+        let now = Utc.ymd(2021, 6, 1).and_hms(12, 0, 0);
+        let session_id = uuid::Uuid::new_v4();
+
+        // Imported entries have no sequence number so the entries of a
+        // session that finished in the same second share the whole key.
+        let entry = |command: &str| Entry {
+            session_id,
+            ..Entry::for_test_at("testhostname", command, now)
+        };
+
+        let entries = vec![
+            entry("a"),
+            entry("b"),
+            entry("c"),
+            entry("d"),
+            entry("d"),
+            entry("e"),
+            Entry {
+                time_finished: now - Duration::minutes(1),
+                ..entry("older")
+            },
+        ];
+
+        let mut commands = Vec::new();
+        let mut cursor = None;
+        loop {
+            let page = super::page_entries(entries.clone(), 2, cursor);
+            commands.extend(page.entries.iter().map(|entry| entry.command.clone()));
+
+            match page.next_cursor {
+                Some(next) => cursor = Some(next.parse().expect("valid cursor")),
+                None => break,
+            }
+        }
+
+        assert_eq!(commands, vec!["e", "d", "d", "c", "b", "a", "older"]);
+    }
+
+    #[test]
+    fn cursor_far_future() {
+        let cursor = super::Cursor {
+            time_finished: Utc.ymd(2300, 1, 1).and_hms_nano(0, 0, 0, 5),
+            session_id: uuid::Uuid::nil(),
+            seq: 3,
+            returned: 1,
+        };
+
+        assert_eq!(
+            cursor
+                .to_string()
+                .parse::<super::Cursor>()
+                .expect("valid cursor"),
+            cursor
+        );
+    }
+
+    #[test]
+    fn render_last() {
+        let clock = FixedClock(Utc.ymd(2021, 6, 1).and_hms(12, 0, 0));
+        let entry = |command: &str, result| Entry {
+            time_finished: clock.0,
+            time_start: clock.0 - Duration::seconds(3),
+            result,
+            session_id: uuid::Uuid::nil(),
+            duration_ms: Some(3000),
+            ..Entry::for_test("testhostname", command)
+        };
+        let entries = vec![entry("true", 0), entry("false", 1)];
+
+        let display = super::TableDisplay {
+            output: super::OutputFormat::Json,
+            ..super::TableDisplay::default()
+        };
+
+        let mut output = Vec::new();
+        super::render_last(&mut output, &clock, &display, &entries).expect("can render entry");
+
+        let got: Entry = serde_json::from_slice(&output).expect("single json object");
+        assert_eq!(got, entries[1]);
+
+        let display = super::TableDisplay {
+            output: super::OutputFormat::Plain,
+            header: super::Display::Hide,
+            columns: vec![super::Column::Status, super::Column::Command],
+            ..super::TableDisplay::default()
+        };
+
+        let mut output = Vec::new();
+        super::render_last(&mut output, &clock, &display, &entries).expect("can render entry");
+        assert_eq!(String::from_utf8_lossy(&output), "1\tfalse\n");
+
+        assert!(matches!(
+            super::render_last(&mut Vec::new(), &clock, &display, &[]),
+            Err(super::Error::NoEntries)
+        ));
+    }
+
+    #[test]
+    fn add_history_disabled() {
+        let clock = FixedClock(Utc::now());
+        let config = crate::config::Config::default();
+        let socket_dir = tempfile::tempdir().expect("can create socket dir");
+        let socket_path = socket_dir.path().join("server_socket");
+
+        let add_history = |disable| {
+            super::add_history(
+                &config,
+                &clock,
+                "ls".to_string(),
+                socket_path.clone(),
+                crate::message::vars_for_test(&[("HISTDB_RS_DISABLE", disable)]),
+            )
+        };
+
+        // Nothing is sent so it does not matter that no server is running.
+        let disabled = add_history("1");
+        let enabled = add_history("0");
+
+        assert!(disabled.is_ok());
+        assert!(matches!(enabled, Err(super::Error::Client(_))));
+    }
+
+    #[test]
+    fn status_color() {
+        assert_eq!(super::status_color(0), comfy_table::Color::Green);
+        assert_eq!(super::status_color(1), comfy_table::Color::Red);
+        assert_eq!(super::status_color(130), comfy_table::Color::Red);
+    }
+
+    #[test]
+    fn maintenance_sqlite_backend() {
+        let data_dir = tempfile::tempdir().expect("can create data dir");
+        let socket_path = data_dir.path().join("server_socket");
+        let mut config = crate::config::Config::default();
+        config.backend = crate::store::Backend::Sqlite;
+
+        let clock = crate::clock::FixedClock(Utc::now());
+        let older_than = std::time::Duration::from_secs(60);
+
+        assert!(matches!(
+            super::gc(
+                &config,
+                &clock,
+                data_dir.path().to_path_buf(),
+                &socket_path,
+                older_than
+            ),
+            Err(super::Error::NotSupportedForSqlite("gc"))
+        ));
+        assert!(matches!(
+            super::prune(
+                &config,
+                &clock,
+                data_dir.path().to_path_buf(),
+                &socket_path,
+                Some(older_than),
+                None,
+                false
+            ),
+            Err(super::Error::NotSupportedForSqlite("prune"))
+        ));
+        assert!(matches!(
+            super::check(
+                &config,
+                data_dir.path().to_path_buf(),
+                &socket_path,
+                false,
+                true,
+                false
+            ),
+            Err(super::Error::NotSupportedForSqlite("check --rows"))
+        ));
+    }
+
+    #[test]
+    fn maintenance_server_running() {
+        let data_dir = tempfile::tempdir().expect("can create data dir");
+        let socket_path = data_dir.path().join("server_socket");
+        let config = crate::config::Config::default();
+        let clock = crate::clock::FixedClock(Utc::now());
+
+        let store = crate::store::new(data_dir.path().to_path_buf());
+        store
+            .add_entry(&Entry {
+                deleted: true,
+                time_finished: clock.0 - Duration::days(60),
+                ..Entry::for_test("testhostname", "ls")
+            })
+            .expect("can add entry");
+
+        let gc = || {
+            super::gc(
+                &config,
+                &clock,
+                data_dir.path().to_path_buf(),
+                &socket_path,
+                std::time::Duration::from_secs(60),
+            )
+        };
+
+        let server = std::os::unix::net::UnixDatagram::bind(&socket_path).expect("can bind socket");
+        assert!(matches!(gc(), Err(super::Error::ServerRunning(_))));
+        assert!(data_dir.path().join("testhostname.csv").exists());
+
+        drop(server);
+        std::fs::remove_file(&socket_path).expect("can remove socket");
+        gc().expect("can remove deleted entries");
+        assert!(!data_dir.path().join("testhostname.csv").exists());
     }
 }
@@ -1,16 +1,38 @@
 use super::{
+    commit::Committer,
     db,
     Server,
 };
-use crate::store;
+use crate::{
+    store,
+    store::{
+        git::CommitMode,
+        Backend,
+        StorageKey,
+    },
+};
 use crossbeam_utils::sync::WaitGroup;
+use log::{
+    info,
+    warn,
+};
+use socket2::SockRef;
 use std::{
-    os::unix::net::UnixDatagram,
-    path::PathBuf,
+    io::ErrorKind,
+    net::TcpListener,
+    os::unix::{
+        fs::FileTypeExt,
+        net::UnixDatagram,
+    },
+    path::{
+        Path,
+        PathBuf,
+    },
     sync::{
         atomic::AtomicBool,
         Arc,
     },
+    time::Duration,
 };
 use thiserror::Error;
 
@@ -25,26 +47,158 @@ pub enum Error {
     #[error("can not bind to socket: {0}")]
     BindSocket(std::io::Error),
 
+    #[error("server is already running on socket {0:?}")]
+    ServerRunning(PathBuf),
+
+    #[error("can not remove stale socket {0:?}: {1}")]
+    RemoveStaleSocket(PathBuf, std::io::Error),
+
+    #[error("can not set socket receive buffer size: {0}")]
+    SetSocketRecvBuffer(std::io::Error),
+
+    #[error("can not get socket receive buffer size: {0}")]
+    GetSocketRecvBuffer(std::io::Error),
+
     #[error("{0}")]
     Db(#[from] db::Error),
+
+    #[error("can not open store: {0}")]
+    OpenStore(store::Error),
+
+    #[cfg(feature = "tcp-transport")]
+    #[error("can not listen on {0}: {1}")]
+    BindTcpListener(String, std::io::Error),
+
+    #[cfg(not(feature = "tcp-transport"))]
+    #[error("tcp transport is not enabled in this build")]
+    TcpTransportDisabled,
 }
 
+#[derive(Debug)]
 pub struct Builder {
     pub(super) cache_dir: PathBuf,
     pub(super) data_dir: PathBuf,
     pub(super) socket: PathBuf,
     pub(super) handle_ctrlc: bool,
+    pub(super) socket_recv_buffer_bytes: Option<usize>,
+    pub(super) backend: Backend,
+    pub(super) storage_key: StorageKey,
+    pub(super) split_compound_commands: bool,
+    pub(super) processor_queue_size: usize,
+    pub(super) git_commit_mode: CommitMode,
+    pub(super) git_commit_batch_entries: usize,
+    pub(super) git_commit_batch_interval: Duration,
+    pub(super) listen: Option<String>,
+    pub(super) auth_token: Option<String>,
 }
 
 impl Builder {
+    /// Size of the kernel receive buffer (`SO_RCVBUF`) of the server socket.
+    /// When unset the system default is used.
+    pub fn socket_recv_buffer_bytes(self, socket_recv_buffer_bytes: Option<usize>) -> Self {
+        Self {
+            socket_recv_buffer_bytes,
+            ..self
+        }
+    }
+
+    /// Decides where the server writes new entries.
+    pub fn backend(self, backend: Backend) -> Self {
+        Self { backend, ..self }
+    }
+
+    /// Decides in which file the server writes new entries.
+    pub fn storage_key(self, storage_key: StorageKey) -> Self {
+        Self {
+            storage_key,
+            ..self
+        }
+    }
+
+    /// Store every command of a compound command line like `cmd1 && cmd2` as
+    /// its own entry.
+    pub fn split_compound_commands(self, split_compound_commands: bool) -> Self {
+        Self {
+            split_compound_commands,
+            ..self
+        }
+    }
+
+    /// How many received messages can wait for being processed. When the queue
+    /// is full the server stops reading from the socket which makes the
+    /// shell hooks wait. 0 makes the queue unbounded.
+    pub fn processor_queue_size(self, processor_queue_size: usize) -> Self {
+        Self {
+            processor_queue_size,
+            ..self
+        }
+    }
+
+    /// Decides when the server commits the history files to git.
+    pub fn git_commit_mode(self, git_commit_mode: CommitMode) -> Self {
+        Self {
+            git_commit_mode,
+            ..self
+        }
+    }
+
+    /// With `CommitMode::Batched` the server commits once this many entries
+    /// were added or the oldest uncommitted entry is older than the interval.
+    pub fn git_commit_batch(
+        self,
+        git_commit_batch_entries: usize,
+        git_commit_batch_interval: Duration,
+    ) -> Self {
+        Self {
+            git_commit_batch_entries,
+            git_commit_batch_interval,
+            ..self
+        }
+    }
+
+    /// Also accept messages on this TCP address, e.g. `0.0.0.0:9999`. Without
+    /// an auth token anyone who can reach the address can add entries to the
+    /// history.
+    pub fn listen(self, listen: Option<String>) -> Self {
+        Self { listen, ..self }
+    }
+
+    /// Only process messages that carry this token. Without a token every
+    /// message is processed.
+    pub fn auth_token(self, auth_token: Option<String>) -> Self {
+        Self { auth_token, ..self }
+    }
+
     pub fn build(self) -> Result<Server, Error> {
         let db = db::new(self.cache_dir)?;
 
         let socket_path_parent = self.socket.parent().ok_or(Error::NoSocketPathParent)?;
         std::fs::create_dir_all(socket_path_parent).map_err(Error::CreateSocketPathParent)?;
-        let socket = UnixDatagram::bind(&self.socket).map_err(Error::BindSocket)?;
+        let socket = bind_socket(&self.socket)?;
+
+        let socket_ref = SockRef::from(&socket);
+        if let Some(size) = self.socket_recv_buffer_bytes {
+            socket_ref
+                .set_recv_buffer_size(size)
+                .map_err(Error::SetSocketRecvBuffer)?;
+        }
+
+        let recv_buffer_size = socket_ref
+            .recv_buffer_size()
+            .map_err(Error::GetSocketRecvBuffer)?;
+        info!("socket receive buffer size is {} bytes", recv_buffer_size);
+
+        let tcp_listener = self.listen.as_deref().map(bind_tcp_listener).transpose()?;
 
-        let store = store::new(self.data_dir);
+        let committer = Committer::new(
+            self.data_dir.clone(),
+            self.git_commit_mode,
+            self.git_commit_batch_entries,
+            self.git_commit_batch_interval,
+        );
+
+        let store =
+            store::open(self.data_dir, self.backend, self.storage_key).map_err(Error::OpenStore)?;
 
         let stopping = Arc::new(AtomicBool::new(false));
         let wait_group = WaitGroup::new();
@@ -55,10 +209,129 @@ impl Builder {
             db,
             socket,
             socket_path: self.socket,
+            tcp_listener,
             store,
+            committer,
             stopping,
             wait_group,
             handle_ctrlc,
+            split_compound_commands: self.split_compound_commands,
+            processor_queue_size: self.processor_queue_size,
+            auth_token: self.auth_token,
         })
     }
 }
+
+/// Binds the server socket. A socket file left behind by a server that did not
+/// exit cleanly is removed when no server answers on it anymore.
+fn bind_socket(path: &Path) -> Result<UnixDatagram, Error> {
+    let err = match UnixDatagram::bind(path) {
+        Ok(socket) => return Ok(socket),
+        Err(err) => err,
+    };
+
+    if err.kind() != ErrorKind::AddrInUse {
+        return Err(Error::BindSocket(err));
+    }
+
+    let is_socket =
+        std::fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket());
+
+    if !is_socket {
+        return Err(Error::BindSocket(err));
+    }
+
+    let probe = UnixDatagram::unbound().map_err(Error::BindSocket)?;
+    if probe.connect(path).is_ok() {
+        return Err(Error::ServerRunning(path.to_path_buf()));
+    }
+
+    warn!(
+        "removing stale socket {:?} of a server that is not running",
+        path
+    );
+    std::fs::remove_file(path).map_err(|err| Error::RemoveStaleSocket(path.to_path_buf(), err))?;
+
+    UnixDatagram::bind(path).map_err(Error::BindSocket)
+}
+
+#[cfg(feature = "tcp-transport")]
+fn bind_tcp_listener(address: &str) -> Result<TcpListener, Error> {
+    TcpListener::bind(address).map_err(|err| Error::BindTcpListener(address.to_string(), err))
+}
+
+#[cfg(not(feature = "tcp-transport"))]
+fn bind_tcp_listener(_address: &str) -> Result<TcpListener, Error> {
+    Err(Error::TcpTransportDisabled)
+}
+
+#[cfg(test)]
+mod test {
+    use socket2::SockRef;
+
+    #[test]
+    fn socket_recv_buffer_bytes() {
+        let cache_dir = tempfile::tempdir().expect("can create cache dir");
+        let data_dir = tempfile::tempdir().expect("can create data dir");
+        let socket_dir = tempfile::tempdir().expect("can create socket dir");
+
+        let default =
+            SockRef::from(&std::os::unix::net::UnixDatagram::unbound().expect("can create socket"))
+                .recv_buffer_size()
+                .expect("can get default receive buffer size");
+
+        let requested = default * 2;
+
+        let server = super::super::builder(
+            cache_dir.path().to_path_buf(),
+            data_dir.path().to_path_buf(),
+            socket_dir.path().join("server_socket"),
+            false,
+        )
+        .socket_recv_buffer_bytes(Some(requested))
+        .build()
+        .expect("can build server");
+
+        let got = SockRef::from(&server.socket)
+            .recv_buffer_size()
+            .expect("can get receive buffer size");
+
+        // The kernel is free to adjust the requested size (linux doubles it
+        // for bookkeeping and caps it at net.core.rmem_max) so we can only
+        // check that it grew beyond the default.
+        assert!(got > default, "got {got} with default {default}");
+    }
+
+    #[test]
+    fn stale_socket() {
+        let cache_dir = tempfile::tempdir().expect("can create cache dir");
+        let data_dir = tempfile::tempdir().expect("can create data dir");
+        let socket_dir = tempfile::tempdir().expect("can create socket dir");
+        let socket = socket_dir.path().join("server_socket");
+
+        // Dropping the socket leaves the file behind like a crashed server.
+        drop(std::os::unix::net::UnixDatagram::bind(&socket).expect("can bind socket"));
+        assert!(socket.exists());
+
+        let _server = super::super::builder(
+            cache_dir.path().to_path_buf(),
+            data_dir.path().to_path_buf(),
+            socket.clone(),
+            false,
+        )
+        .build()
+        .expect("can build server on stale socket");
+
+        let other_cache_dir = tempfile::tempdir().expect("can create cache dir");
+        let err = super::super::builder(
+            other_cache_dir.path().to_path_buf(),
+            data_dir.path().to_path_buf(),
+            socket,
+            false,
+        )
+        .build()
+        .expect_err("can not build second server");
+
+        assert!(matches!(err, super::Error::ServerRunning(_)), "{err:?}");
+    }
+}
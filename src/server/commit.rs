@@ -0,0 +1,176 @@
+use crate::store::git::{
+    self,
+    CommitMode,
+};
+use std::{
+    path::PathBuf,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+/// Commits the history files of the data dir after entries were added
+/// according to the commit mode.
+#[derive(Debug)]
+pub struct Committer {
+    data_dir: PathBuf,
+    mode: CommitMode,
+    batch_entries: usize,
+    batch_interval: Duration,
+    pending: usize,
+    first_pending: Option<Instant>,
+}
+
+impl Committer {
+    pub const fn new(
+        data_dir: PathBuf,
+        mode: CommitMode,
+        batch_entries: usize,
+        batch_interval: Duration,
+    ) -> Self {
+        Self {
+            data_dir,
+            mode,
+            batch_entries,
+            batch_interval,
+            pending: 0,
+            first_pending: None,
+        }
+    }
+
+    /// Has to be called after a finished command was added to the store.
+    pub fn entry_added(&mut self) -> Result<(), git::Error> {
+        match self.mode {
+            CommitMode::Off => Ok(()),
+            CommitMode::Individual => {
+                self.pending += 1;
+                self.commit()
+            }
+            CommitMode::Batched => {
+                self.pending += 1;
+                self.first_pending.get_or_insert_with(Instant::now);
+
+                if self.pending >= self.batch_entries {
+                    self.commit()
+                } else {
+                    Ok(())
+                }
+            }
+        }
+    }
+
+    /// How long to wait for new messages before the pending batch is due.
+    /// Returns none when nothing is pending.
+    pub fn wait_timeout(&self) -> Option<Duration> {
+        self.first_pending
+            .map(|first_pending| self.batch_interval.saturating_sub(first_pending.elapsed()))
+    }
+
+    /// Commits the pending batch if its oldest entry waited long enough.
+    pub fn commit_if_due(&mut self) -> Result<(), git::Error> {
+        match self.first_pending {
+            Some(first_pending) if first_pending.elapsed() >= self.batch_interval => self.commit(),
+            _ => Ok(()),
+        }
+    }
+
+    /// Commits all pending entries. Used when the server stops.
+    pub fn commit_pending(&mut self) -> Result<(), git::Error> {
+        if self.pending > 0 {
+            self.commit()
+        } else {
+            Ok(())
+        }
+    }
+
+    fn commit(&mut self) -> Result<(), git::Error> {
+        let pending = self.pending;
+        self.pending = 0;
+        self.first_pending = None;
+
+        // Entries with an empty command are not written so there might be
+        // nothing to commit.
+        if git::status(&self.data_dir)?.is_empty() {
+            return Ok(());
+        }
+
+        let message = if pending == 1 {
+            "Add 1 entry".to_string()
+        } else {
+            format!("Add {pending} entries")
+        };
+
+        git::commit_all(&self.data_dir, &message)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Committer;
+    use crate::store::git::CommitMode;
+    use std::time::Duration;
+
+    #[test]
+    fn batched() {
+        let data_dir = tempfile::tempdir().expect("can create data dir");
+
+        let git = |args: &[&str]| {
+            let output = std::process::Command::new("git")
+                .arg("-C")
+                .arg(data_dir.path())
+                .args(args)
+                .output()
+                .expect("can run git");
+
+            assert!(output.status.success(), "{output:?}");
+            String::from_utf8_lossy(&output.stdout).trim().to_string()
+        };
+
+        git(&["init", "--quiet"]);
+        git(&["config", "user.name", "hstdb"]);
+        git(&["config", "user.email", "hstdb@localhost"]);
+
+        let host_file = data_dir.path().join("host.csv");
+        let mut content = String::new();
+        let mut add = |committer: &mut Committer, line: &str| {
+            content.push_str(line);
+            std::fs::write(&host_file, &content).expect("can write host file");
+            committer.entry_added().expect("can add entry");
+        };
+
+        let mut committer = Committer::new(
+            data_dir.path().to_path_buf(),
+            CommitMode::Batched,
+            2,
+            Duration::from_secs(3600),
+        );
+
+        add(&mut committer, "a\n");
+        assert!(committer.wait_timeout().is_some());
+        assert!(git(&["rev-list", "--all"]).is_empty());
+
+        add(&mut committer, "b\n");
+        assert_eq!(committer.wait_timeout(), None);
+        assert_eq!(git(&["rev-list", "--count", "HEAD"]), "1");
+        assert_eq!(git(&["log", "--format=%s"]), "Add 2 entries");
+
+        add(&mut committer, "c\n");
+        committer.commit_if_due().expect("can check batch");
+        assert_eq!(git(&["rev-list", "--count", "HEAD"]), "1");
+
+        committer.commit_pending().expect("can commit pending");
+        assert_eq!(git(&["rev-list", "--count", "HEAD"]), "2");
+
+        let mut committer = Committer::new(
+            data_dir.path().to_path_buf(),
+            CommitMode::Off,
+            1,
+            Duration::from_secs(0),
+        );
+
+        add(&mut committer, "d\n");
+        committer.commit_pending().expect("can commit pending");
+        assert_eq!(git(&["rev-list", "--count", "HEAD"]), "2");
+    }
+}
@@ -1,4 +1,8 @@
 use crate::message::CommandStart;
+use chrono::{
+    DateTime,
+    Utc,
+};
 use serde::Serialize;
 use std::path::Path;
 use thiserror::Error;
@@ -36,6 +40,7 @@ pub fn new(path: impl AsRef<Path>) -> Result<Db, Error> {
     })
 }
 
+#[derive(Debug)]
 pub struct Db {
     entries: sled::Db,
     disabled_sessions: sled::Db,
@@ -56,9 +61,15 @@ impl Db {
         Ok(contains)
     }
 
+    /// Returns a number that is higher than every number returned before,
+    /// also by earlier runs of the server.
+    pub fn next_seq(&self) -> Result<u64, Error> {
+        Ok(self.entries.generate_id()?)
+    }
+
     pub fn add_entry(&self, entry: &CommandStart) -> Result<(), Error> {
-        let key = Self::serialize(&entry.session_id)?;
-        let value = Self::serialize(&entry)?;
+        let key = Self::serialize(entry.session_id)?;
+        let value = Self::serialize(entry)?;
 
         self.entries.insert(key, value)?;
 
@@ -75,6 +86,20 @@ impl Db {
         Ok(entry)
     }
 
+    /// Sets the start time of the started command of the session.
+    pub fn set_entry_start(&self, uuid: &Uuid, time_stamp: DateTime<Utc>) -> Result<(), Error> {
+        let key = Self::serialize(uuid)?;
+
+        let data = self.entries.get(&key)?.ok_or(Error::EntryNotExist)?;
+
+        let mut entry = Self::deserialize_entry(&data)?;
+        entry.time_stamp = time_stamp;
+
+        self.entries.insert(key, Self::serialize(entry)?)?;
+
+        Ok(())
+    }
+
     pub fn disable_session(&self, uuid: &Uuid) -> Result<(), Error> {
         let key = Self::serialize(uuid)?;
         let value = Self::serialize(true)?;
@@ -94,6 +119,48 @@ impl Db {
         Ok(())
     }
 
+    /// Size of all databases on disk in bytes.
+    pub fn size_on_disk(&self) -> Result<u64, Error> {
+        Ok(self.entries.size_on_disk()? + self.disabled_sessions.size_on_disk()?)
+    }
+
+    pub fn flush(&self) -> Result<(), Error> {
+        self.entries.flush()?;
+        self.disabled_sessions.flush()?;
+
+        Ok(())
+    }
+
+    /// Removes started commands that never finished and were started before
+    /// the given time. Returns how many entries were removed.
+    pub fn remove_stale_entries(&self, before: DateTime<Utc>) -> Result<usize, Error> {
+        let mut removed = 0;
+
+        for item in self.entries.iter() {
+            let (key, value) = item?;
+            let entry = Self::deserialize_entry(&value)?;
+
+            if entry.time_stamp < before {
+                self.entries.remove(key)?;
+                removed += 1;
+            }
+        }
+
+        Ok(removed)
+    }
+
+    /// Returns all started commands that did not finish yet.
+    pub fn running_entries(&self) -> Result<Vec<CommandStart>, Error> {
+        self.entries
+            .iter()
+            .map(|item| {
+                let (_, value) = item?;
+
+                Self::deserialize_entry(&value)
+            })
+            .collect()
+    }
+
     fn serialize(data: impl Serialize) -> Result<Vec<u8>, Error> {
         let bytes = bincode::serialize(&data).map_err(Error::SerializeData)?;
 
@@ -106,3 +173,48 @@ impl Db {
         Ok(entry)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::message::CommandStart;
+    use chrono::{
+        Duration,
+        Utc,
+    };
+    use std::path::PathBuf;
+    use uuid::Uuid;
+
+    #[test]
+    fn gc_populated_db() {
+        let cache_dir = tempfile::tempdir().expect("can create cache dir");
+        let db = super::new(cache_dir.path()).expect("can open db");
+
+        let now = Utc::now();
+        let start = |time_stamp| CommandStart {
+            command: "sleep 1000".to_string(),
+            pwd: PathBuf::from("/tmp"),
+            session_id: Uuid::new_v4(),
+            time_stamp,
+            user: "testuser".to_string(),
+            hostname: "testhostname".to_string(),
+            hist_event: None,
+            tag: None,
+        };
+
+        let stale = start(now - Duration::days(2));
+        let running = start(now);
+
+        db.add_entry(&stale).expect("can add entry");
+        db.add_entry(&running).expect("can add entry");
+
+        let removed = db
+            .remove_stale_entries(now - Duration::days(1))
+            .expect("can remove stale entries");
+        db.flush().expect("can flush");
+        db.size_on_disk().expect("can get size");
+
+        assert_eq!(removed, 1);
+        assert!(!db.contains_entry(&stale.session_id).expect("can check"));
+        assert!(db.contains_entry(&running.session_id).expect("can check"));
+    }
+}
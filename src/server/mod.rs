@@ -1,4 +1,5 @@
 pub mod builder;
+mod commit;
 pub mod db;
 
 pub use builder::{
@@ -12,10 +13,19 @@ use crate::{
     message::{
         CommandFinished,
         CommandStart,
+        CommandStarted,
+        Envelope,
         Message,
     },
-    store::Store,
+    store::{
+        git::CommitMode,
+        Backend,
+        StorageKey,
+        StoreBackend,
+    },
+    transport::Transport,
 };
+use commit::Committer;
 use crossbeam_utils::sync::WaitGroup;
 use db::Db;
 use flume::{
@@ -23,10 +33,16 @@ use flume::{
     Sender,
 };
 use log::{
+    debug,
     info,
     warn,
 };
 use std::{
+    net::{
+        SocketAddr,
+        TcpListener,
+        TcpStream,
+    },
     os::unix::net::UnixDatagram,
     path::{
         Path,
@@ -40,19 +56,25 @@ use std::{
         Arc,
     },
     thread,
+    time::Duration,
 };
 use thiserror::Error;
 use uuid::Uuid;
 
 const BUFFER_SIZE: usize = 65_527;
 
+/// How long the server waits for a TCP client to send its message. Messages
+/// are read one after the other so a client that never finishes its message
+/// would otherwise block every other client.
+const TCP_READ_TIMEOUT: Duration = Duration::from_secs(5);
+
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("can not receive message from socket: {0}")]
     ReceiveFromSocket(std::io::Error),
 
-    #[error("can not send received data to processing: {0}")]
-    SendBuffer(flume::SendError<Vec<u8>>),
+    #[error("can not send received message to processing as the processor stopped")]
+    SendBuffer,
 
     #[error("can not deserialize message: {0}")]
     DeserializeMessage(bincode::Error),
@@ -69,9 +91,6 @@ pub enum Error {
     #[error("command for session already started")]
     SessionCommandAlreadyStarted,
 
-    #[error("command for session not started yet")]
-    SessionCommandNotStarted,
-
     #[error("can not check if key exists in db: {0}")]
     CheckContainsEntry(db::Error),
 
@@ -87,21 +106,57 @@ pub enum Error {
     #[error("can not remove entry from db: {0}")]
     RemoveDbEntry(db::Error),
 
+    #[error("can not get sequence number for entry: {0}")]
+    NextSeq(db::Error),
+
+    #[error("can not update entry in db: {0}")]
+    UpdateDbEntry(db::Error),
+
+    #[error("can not create socket to reply: {0}")]
+    CreateReplySocket(std::io::Error),
+
+    #[error("can not serialize reply: {0}")]
+    SerializeReply(bincode::Error),
+
+    #[error("can not send reply: {0}")]
+    SendReply(std::io::Error),
+
     #[error("can not add to storeo: {0}")]
     AddStore(crate::store::Error),
 
+    #[error("can not commit history files: {0}")]
+    Commit(crate::store::git::Error),
+
     #[error("db error: {0}")]
     Db(#[from] db::Error),
+
+    #[error("can not accept tcp connection: {0}")]
+    AcceptConnection(std::io::Error),
+
+    #[error("can not set read timeout of tcp connection: {0}")]
+    SetReadTimeout(std::io::Error),
+
+    #[error("ignoring message from {0} that is only accepted on the socket")]
+    RemoteMessageNotAllowed(SocketAddr),
+
+    #[error("dropping message with missing or wrong auth token")]
+    InvalidAuthToken,
 }
 
+#[derive(Debug)]
 pub struct Server {
     pub(super) db: Db,
     pub(super) socket: UnixDatagram,
     pub(super) socket_path: PathBuf,
-    pub(super) store: Store,
+    pub(super) tcp_listener: Option<TcpListener>,
+    pub(super) store: Box<dyn StoreBackend>,
+    pub(super) committer: Committer,
     pub(super) stopping: Arc<AtomicBool>,
     pub(super) wait_group: WaitGroup,
     pub(super) handle_ctrlc: bool,
+    pub(super) split_compound_commands: bool,
+    pub(super) processor_queue_size: usize,
+    pub(super) auth_token: Option<String>,
 }
 
 pub fn builder(
@@ -115,28 +170,65 @@ pub fn builder(
         data_dir,
         socket,
         handle_ctrlc,
+        socket_recv_buffer_bytes: None,
+        backend: Backend::default(),
+        storage_key: StorageKey::default(),
+        split_compound_commands: false,
+        processor_queue_size: 10_000,
+        git_commit_mode: CommitMode::default(),
+        git_commit_batch_entries: 100,
+        git_commit_batch_interval: std::time::Duration::from_secs(300),
+        listen: None,
+        auth_token: None,
     }
 }
 
 impl Server {
     pub fn run(self) -> Result<(), Error> {
-        let data_sender = Self::start_processor(
+        let (data_sender, data_receiver) = if self.processor_queue_size == 0 {
+            info!("processor queue is unbounded");
+
+            flume::unbounded()
+        } else {
+            info!(
+                "processor queue is bounded to {} messages",
+                self.processor_queue_size
+            );
+
+            flume::bounded(self.processor_queue_size)
+        };
+
+        // Only wakes up the receiver which might already be gone, so there is
+        // no point in trying again.
+        let wake_client = client::new(self.socket_path.clone())
+            .retries(0, Duration::default())
+            .auth_token(self.auth_token.clone());
+
+        Self::start_processor(
             Arc::clone(&self.stopping),
             self.wait_group.clone(),
             self.db,
             self.store,
-            self.socket_path.clone(),
+            self.committer,
+            data_receiver,
+            wake_client,
+            self.split_compound_commands,
         );
 
         Self::start_receiver(
             Arc::clone(&self.stopping),
             self.wait_group.clone(),
             self.socket,
-            data_sender,
+            data_sender.clone(),
+            self.auth_token.clone(),
         );
 
+        if let Some(tcp_listener) = self.tcp_listener {
+            Self::start_tcp_receiver(tcp_listener, data_sender, self.auth_token.clone());
+        }
+
         if self.handle_ctrlc {
-            Self::ctrl_c_watcher(self.stopping, self.socket_path.clone())?;
+            Self::ctrl_c_watcher(self.stopping, self.socket_path.clone(), self.auth_token)?;
         }
 
         info!("listening on {:?}", self.socket_path);
@@ -148,11 +240,19 @@ impl Server {
         Ok(())
     }
 
-    fn ctrl_c_watcher(stopping: Arc<AtomicBool>, socket_path: PathBuf) -> Result<(), Error> {
+    // ctrlc is built with the termination feature so this also handles
+    // SIGTERM and SIGHUP, e.g. when systemd stops the service. The handler
+    // only sends a stop message, messages that are already queued are still
+    // written to the store before the server exits.
+    fn ctrl_c_watcher(
+        stopping: Arc<AtomicBool>,
+        socket_path: PathBuf,
+        auth_token: Option<String>,
+    ) -> Result<(), Error> {
         ctrlc::set_handler(move || {
             stopping.store(true, Ordering::SeqCst);
 
-            let client = client::new(socket_path.clone());
+            let client = client::new(socket_path.clone()).auth_token(auth_token.clone());
             if let Err(err) = client.send(&Message::Stop) {
                 warn!("{}", err);
             }
@@ -166,7 +266,8 @@ impl Server {
         stopping: Arc<AtomicBool>,
         wait_group: WaitGroup,
         socket: UnixDatagram,
-        data_sender: Sender<Vec<u8>>,
+        data_sender: Sender<Message>,
+        auth_token: Option<String>,
     ) {
         thread::spawn(move || {
             loop {
@@ -174,7 +275,7 @@ impl Server {
                     break;
                 }
 
-                if let Err(err) = Self::receive(&socket, &data_sender) {
+                if let Err(err) = Self::receive(&socket, &data_sender, auth_token.as_deref()) {
                     warn!("{}", err);
                 }
             }
@@ -183,80 +284,196 @@ impl Server {
         });
     }
 
-    fn receive(socket: &UnixDatagram, data_sender: &Sender<Vec<u8>>) -> Result<(), Error> {
-        let mut buffer = [0_u8; BUFFER_SIZE];
-        let (written, _) = socket
-            .recv_from(&mut buffer)
-            .map_err(Error::ReceiveFromSocket)?;
+    fn receive(
+        socket: &UnixDatagram,
+        data_sender: &Sender<Message>,
+        auth_token: Option<&str>,
+    ) -> Result<(), Error> {
+        let message = Self::read_message(socket, auth_token)?;
 
-        data_sender
-            .send(buffer[0..written].to_vec())
-            .map_err(Error::SendBuffer)?;
+        data_sender.send(message).map_err(|_| Error::SendBuffer)?;
 
         Ok(())
     }
 
+    /// Accepts the messages that record commands on the TCP listener. The
+    /// thread is not part of the wait group as it would block in accept
+    /// when the server stops.
+    fn start_tcp_receiver(
+        tcp_listener: TcpListener,
+        data_sender: Sender<Message>,
+        auth_token: Option<String>,
+    ) {
+        match tcp_listener.local_addr() {
+            Ok(address) if auth_token.is_some() => info!("listening on tcp {}", address),
+            Ok(address) => warn!(
+                "listening on tcp {} without auth_token, anyone who can reach it can add entries \
+                 to the history",
+                address
+            ),
+            Err(err) => warn!("can not get address of tcp listener: {}", err),
+        }
+
+        thread::spawn(move || {
+            for stream in tcp_listener.incoming() {
+                let result = stream.map_err(Error::AcceptConnection).and_then(|stream| {
+                    Self::receive_tcp(&stream, &data_sender, auth_token.as_deref())
+                });
+
+                if let Err(err) = result {
+                    warn!("{}", err);
+                }
+            }
+        });
+    }
+
+    fn receive_tcp(
+        stream: &TcpStream,
+        data_sender: &Sender<Message>,
+        auth_token: Option<&str>,
+    ) -> Result<(), Error> {
+        let peer = stream.peer_addr().map_err(Error::AcceptConnection)?;
+
+        stream
+            .set_read_timeout(Some(TCP_READ_TIMEOUT))
+            .map_err(Error::SetReadTimeout)?;
+
+        let message = Self::read_message(stream, auth_token)?;
+
+        // Control messages could stop the server and the reply of the other
+        // messages would go to a path on the machine of the server.
+        if !message.records_commands() {
+            return Err(Error::RemoteMessageNotAllowed(peer));
+        }
+
+        data_sender.send(message).map_err(|_| Error::SendBuffer)?;
+
+        Ok(())
+    }
+
+    /// Receives one message and checks its token before it is queued for
+    /// processing.
+    fn read_message(
+        transport: &impl Transport,
+        auth_token: Option<&str>,
+    ) -> Result<Message, Error> {
+        let mut buffer = vec![0_u8; BUFFER_SIZE];
+        let received = transport
+            .recv(&mut buffer)
+            .map_err(Error::ReceiveFromSocket)?;
+
+        let envelope: Envelope =
+            bincode::deserialize(&buffer[..received]).map_err(Error::DeserializeMessage)?;
+
+        envelope.open(auth_token).ok_or(Error::InvalidAuthToken)
+    }
+
+    /// Returns the address of the TCP listener if the server listens on one.
+    pub fn tcp_address(&self) -> Option<SocketAddr> {
+        self.tcp_listener
+            .as_ref()
+            .and_then(|tcp_listener| tcp_listener.local_addr().ok())
+    }
+
+    #[allow(clippy::too_many_arguments)]
     fn start_processor(
         stopping: Arc<AtomicBool>,
         wait_group: WaitGroup,
         db: Db,
-        store: Store,
-        socket_path: PathBuf,
-    ) -> Sender<Vec<u8>> {
-        let (data_sender, data_receiver) = flume::bounded(10_000);
-
+        store: Box<dyn StoreBackend>,
+        mut committer: Committer,
+        data_receiver: Receiver<Message>,
+        wake_client: client::Client,
+        split_compound_commands: bool,
+    ) {
         thread::spawn(move || {
             loop {
                 if stopping.load(Ordering::SeqCst) {
                     break;
                 }
 
-                if let Err(err) =
-                    Self::process(&stopping, &data_receiver, &db, &store, &socket_path)
-                {
+                if let Err(err) = Self::process(
+                    &stopping,
+                    &data_receiver,
+                    &db,
+                    store.as_ref(),
+                    &mut committer,
+                    &wake_client,
+                    split_compound_commands,
+                ) {
                     warn!("{}", err);
                 }
+
+                if let Err(err) = committer.commit_if_due() {
+                    warn!("{}", Error::Commit(err));
+                }
             }
 
             while !data_receiver.is_empty() {
-                if let Err(err) =
-                    Self::process(&stopping, &data_receiver, &db, &store, &socket_path)
-                {
+                if let Err(err) = Self::process(
+                    &stopping,
+                    &data_receiver,
+                    &db,
+                    store.as_ref(),
+                    &mut committer,
+                    &wake_client,
+                    split_compound_commands,
+                ) {
                     warn!("{}", err);
                 }
             }
 
+            if let Err(err) = committer.commit_pending() {
+                warn!("{}", Error::Commit(err));
+            }
+
             drop(wait_group);
         });
-
-        data_sender
     }
 
     fn process(
         stopping: &Arc<AtomicBool>,
-        data_receiver: &Receiver<Vec<u8>>,
+        data_receiver: &Receiver<Message>,
         db: &Db,
-        store: &Store,
-        socket_path: impl AsRef<Path>,
+        store: &dyn StoreBackend,
+        committer: &mut Committer,
+        wake_client: &client::Client,
+        split_compound_commands: bool,
     ) -> Result<(), Error> {
-        let data = data_receiver.recv().map_err(Error::ReceiveData)?;
-        let message = bincode::deserialize(&data).map_err(Error::DeserializeMessage)?;
+        // With a pending batch of entries we have to wake up in time to commit
+        // it even if no more messages arrive.
+        let message = match committer.wait_timeout() {
+            Some(timeout) => match data_receiver.recv_timeout(timeout) {
+                Ok(message) => message,
+                Err(flume::RecvTimeoutError::Timeout) => return Ok(()),
+                Err(flume::RecvTimeoutError::Disconnected) => {
+                    return Err(Error::ReceiveData(flume::RecvError::Disconnected))
+                }
+            },
+            None => data_receiver.recv().map_err(Error::ReceiveData)?,
+        };
 
         match message {
             Message::Stop => {
                 stopping.store(true, Ordering::SeqCst);
 
-                let client = client::new(socket_path.as_ref().to_path_buf());
-                if let Err(err) = client.send(&Message::Stop) {
+                if let Err(err) = wake_client.send(&Message::Stop) {
                     warn!("{}", err);
                 }
 
                 Ok(())
             }
             Message::CommandStart(data) => Self::command_start(db, &data),
-            Message::CommandFinished(data) => Self::command_finished(db, store, &data),
+            Message::CommandFinished(data) => {
+                Self::command_finished(db, store, &data, split_compound_commands)?;
+                committer.entry_added().map_err(Error::Commit)
+            }
             Message::Disable(uuid) => Self::disable_session(db, &uuid),
             Message::Enable(uuid) => Self::enable_session(db, &uuid),
+            Message::Running(reply_path) => Self::running(db, &reply_path),
+            Message::CommandStarted(data) => Self::command_started(db, &data),
+            Message::Ping(reply_path) => Self::ping(&reply_path),
+            Message::Flush(reply_path) => Self::flush(committer, &reply_path),
         }
     }
 
@@ -280,7 +497,33 @@ impl Server {
         Ok(())
     }
 
-    fn command_finished(db: &Db, store: &Store, data: &CommandFinished) -> Result<(), Error> {
+    fn command_started(db: &Db, data: &CommandStarted) -> Result<(), Error> {
+        if !db
+            .contains_entry(&data.session_id)
+            .map_err(Error::CheckContainsEntry)?
+        {
+            // Commands that are not recorded, like the ones starting with a
+            // space or run in a disabled session, have no started command.
+            debug!(
+                "ignoring started command for session {} without started command",
+                data.session_id
+            );
+
+            return Ok(());
+        }
+
+        db.set_entry_start(&data.session_id, data.time_stamp)
+            .map_err(Error::UpdateDbEntry)?;
+
+        Ok(())
+    }
+
+    fn command_finished(
+        db: &Db,
+        store: &dyn StoreBackend,
+        data: &CommandFinished,
+        split_compound_commands: bool,
+    ) -> Result<(), Error> {
         if db
             .is_session_disabled(&data.session_id)
             .map_err(Error::CheckDisabledSession)?
@@ -292,7 +535,14 @@ impl Server {
             .contains_entry(&data.session_id)
             .map_err(Error::CheckContainsEntry)?
         {
-            return Err(Error::SessionCommandNotStarted);
+            // The first prompt of a session reports the result of whatever
+            // ran before recording started so there is nothing to finish.
+            debug!(
+                "ignoring finished command for session {} without started command",
+                data.session_id
+            );
+
+            return Ok(());
         }
 
         let start = db
@@ -301,7 +551,16 @@ impl Server {
 
         let entry = Entry::from_messages(start, data);
 
-        store.add(&entry).map_err(Error::AddStore)?;
+        let entries = if split_compound_commands {
+            entry.split_compound()
+        } else {
+            vec![entry]
+        };
+
+        for mut entry in entries {
+            entry.seq = db.next_seq().map_err(Error::NextSeq)?;
+            store.add(&entry).map_err(Error::AddStore)?;
+        }
 
         Ok(())
     }
@@ -312,9 +571,144 @@ impl Server {
         Ok(())
     }
 
+    /// Sends every running command as its own datagram so the reply is not
+    /// limited by the maximum datagram size. An empty datagram marks the end
+    /// of the reply.
+    fn running(db: &Db, reply_path: impl AsRef<Path>) -> Result<(), Error> {
+        let socket = UnixDatagram::unbound().map_err(Error::CreateReplySocket)?;
+
+        for entry in db.running_entries()? {
+            let data = bincode::serialize(&entry).map_err(Error::SerializeReply)?;
+
+            socket
+                .send_to(&data, &reply_path)
+                .map_err(Error::SendReply)?;
+        }
+
+        socket.send_to(&[], &reply_path).map_err(Error::SendReply)?;
+
+        Ok(())
+    }
+
+    /// Commits the pending entries and replies with the result of the commit.
+    /// Entries are synced to disk when they are written so committing is all
+    /// that is left to do.
+    fn flush(committer: &mut Committer, reply_path: impl AsRef<Path>) -> Result<(), Error> {
+        let result = committer.commit_pending();
+
+        let socket = UnixDatagram::unbound().map_err(Error::CreateReplySocket)?;
+        let reply: Result<(), String> = match &result {
+            Ok(()) => Ok(()),
+            Err(err) => Err(err.to_string()),
+        };
+        let data = bincode::serialize(&reply).map_err(Error::SerializeReply)?;
+
+        socket
+            .send_to(&data, &reply_path)
+            .map_err(Error::SendReply)?;
+
+        result.map_err(Error::Commit)
+    }
+
+    fn ping(reply_path: impl AsRef<Path>) -> Result<(), Error> {
+        let socket = UnixDatagram::unbound().map_err(Error::CreateReplySocket)?;
+        let data = bincode::serialize(&std::process::id()).map_err(Error::SerializeReply)?;
+
+        socket
+            .send_to(&data, &reply_path)
+            .map_err(Error::SendReply)?;
+
+        Ok(())
+    }
+
     fn enable_session(db: &Db, uuid: &Uuid) -> Result<(), Error> {
         db.enable_session(uuid)?;
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::Server;
+    use crate::{
+        message::{
+            CommandFinished,
+            CommandStart,
+            CommandStarted,
+        },
+        server::db,
+        store,
+    };
+    use chrono::{
+        Duration,
+        Utc,
+    };
+    use std::path::PathBuf;
+    use uuid::Uuid;
+
+    #[test]
+    fn command_finished_without_start() {
+        let cache_dir = tempfile::tempdir().expect("can create cache dir");
+        let data_dir = tempfile::tempdir().expect("can create data dir");
+
+        let db = db::new(cache_dir.path()).expect("can open db");
+        let store = store::new(data_dir.path().to_path_buf());
+
+        let data = CommandFinished {
+            session_id: Uuid::new_v4(),
+            time_stamp: Utc::now(),
+            result: 0,
+            reported_duration_ms: None,
+        };
+
+        Server::command_finished(&db, &store, &data, false)
+            .expect("finished command without start is ignored");
+
+        assert!(store.host_files().expect("can list host files").is_empty());
+    }
+
+    #[test]
+    fn command_started_updates_start() {
+        let cache_dir = tempfile::tempdir().expect("can create cache dir");
+        let db = db::new(cache_dir.path()).expect("can open db");
+
+        let added = Utc::now();
+        let started = added + Duration::seconds(5);
+
+        let start = CommandStart {
+            command: "sleep 1000".to_string(),
+            pwd: PathBuf::from("/tmp"),
+            session_id: Uuid::new_v4(),
+            time_stamp: added,
+            user: "testuser".to_string(),
+            hostname: "testhostname".to_string(),
+            hist_event: None,
+            tag: None,
+        };
+
+        Server::command_start(&db, &start).expect("can start command");
+        Server::command_started(
+            &db,
+            &CommandStarted {
+                session_id: start.session_id,
+                time_stamp: started,
+            },
+        )
+        .expect("can update start of command");
+
+        let running = db.running_entries().expect("can get running entries");
+        assert_eq!(running.len(), 1);
+        assert_eq!(running[0].time_stamp, started);
+        assert_eq!(running[0].command, start.command);
+
+        Server::command_started(
+            &db,
+            &CommandStarted {
+                session_id: Uuid::new_v4(),
+                time_stamp: started,
+            },
+        )
+        .expect("started command without start is ignored");
+    }
+}
@@ -1,7 +1,18 @@
 use crate::entry::Entry;
+use chrono::{
+    DateTime,
+    Utc,
+};
 use regex::Regex;
-use std::path::PathBuf;
+use std::{
+    collections::HashSet,
+    path::{
+        Path,
+        PathBuf,
+    },
+};
 use thiserror::Error;
+use uuid::Uuid;
 
 #[derive(Error, Debug)]
 pub enum Error {
@@ -10,40 +21,91 @@ pub enum Error {
 
     #[error("can not get current directory: {0}")]
     GetCurrentDir(std::io::Error),
+
+    #[error("no git repository found in {0:?} or any of its parent directories")]
+    NoProjectRoot(PathBuf),
 }
 
+/// Maps hostnames matching a pattern to one canonical hostname so entries of
+/// a host that reported different names over time are read as one host.
+#[derive(Debug, Default, Clone)]
+pub struct HostnameAliases(Vec<(Regex, String)>);
+
+impl HostnameAliases {
+    pub fn new<'a>(
+        aliases: impl IntoIterator<Item = (&'a str, &'a str)>,
+    ) -> Result<Self, regex::Error> {
+        let aliases = aliases
+            .into_iter()
+            .map(|(pattern, hostname)| Ok((Regex::new(pattern)?, hostname.to_string())))
+            .collect::<Result<_, regex::Error>>()?;
+
+        Ok(Self(aliases))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Returns the hostname of the first matching pattern or the hostname
+    /// itself if no pattern matches.
+    pub fn canonical_hostname(&self, hostname: &str) -> String {
+        self.0
+            .iter()
+            .find(|(pattern, _)| pattern.is_match(hostname))
+            .map_or_else(|| hostname.to_string(), |(_, canonical)| canonical.clone())
+    }
+}
+
+#[allow(clippy::struct_field_names, clippy::struct_excessive_bools)]
 #[derive(Debug, Default)]
 pub struct Filter {
-    pub hostname: Option<String>,
+    pub hostnames: Vec<String>,
     pub directory: Option<PathBuf>,
+    pub directory_excluded: Vec<PathBuf>,
     pub command: Option<String>,
     pub no_subdirs: bool,
     pub command_text: Option<Regex>,
     pub command_text_excluded: Option<Regex>,
     pub count: usize,
     pub session: Option<Regex>,
+    pub user: Option<String>,
+    pub tag: Option<String>,
     pub filter_failed: bool,
+    pub failed_only: bool,
     pub find_status: Option<u16>,
+    pub include_deleted: bool,
+    pub search: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub pwd_exists: Option<bool>,
+    pub unique: bool,
+    pub min_pipes: Option<usize>,
+    pub hostname_aliases: HostnameAliases,
 }
 
 impl Filter {
-    pub const fn get_hostname(&self) -> Option<&String> {
-        self.hostname.as_ref()
+    pub fn get_hostnames(&self) -> &[String] {
+        &self.hostnames
     }
 
-    pub fn hostname(self, hostname: Option<String>, all_hosts: bool) -> Result<Self, Error> {
-        let current_hostname = hostname::get()
-            .map_err(Error::GetHostname)?
-            .to_string_lossy()
-            .to_string();
+    /// Only reads the given hosts. Reads the current host if no hosts are
+    /// given and all hosts if `all_hosts` is set.
+    pub fn hostnames(self, hostnames: Vec<String>, all_hosts: bool) -> Result<Self, Error> {
+        let hostnames = if all_hosts {
+            Vec::new()
+        } else if hostnames.is_empty() {
+            let current_hostname = hostname::get()
+                .map_err(Error::GetHostname)?
+                .to_string_lossy()
+                .to_string();
 
-        let hostname = if all_hosts {
-            None
+            vec![current_hostname]
         } else {
-            Some(hostname.unwrap_or(current_hostname))
+            hostnames
         };
 
-        Ok(Self { hostname, ..self })
+        Ok(Self { hostnames, ..self })
     }
 
     pub fn directory(
@@ -65,6 +127,33 @@ impl Filter {
         })
     }
 
+    /// Only reads entries that were run in the git repository that contains
+    /// the current directory, including its subdirectories.
+    pub fn project(self, project: bool) -> Result<Self, Error> {
+        if !project {
+            return Ok(self);
+        }
+
+        let current_dir = std::env::current_dir().map_err(Error::GetCurrentDir)?;
+        let root = project_root(&current_dir).ok_or(Error::NoProjectRoot(current_dir))?;
+
+        Ok(Self {
+            directory: Some(root),
+            no_subdirs: false,
+            ..self
+        })
+    }
+
+    /// Hides entries that were run in one of the given directories. Like the
+    /// included directory subdirectories only match unless `no_subdirs` is
+    /// set.
+    pub fn directory_excluded(self, directory_excluded: Vec<PathBuf>) -> Self {
+        Self {
+            directory_excluded,
+            ..self
+        }
+    }
+
     pub fn count(self, count: usize) -> Self {
         Self { count, ..self }
     }
@@ -83,52 +172,61 @@ impl Filter {
         }
     }
 
+    /// Returns true when the filter only has predicates on the hostname, time,
+    /// status, user, tag and deleted columns which storage backends can apply
+    /// themselves and does not remove duplicate commands.
+    pub fn matches_columns_only(&self) -> bool {
+        self.directory.is_none()
+            && self.directory_excluded.is_empty()
+            && self.command.is_none()
+            && self.command_text.is_none()
+            && self.command_text_excluded.is_none()
+            && self.session.is_none()
+            && self.search.is_none()
+            && self.pwd_exists.is_none()
+            && self.min_pipes.is_none()
+            && !self.unique
+    }
+
     pub fn filter_entries(&self, entries: Vec<Entry>) -> Vec<Entry> {
-        let filtered: Vec<Entry> = entries
+        let search_tokens = self.search_tokens();
+
+        let filtered = entries
             .into_iter()
-            .filter(|entry| {
-                self.command.as_ref().map_or(true, |command| {
-                    Self::filter_command(&entry.command, command)
-                })
-            })
-            .filter(|entry| {
-                self.directory.as_ref().map_or(true, |dir| {
-                    if self.no_subdirs {
-                        entry.pwd == *dir
-                    } else {
-                        entry.pwd.as_path().starts_with(dir)
-                    }
-                })
-            })
-            .filter(|entry| {
-                self.command_text
-                    .as_ref()
-                    .map_or(true, |regex| regex.is_match(&entry.command))
-            })
-            .filter(|entry| {
-                self.command_text_excluded
-                    .as_ref()
-                    .map_or(true, |regex| !regex.is_match(&entry.command))
-            })
-            .filter(|entry| {
-                self.session
-                    .as_ref()
-                    .map_or(true, |regex| regex.is_match(&entry.session_id.to_string()))
-            })
-            .filter(|entry| !self.filter_failed || entry.result == 0)
-            .filter(|entry| {
-                self.find_status
-                    .and_then(|find_status| {
-                        if find_status == entry.result {
-                            None
-                        } else {
-                            Some(())
-                        }
-                    })
-                    .is_none()
-            })
+            .filter(|entry| self.matches_entry(entry, search_tokens.as_deref()))
             .collect();
 
+        self.limit_entries(filtered)
+    }
+
+    /// Returns true when the entry matches every predicate of the filter. Does
+    /// not look at other entries so duplicate commands and the count are not
+    /// taken into account.
+    pub fn matches(&self, entry: &Entry) -> bool {
+        self.matches_entry(entry, self.search_tokens().as_deref())
+    }
+
+    /// Removes duplicate commands if the filter only keeps unique commands and
+    /// keeps the newest entries up to the count. Expects entries that match
+    /// the filter sorted from oldest to newest.
+    pub fn limit_entries(&self, entries: Vec<Entry>) -> Vec<Entry> {
+        // Only keep the newest entry of every command. This happens before
+        // limiting the count so the count is the number of distinct commands.
+        let filtered = if self.unique {
+            let mut seen = HashSet::new();
+
+            let mut unique: Vec<_> = entries
+                .into_iter()
+                .rev()
+                .filter(|entry| seen.insert(entry.command.clone()))
+                .collect();
+
+            unique.reverse();
+            unique
+        } else {
+            entries
+        };
+
         if self.count > 0 {
             filtered.into_iter().rev().take(self.count).rev().collect()
         } else {
@@ -136,10 +234,89 @@ impl Filter {
         }
     }
 
+    fn search_tokens(&self) -> Option<Vec<String>> {
+        self.search
+            .as_ref()
+            .map(|search| search.split_whitespace().map(str::to_lowercase).collect())
+    }
+
+    fn matches_entry(&self, entry: &Entry, search_tokens: Option<&[String]>) -> bool {
+        self.command.as_ref().map_or(true, |command| {
+            Self::filter_command(&entry.command, command)
+        }) && self.directory.as_ref().map_or(true, |dir| {
+            if self.no_subdirs {
+                entry.pwd == *dir
+            } else {
+                entry.pwd.as_path().starts_with(dir)
+            }
+        }) && !self.directory_excluded.iter().any(|dir| {
+            if self.no_subdirs {
+                entry.pwd == *dir
+            } else {
+                entry.pwd.as_path().starts_with(dir)
+            }
+        }) && self
+            .command_text
+            .as_ref()
+            .map_or(true, |regex| regex.is_match(&entry.command))
+            && self
+                .command_text_excluded
+                .as_ref()
+                .map_or(true, |regex| !regex.is_match(&entry.command))
+            && search_tokens.map_or(true, |tokens| Self::filter_search(&entry.command, tokens))
+            && self
+                .session
+                .as_ref()
+                .map_or(true, |regex| regex.is_match(&entry.session_id.to_string()))
+            && self.user.as_ref().map_or(true, |user| &entry.user == user)
+            && self
+                .tag
+                .as_ref()
+                .map_or(true, |tag| entry.tag.as_ref() == Some(tag))
+            && (self.include_deleted || !entry.deleted)
+            && self
+                .since
+                .map_or(true, |since| entry.time_finished >= since)
+            && self
+                .until
+                .map_or(true, |until| entry.time_finished <= until)
+            && self
+                .pwd_exists
+                .map_or(true, |pwd_exists| entry.pwd.exists() == pwd_exists)
+            && self
+                .min_pipes
+                .map_or(true, |min_pipes| entry.pipes() >= min_pipes)
+            && (!self.filter_failed || entry.result == 0)
+            && (!self.failed_only || entry.result != 0)
+            && self
+                .find_status
+                .map_or(true, |find_status| find_status == entry.result)
+    }
+
     pub fn session(self, session: Option<Regex>) -> Self {
         Self { session, ..self }
     }
 
+    /// Only reads entries of the given session. Unlike `session` the whole
+    /// session id has to match.
+    pub fn session_id(self, session_id: Uuid) -> Self {
+        let session = Regex::new(&format!("^{}$", regex::escape(&session_id.to_string())))
+            .expect("escaped session id is a valid regex");
+
+        Self {
+            session: Some(session),
+            ..self
+        }
+    }
+
+    pub fn user(self, user: Option<String>) -> Self {
+        Self { user, ..self }
+    }
+
+    pub fn tag(self, tag: Option<String>) -> Self {
+        Self { tag, ..self }
+    }
+
     pub fn filter_failed(self, filter_failed: bool) -> Self {
         Self {
             filter_failed,
@@ -147,16 +324,63 @@ impl Filter {
         }
     }
 
+    pub fn only_failed(self, failed_only: bool) -> Self {
+        Self {
+            failed_only,
+            ..self
+        }
+    }
+
+    pub fn search(self, search: Option<String>) -> Self {
+        Self { search, ..self }
+    }
+
+    pub fn include_deleted(self, include_deleted: bool) -> Self {
+        Self {
+            include_deleted,
+            ..self
+        }
+    }
+
+    pub fn since(self, since: Option<DateTime<Utc>>) -> Self {
+        Self { since, ..self }
+    }
+
+    pub fn until(self, until: Option<DateTime<Utc>>) -> Self {
+        Self { until, ..self }
+    }
+
+    pub fn pwd_exists(self, pwd_exists: Option<bool>) -> Self {
+        Self { pwd_exists, ..self }
+    }
+
+    pub fn unique(self, unique: bool) -> Self {
+        Self { unique, ..self }
+    }
+
+    pub fn min_pipes(self, min_pipes: Option<usize>) -> Self {
+        Self { min_pipes, ..self }
+    }
+
+    pub fn hostname_aliases(self, hostname_aliases: HostnameAliases) -> Self {
+        Self {
+            hostname_aliases,
+            ..self
+        }
+    }
+
     fn filter_command(entry_command: &str, command: &str) -> bool {
         entry_command
             .split('|')
-            .map(|pipe_command| {
-                pipe_command
-                    .split_whitespace()
-                    .next()
-                    .map_or(false, |entry_command| entry_command == command)
-            })
-            .any(|has_command| has_command)
+            .any(|pipe_command| pipe_command.split_whitespace().next() == Some(command))
+    }
+
+    fn filter_search(entry_command: &str, tokens: &[String]) -> bool {
+        let entry_command = entry_command.to_lowercase();
+
+        tokens
+            .iter()
+            .all(|token| entry_command.contains(token.as_str()))
     }
 
     pub fn find_status(self, find_status: Option<u16>) -> Self {
@@ -167,9 +391,37 @@ impl Filter {
     }
 }
 
+/// Returns the nearest directory that contains a `.git` directory or, for git
+/// worktrees and submodules, a `.git` file.
+fn project_root(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .find(|dir| dir.join(".git").exists())
+        .map(Path::to_path_buf)
+}
+
 #[cfg(test)]
 mod test {
-    use super::Filter;
+    use super::{
+        Filter,
+        HostnameAliases,
+    };
+    use crate::{
+        config::DirectoryMatch,
+        entry::Entry,
+    };
+    use chrono::{
+        Duration,
+        Utc,
+    };
+    use std::path::PathBuf;
+    use uuid::Uuid;
+
+    fn entry(command: &str, pwd: &str) -> Entry {
+        Entry {
+            pwd: PathBuf::from(pwd),
+            ..Entry::for_test("testhostname", command)
+        }
+    }
 
     #[test]
     fn filter_command() {
@@ -184,8 +436,345 @@ mod test {
         ];
         let check_command = "tr";
 
-        cases.into_iter().for_each(|(entry_command, result)| {
-            assert_eq!(Filter::filter_command(entry_command, check_command), result)
-        });
+        for (entry_command, result) in cases {
+            assert_eq!(Filter::filter_command(entry_command, check_command), result);
+        }
+    }
+
+    #[test]
+    fn directory_match_exact_default() {
+        let no_subdirs = DirectoryMatch::Exact.no_subdirs(false, false);
+        let filter = Filter::default()
+            .directory(Some(PathBuf::from("/tmp")), false, no_subdirs)
+            .expect("directory filter without in_current can not fail");
+
+        let entries = vec![entry("ls", "/tmp"), entry("ls", "/tmp/subdir")];
+        let got = filter.filter_entries(entries);
+
+        assert_eq!(got.len(), 1);
+        assert_eq!(got[0].pwd, PathBuf::from("/tmp"));
+
+        assert!(!DirectoryMatch::Exact.no_subdirs(true, false));
+        assert!(DirectoryMatch::Prefix.no_subdirs(false, true));
+        assert!(!DirectoryMatch::Prefix.no_subdirs(false, false));
+    }
+
+    #[test]
+    fn session_id() {
+        let session_id = Uuid::new_v4();
+
+        let mut current = entry("ls", "/tmp");
+        current.session_id = session_id;
+
+        let entries = vec![current.clone(), entry("ls", "/tmp")];
+
+        let filter = Filter::default().session_id(session_id);
+        assert_eq!(filter.filter_entries(entries), vec![current]);
+
+        let partial = Filter::default().session_id(session_id);
+        let session = session_id.to_string();
+        assert!(!partial
+            .session
+            .expect("session is set")
+            .is_match(&session[..8]));
+    }
+
+    #[test]
+    fn directory_excluded() {
+        let entries = || {
+            vec![
+                entry("ls", "/home/user"),
+                entry("ls", "/home/user/secret"),
+                entry("ls", "/home/user/secret/nested"),
+                entry("ls", "/home/user/secretive"),
+                entry("ls", "/tmp"),
+            ]
+        };
+        let pwds = |filter: &Filter| {
+            filter
+                .filter_entries(entries())
+                .into_iter()
+                .map(|entry| entry.pwd)
+                .collect::<Vec<_>>()
+        };
+
+        let filter = Filter::default()
+            .directory(Some(PathBuf::from("/home/user")), false, false)
+            .expect("directory filter without in_current can not fail")
+            .directory_excluded(vec![PathBuf::from("/home/user/secret")]);
+
+        assert_eq!(
+            pwds(&filter),
+            vec![
+                PathBuf::from("/home/user"),
+                PathBuf::from("/home/user/secretive")
+            ]
+        );
+
+        let filter = Filter {
+            no_subdirs: true,
+            ..filter
+        };
+
+        assert_eq!(pwds(&filter), vec![PathBuf::from("/home/user")]);
+
+        let filter = Filter::default()
+            .directory(None, false, true)
+            .expect("directory filter without in_current can not fail")
+            .directory_excluded(vec![PathBuf::from("/home/user/secret")]);
+
+        assert_eq!(
+            pwds(&filter),
+            vec![
+                PathBuf::from("/home/user"),
+                PathBuf::from("/home/user/secret/nested"),
+                PathBuf::from("/home/user/secretive"),
+                PathBuf::from("/tmp")
+            ]
+        );
+    }
+
+    #[test]
+    fn deleted_entries_hidden() {
+        let mut deleted = entry("rm -rf /tmp/secret", "/tmp");
+        deleted.deleted = true;
+
+        let entries = vec![entry("ls", "/tmp"), deleted];
+
+        let got = Filter::default().filter_entries(entries);
+        assert_eq!(got.len(), 1);
+        assert_eq!(got[0].command, "ls");
+
+        let mut deleted = entry("rm -rf /tmp/secret", "/tmp");
+        deleted.deleted = true;
+
+        let entries = vec![entry("ls", "/tmp"), deleted];
+
+        let got = Filter::default()
+            .include_deleted(true)
+            .filter_entries(entries);
+        assert_eq!(got.len(), 2);
+    }
+
+    #[test]
+    fn filter_search() {
+        let cases = vec![
+            ("docker compose up -d", true),
+            ("docker-compose up", true),
+            ("DOCKER COMPOSE logs", true),
+            ("compose docker", true),
+            ("docker ps", false),
+            ("", false),
+        ];
+        let tokens = vec!["docker".to_string(), "compose".to_string()];
+
+        for (entry_command, result) in cases {
+            assert_eq!(
+                Filter::filter_search(entry_command, &tokens),
+                result,
+                "{entry_command}"
+            );
+        }
+    }
+
+    #[test]
+    fn time_range_before_count() {
+        let now = Utc::now();
+        let finished = |command: &str, days_ago: i64| {
+            let mut entry = entry(command, "/tmp");
+            entry.time_finished = now - Duration::days(days_ago);
+            entry
+        };
+
+        let entries = vec![
+            finished("old", 10),
+            finished("first", 3),
+            finished("second", 2),
+            finished("new", 0),
+        ];
+
+        let got = Filter::default()
+            .since(Some(now - Duration::days(5)))
+            .until(Some(now - Duration::days(1)))
+            .count(5)
+            .filter_entries(entries);
+
+        let commands: Vec<_> = got.iter().map(|entry| entry.command.as_str()).collect();
+        assert_eq!(commands, vec!["first", "second"]);
+    }
+
+    #[test]
+    fn project_root() {
+        let dir = tempfile::tempdir().expect("can create dir");
+        let project = dir.path().join("project");
+        let worktree = dir.path().join("worktree");
+        let nested = project.join("src").join("module");
+
+        std::fs::create_dir_all(project.join(".git")).expect("can create git dir");
+        std::fs::create_dir_all(&nested).expect("can create nested dir");
+        std::fs::create_dir_all(worktree.join("src")).expect("can create worktree");
+        std::fs::write(
+            worktree.join(".git"),
+            "gitdir: ../project/.git/worktrees/wt",
+        )
+        .expect("can write git file");
+
+        assert_eq!(super::project_root(&nested), Some(project.clone()));
+        assert_eq!(super::project_root(&project), Some(project));
+        assert_eq!(
+            super::project_root(&worktree.join("src")),
+            Some(worktree.clone())
+        );
+        assert_eq!(super::project_root(dir.path()), None);
+    }
+
+    #[test]
+    fn min_pipes() {
+        let entries = vec![
+            entry("ls", "/tmp"),
+            entry("grep 'a|b' file", "/tmp"),
+            entry("cat file | sort", "/tmp"),
+            entry("cat file | sort | uniq", "/tmp"),
+        ];
+
+        let got = Filter::default().min_pipes(Some(1)).filter_entries(entries);
+
+        let commands: Vec<_> = got.iter().map(|entry| entry.command.as_str()).collect();
+        assert_eq!(commands, vec!["cat file | sort", "cat file | sort | uniq"]);
+    }
+
+    #[test]
+    fn pwd_exists() {
+        let dir = tempfile::tempdir().expect("can create existing dir");
+        let missing = dir.path().join("missing");
+
+        let existing = dir.path().to_string_lossy().to_string();
+        let missing = missing.to_string_lossy().to_string();
+
+        let entries = || vec![entry("ls", &existing), entry("ls", &missing)];
+
+        let got = Filter::default()
+            .pwd_exists(Some(true))
+            .filter_entries(entries());
+        assert_eq!(got.len(), 1);
+        assert_eq!(got[0].pwd, PathBuf::from(&existing));
+
+        let got = Filter::default()
+            .pwd_exists(Some(false))
+            .filter_entries(entries());
+        assert_eq!(got.len(), 1);
+        assert_eq!(got[0].pwd, PathBuf::from(&missing));
+
+        let got = Filter::default().filter_entries(entries());
+        assert_eq!(got.len(), 2);
+    }
+
+    #[test]
+    fn canonical_hostname() {
+        let aliases = HostnameAliases::new(vec![
+            (r"^laptop(\..+)?$", "laptop"),
+            (r"^desktop-\d+$", "desktop"),
+        ])
+        .expect("patterns are valid");
+
+        let cases = vec![
+            ("laptop", "laptop"),
+            ("laptop.local", "laptop"),
+            ("laptop.home.arpa", "laptop"),
+            ("desktop-2", "desktop"),
+            ("laptops", "laptops"),
+            ("server", "server"),
+        ];
+
+        for (hostname, expected) in cases {
+            assert_eq!(aliases.canonical_hostname(hostname), expected, "{hostname}");
+        }
+
+        assert!(HostnameAliases::new(vec![("(", "broken")]).is_err());
+    }
+
+    #[test]
+    fn filter_failed_and_only_failed() {
+        let entries = || {
+            vec![0, 1, 0, 127]
+                .into_iter()
+                .map(|result| {
+                    let mut entry = entry("ls", "/tmp");
+                    entry.result = result;
+                    entry
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let results = |filter: Filter| {
+            filter
+                .filter_entries(entries())
+                .into_iter()
+                .map(|entry| entry.result)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(results(Filter::default().filter_failed(true)), vec![0, 0]);
+        assert_eq!(results(Filter::default().only_failed(true)), vec![1, 127]);
+        assert_eq!(results(Filter::default()), vec![0, 1, 0, 127]);
+    }
+
+    #[test]
+    fn user() {
+        let entries = vec!["alice", "bob", "alice", "alice2"]
+            .into_iter()
+            .map(|user| {
+                let mut entry = entry("ls", "/tmp");
+                entry.user = user.to_string();
+                entry
+            })
+            .collect();
+
+        let users: Vec<_> = Filter::default()
+            .user(Some("alice".to_string()))
+            .filter_entries(entries)
+            .into_iter()
+            .map(|entry| entry.user)
+            .collect();
+
+        assert_eq!(users, vec!["alice", "alice"]);
+    }
+
+    #[test]
+    fn unique() {
+        let entries = vec!["ls", "git status", "ls", "make", "git status", "ls"]
+            .into_iter()
+            .enumerate()
+            .map(|(index, command)| {
+                let mut entry = entry(command, "/tmp");
+                entry.hist_event = Some(index as u64);
+                entry
+            })
+            .collect::<Vec<_>>();
+
+        let got = |filter: Filter| {
+            filter
+                .filter_entries(entries.clone())
+                .into_iter()
+                .map(|entry| (entry.command, entry.hist_event))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            got(Filter::default().unique(true)),
+            vec![
+                ("make".to_string(), Some(3)),
+                ("git status".to_string(), Some(4)),
+                ("ls".to_string(), Some(5)),
+            ]
+        );
+
+        assert_eq!(
+            got(Filter::default().unique(true).count(2)),
+            vec![
+                ("git status".to_string(), Some(4)),
+                ("ls".to_string(), Some(5)),
+            ]
+        );
     }
 }
@@ -0,0 +1,136 @@
+use serde::Deserialize;
+use std::{
+    path::{
+        Path,
+        PathBuf,
+    },
+    process::Command,
+};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("can not run git: {0}")]
+    Run(std::io::Error),
+
+    #[error("git {0} failed: {1}")]
+    Failed(String, String),
+
+    #[error("can not write replacements for git filter-repo to {0:?}: {1}")]
+    WriteReplacements(PathBuf, std::io::Error),
+}
+
+/// Decides when the server commits the history files of the data dir.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CommitMode {
+    /// Commit after every entry.
+    Individual,
+
+    /// Commit once enough entries were added or the oldest uncommitted entry
+    /// is old enough.
+    Batched,
+
+    /// Never run git.
+    #[default]
+    Off,
+}
+
+/// Returns the lines of `git status --porcelain` for the repository. An empty
+/// list means the working tree matches the last commit.
+pub fn status(repo: impl AsRef<Path>) -> Result<Vec<String>, Error> {
+    let output = run(repo, &["status", "--porcelain"])?;
+
+    Ok(String::from_utf8_lossy(&output)
+        .lines()
+        .map(ToString::to_string)
+        .collect())
+}
+
+/// Returns true when the directory is the top level of a git work tree. A
+/// directory somewhere inside of a work tree is not enough as committing
+/// would also commit unrelated changes of the outer repository.
+pub fn is_repository_root(repo: impl AsRef<Path>) -> bool {
+    let Ok(toplevel) = run(&repo, &["rev-parse", "--show-toplevel"]) else {
+        return false;
+    };
+
+    let toplevel = PathBuf::from(String::from_utf8_lossy(&toplevel).trim());
+
+    match (toplevel.canonicalize(), repo.as_ref().canonicalize()) {
+        (Ok(toplevel), Ok(repo)) => toplevel == repo,
+        _ => false,
+    }
+}
+
+/// Returns the paths of all files in the last commit relative to the
+/// repository root.
+pub fn committed_files(repo: impl AsRef<Path>) -> Result<Vec<PathBuf>, Error> {
+    let output = run(repo, &["ls-tree", "-r", "-z", "--name-only", "HEAD"])?;
+
+    Ok(output
+        .split(|byte| *byte == 0)
+        .filter(|name| !name.is_empty())
+        .map(|name| PathBuf::from(String::from_utf8_lossy(name).to_string()))
+        .collect())
+}
+
+/// Returns the content of the file as it was in the last commit.
+pub fn show_committed(repo: impl AsRef<Path>, file: impl AsRef<Path>) -> Result<Vec<u8>, Error> {
+    let object = format!("HEAD:{}", file.as_ref().to_string_lossy());
+
+    run(repo, &["show", &object])
+}
+
+/// Stages all changes in the repository and commits them with the given
+/// message.
+pub fn commit_all(repo: impl AsRef<Path>, message: &str) -> Result<(), Error> {
+    run(&repo, &["add", "--all"])?;
+    run(&repo, &["commit", "--quiet", "--message", message])?;
+
+    Ok(())
+}
+
+/// Replaces the matches of the regex with `***` in every commit of the
+/// repository using `git filter-repo`, which has to be installed. This
+/// rewrites the history, so clones of the repository have to be replaced.
+pub fn replace_in_history(repo: impl AsRef<Path>, pattern: &str) -> Result<(), Error> {
+    let git_dir = run(&repo, &["rev-parse", "--absolute-git-dir"])?;
+    let replacements =
+        PathBuf::from(String::from_utf8_lossy(&git_dir).trim()).join("hstdb-replacements");
+
+    std::fs::write(&replacements, format!("regex:{pattern}==>***\n"))
+        .map_err(|err| Error::WriteReplacements(replacements.clone(), err))?;
+
+    let result = run(
+        &repo,
+        &[
+            "filter-repo",
+            "--force",
+            "--replace-text",
+            &replacements.to_string_lossy(),
+        ],
+    );
+
+    // The replacements contain the secret so they should not stay around.
+    let _ = std::fs::remove_file(&replacements);
+
+    result.map(|_| ())
+}
+
+fn run(repo: impl AsRef<Path>, args: &[&str]) -> Result<Vec<u8>, Error> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(repo.as_ref())
+        .args(args)
+        .output()
+        .map_err(Error::Run)?;
+
+    if !output.status.success() {
+        return Err(Error::Failed(
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+
+    Ok(output.stdout)
+}
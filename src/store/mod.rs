@@ -1,16 +1,53 @@
 pub mod filter;
+pub mod git;
+#[cfg(feature = "sqlite-backend")]
+pub mod sqlite;
 
 use crate::entry::Entry;
-pub use filter::Filter;
+use chrono::{
+    DateTime,
+    Utc,
+};
+pub use filter::{
+    Filter,
+    HostnameAliases,
+};
+use flate2::{
+    bufread::MultiGzDecoder,
+    write::GzEncoder,
+    Compression,
+};
+use log::warn;
+use regex::Regex;
+use serde::Deserialize;
 use std::{
+    cell::RefCell,
+    collections::{
+        BTreeMap,
+        HashMap,
+        HashSet,
+    },
+    fmt,
     fs,
+    io::{
+        BufRead,
+        Read,
+        Seek,
+        SeekFrom,
+        Write,
+    },
     path::{
         Path,
         PathBuf,
     },
+    time::SystemTime,
 };
 use thiserror::Error;
 
+/// How many bytes are read from the end of a log file at first when only the
+/// newest entries are needed. Doubles until enough entries are read.
+const TAIL_BLOCK_SIZE: u64 = 64 * 1024;
+
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("can not create log folder: {0}")]
@@ -22,6 +59,9 @@ pub enum Error {
     #[error("can not serialize entry: {0}")]
     SerializeEntry(csv::Error),
 
+    #[error("can not flush log file {0:?}: {1}")]
+    FlushIndexFile(PathBuf, std::io::Error),
+
     #[error("glob is not valid: {0}")]
     InvalidGlob(glob::PatternError),
 
@@ -31,31 +71,281 @@ pub enum Error {
     #[error("can not read log file {0:?}: {1}")]
     ReadLogFile(PathBuf, csv::Error),
 
+    #[error("can not read jsonl file {0:?}: {1}")]
+    ReadJsonlFile(PathBuf, serde_json::Error),
+
+    #[error("can not write log file {0:?}: {1}")]
+    WriteLogFile(PathBuf, std::io::Error),
+
+    #[error("can not replace log file {0:?}: {1}")]
+    ReplaceLogFile(PathBuf, std::io::Error),
+
+    #[error("can not remove log file {0:?}: {1}")]
+    RemoveLogFile(PathBuf, std::io::Error),
+
+    #[error(
+        "log file {0:?} has {1} rows that are not valid entries, remove them with `check --rows \
+         --repair` first"
+    )]
+    InvalidRows(PathBuf, usize),
+
+    #[cfg(not(feature = "sqlite-backend"))]
+    #[error("sqlite backend is not enabled in this build")]
+    SqliteBackendDisabled,
+
+    #[cfg(feature = "sqlite-backend")]
+    #[error("can not open sqlite database: {0}")]
+    OpenDatabase(rusqlite::Error),
+
+    #[cfg(feature = "sqlite-backend")]
+    #[error("can not create sqlite database schema: {0}")]
+    CreateDatabaseSchema(rusqlite::Error),
+
+    #[cfg(feature = "sqlite-backend")]
+    #[error("can not insert entry into sqlite database: {0}")]
+    InsertEntry(rusqlite::Error),
+
+    #[cfg(feature = "sqlite-backend")]
+    #[error("can not query entries from sqlite database: {0}")]
+    QueryEntries(rusqlite::Error),
+
+    #[cfg(feature = "sqlite-backend")]
+    #[error("can not update entries in sqlite database: {0}")]
+    UpdateEntries(rusqlite::Error),
+
+    #[cfg(feature = "sqlite-backend")]
+    #[error(
+        "can not store {0} in the sqlite database, only times between 1677 and 2262 are supported"
+    )]
+    TimestampOutOfRange(DateTime<Utc>),
+
     #[error("{0}")]
     Filter(#[from] filter::Error),
+
+    #[error("{0}")]
+    Git(#[from] git::Error),
+}
+
+#[derive(Debug)]
+pub enum GitDiscrepancy {
+    /// A line of `git status --porcelain` for a change that is not committed.
+    Uncommitted(String),
+
+    /// A committed history file that can not be parsed.
+    InvalidCommitted(PathBuf, csv::Error),
+}
+
+impl fmt::Display for GitDiscrepancy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Uncommitted(status) => write!(f, "uncommitted change: {status}"),
+            Self::InvalidCommitted(path, err) => {
+                write!(f, "committed file {} is not valid: {}", path.display(), err)
+            }
+        }
+    }
+}
+
+/// Disk usage of one log file.
+#[derive(Debug, PartialEq, Eq)]
+pub struct FileUsage {
+    pub hostname: String,
+    pub path: PathBuf,
+    pub bytes: u64,
+    pub entries: usize,
+}
+
+/// Rows of one log file that can not be read as an entry.
+#[derive(Debug)]
+pub struct RowCheck {
+    pub hostname: String,
+    pub path: PathBuf,
+    /// Number of rows that are valid entries.
+    pub entries: usize,
+    pub invalid: Vec<InvalidRow>,
+}
+
+#[derive(Debug)]
+pub struct InvalidRow {
+    /// Line of the log file the row starts at, counting from 1.
+    pub line: u64,
+    pub error: csv::Error,
+}
+
+/// Decides in which file new entries are written.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StorageKey {
+    /// One file per host: `<host>.csv`.
+    #[default]
+    Host,
+
+    /// One file per user in a folder per host: `<host>/<user>.csv`.
+    HostUser,
+}
+
+/// Decides where the server writes new entries and where they are read from.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backend {
+    /// CSV files in the data dir, laid out according to the storage key.
+    #[serde(alias = "csv")]
+    #[default]
+    Csv,
+
+    /// One `SQLite` database in the data dir.
+    #[serde(alias = "sqlite")]
+    Sqlite,
+}
+
+/// Adds and reads entries independent of how they are stored.
+pub trait StoreBackend: fmt::Debug + Send {
+    fn add(&self, entry: &Entry) -> Result<(), Error>;
+
+    fn get_entries(&self, filter: &Filter) -> Result<Vec<Entry>, Error>;
+
+    /// Replaces every match of the regex in the stored commands with `***`.
+    /// Returns how many entries were changed in each file.
+    fn scrub(&self, pattern: &Regex) -> Result<Vec<(PathBuf, usize)>, Error>;
+
+    /// Called once an import added all of its entries.
+    fn finish_import(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Opens the store for an import. Unlike `open` the CSV backend does not sync
+/// every entry to disk when it is added but all written log files at once in
+/// `finish_import`.
+pub fn open_import(
+    data_dir: PathBuf,
+    backend: Backend,
+    storage_key: StorageKey,
+) -> Result<Box<dyn StoreBackend>, Error> {
+    match backend {
+        Backend::Csv => Ok(Box::new(
+            new(data_dir).storage_key(storage_key).batch_sync(),
+        )),
+
+        Backend::Sqlite => open(data_dir, backend, storage_key),
+    }
+}
+
+/// Opens the store for the backend. The storage key only decides in which
+/// files the CSV backend writes new entries.
+pub fn open(
+    data_dir: PathBuf,
+    backend: Backend,
+    storage_key: StorageKey,
+) -> Result<Box<dyn StoreBackend>, Error> {
+    match backend {
+        Backend::Csv => Ok(Box::new(new(data_dir).storage_key(storage_key))),
+
+        #[cfg(feature = "sqlite-backend")]
+        Backend::Sqlite => Ok(Box::new(sqlite::open(data_dir)?)),
+
+        #[cfg(not(feature = "sqlite-backend"))]
+        Backend::Sqlite => Err(Error::SqliteBackendDisabled),
+    }
 }
 
 #[derive(Debug)]
 pub struct Store {
     data_dir: PathBuf,
+    storage_key: StorageKey,
+    cache: Option<RefCell<HashMap<PathBuf, CachedLogFile>>>,
+    /// Log files this store added entries to.
+    written: RefCell<HashSet<PathBuf>>,
+    /// Skips syncing every added entry to disk, see `batch_sync`.
+    batch_sync: bool,
+}
+
+/// Parsed entries of a log file together with the modification time and size
+/// the file had when it was parsed.
+#[derive(Debug)]
+struct CachedLogFile {
+    modified: SystemTime,
+    size: u64,
+    entries: Vec<Entry>,
 }
 
-pub const fn new(data_dir: PathBuf) -> Store {
-    Store { data_dir }
+pub fn new(data_dir: PathBuf) -> Store {
+    Store {
+        data_dir,
+        storage_key: StorageKey::Host,
+        cache: None,
+        written: RefCell::default(),
+        batch_sync: false,
+    }
+}
+
+impl StoreBackend for Store {
+    fn add(&self, entry: &Entry) -> Result<(), Error> {
+        Self::add(self, entry)
+    }
+
+    fn get_entries(&self, filter: &Filter) -> Result<Vec<Entry>, Error> {
+        Self::get_entries(self, filter)
+    }
+
+    fn scrub(&self, pattern: &Regex) -> Result<Vec<(PathBuf, usize)>, Error> {
+        Self::scrub(self, pattern)
+    }
+
+    fn finish_import(&self) -> Result<(), Error> {
+        self.sync_written_log_files()?;
+        self.sort_written_log_files()
+    }
 }
 
 impl Store {
+    pub fn storage_key(self, storage_key: StorageKey) -> Self {
+        Self {
+            storage_key,
+            ..self
+        }
+    }
+
+    /// Does not sync every added entry to disk. The log files have to be
+    /// synced with `sync_written_log_files` once all entries were added. Meant
+    /// for imports that add many entries at once.
+    pub fn batch_sync(self) -> Self {
+        Self {
+            batch_sync: true,
+            ..self
+        }
+    }
+
+    /// Keeps the parsed entries of every log file in memory so repeated calls
+    /// to `get_entries` only parse the files that changed in between. Meant
+    /// for long running programs that query the store many times.
+    pub fn with_cache(self) -> Self {
+        Self {
+            cache: Some(RefCell::new(HashMap::new())),
+            ..self
+        }
+    }
+
     pub fn add_entry(&self, entry: &Entry) -> Result<(), Error> {
         let hostname = &entry.hostname;
 
-        let folder_path = self.data_dir.as_path();
         // Can't use .with_extension here as it will not work properly with hostnames
         // that contain dots. See test::dot_filename_with_extension for an
         // example.
-        let file_path = folder_path.join(format!("{}.csv", hostname));
+        let (folder_path, file_path) = match self.storage_key {
+            StorageKey::Host => (
+                self.data_dir.clone(),
+                self.data_dir.join(format!("{hostname}.csv")),
+            ),
+
+            StorageKey::HostUser => {
+                let folder_path = self.data_dir.join(hostname);
+                let file_path = folder_path.join(format!("{}.csv", entry.user));
+
+                (folder_path, file_path)
+            }
+        };
 
         fs::create_dir_all(&folder_path)
-            .map_err(|err| Error::CreateLogFolder(folder_path.to_path_buf(), err))?;
+            .map_err(|err| Error::CreateLogFolder(folder_path.clone(), err))?;
 
         let mut builder = csv::WriterBuilder::new();
 
@@ -70,9 +360,23 @@ impl Store {
             .open(&file_path)
             .map_err(|err| Error::OpenLogFile(file_path.clone(), err))?;
 
-        let mut writer = builder.from_writer(index_file);
+        let mut writer = builder.from_writer(&index_file);
+
+        writer.serialize(entry).map_err(Error::SerializeEntry)?;
+
+        // Make sure the entry is on disk before the message is done so a crash
+        // of the server or the system does not lose it.
+        writer
+            .flush()
+            .map_err(|err| Error::FlushIndexFile(file_path.clone(), err))?;
 
-        writer.serialize(&entry).map_err(Error::SerializeEntry)?;
+        if !self.batch_sync {
+            index_file
+                .sync_data()
+                .map_err(|err| Error::FlushIndexFile(file_path.clone(), err))?;
+        }
+
+        self.written.borrow_mut().insert(file_path);
 
         Ok(())
     }
@@ -88,28 +392,69 @@ impl Store {
     }
 
     pub fn get_entries(&self, filter: &Filter) -> Result<Vec<Entry>, Error> {
-        let mut entries: Vec<_> = if let Some(hostname) = filter.get_hostname() {
-            let index_path = self.data_dir.join(format!("{}.csv", hostname));
+        // New entries are appended to the log files so the newest entries are
+        // at their end. When the filter can be checked on single entries we
+        // only have to read enough of the end to fill the count.
+        let tail = filter.count > 0 && filter.matches_columns_only();
 
-            Self::read_log_file(index_path)?
-        } else {
-            let glob_string = self.data_dir.join("*.csv");
+        if self.cache.is_none() && !tail {
+            let mut entries = self.query(filter).collect::<Result<Vec<_>, Error>>()?;
+            entries.sort();
 
-            let glob = glob::glob(&glob_string.to_string_lossy()).map_err(Error::InvalidGlob)?;
+            return Ok(filter.limit_entries(entries));
+        }
 
-            let index_paths = glob
-                .collect::<Result<Vec<PathBuf>, glob::GlobError>>()
-                .map_err(Error::GlobIteration)?;
+        let aliases = &filter.hostname_aliases;
+        let hostnames = filter.get_hostnames();
 
-            index_paths
-                .into_iter()
-                .map(Self::read_log_file)
-                .collect::<Result<Vec<Vec<_>>, Error>>()?
-                .into_iter()
-                .flatten()
-                .collect()
+        let read = |path| {
+            if let Some(cache) = &self.cache {
+                Self::read_log_file_cached(cache, path)
+            } else if tail && !is_jsonl(&path) && !is_gzip(&path) {
+                Self::read_log_file_tail(path, filter)
+            } else {
+                Self::read_log_file(path)
+            }
         };
 
+        let mut entries: Vec<_> = self
+            .filter_log_file_paths(filter)?
+            .into_iter()
+            .map(read)
+            .collect::<Result<Vec<Vec<_>>, Error>>()?
+            .into_iter()
+            .flatten()
+            .collect();
+
+        // Jsonl files are not named after a host and can contain entries of
+        // any host so they have to be filtered by the hostname of the entries.
+        let mut jsonl_entries: Vec<_> = self
+            .jsonl_file_paths()?
+            .into_iter()
+            .map(read)
+            .collect::<Result<Vec<Vec<_>>, Error>>()?
+            .into_iter()
+            .flatten()
+            .collect();
+
+        if !hostnames.is_empty() {
+            let canonical: Vec<_> = hostnames
+                .iter()
+                .map(|hostname| aliases.canonical_hostname(hostname))
+                .collect();
+
+            jsonl_entries
+                .retain(|entry| canonical.contains(&aliases.canonical_hostname(&entry.hostname)));
+        }
+
+        entries.append(&mut jsonl_entries);
+
+        if !aliases.is_empty() {
+            for entry in &mut entries {
+                entry.hostname = aliases.canonical_hostname(&entry.hostname);
+            }
+        }
+
         entries.sort();
 
         let entries = filter.filter_entries(entries);
@@ -117,37 +462,2059 @@ impl Store {
         Ok(entries)
     }
 
-    fn read_log_file<P: AsRef<Path>>(file_path: P) -> Result<Vec<Entry>, Error> {
-        let file = std::fs::File::open(&file_path)
-            .map_err(|err| Error::OpenLogFile(file_path.as_ref().to_path_buf(), err))?;
+    /// Returns the entries that match the predicates of the filter without
+    /// loading all of them into memory. The log files are read one after the
+    /// other and the entries are returned in the order they are stored, so
+    /// they are not sorted and neither the count nor `unique` of the filter
+    /// are applied. Rows that are not valid entries are skipped with a warning
+    /// like with `get_entries`.
+    pub fn query<'a>(
+        &'a self,
+        filter: &'a Filter,
+    ) -> impl Iterator<Item = Result<Entry, Error>> + 'a {
+        let paths = self
+            .filter_log_file_paths(filter)
+            .and_then(|log_file_paths| {
+                let jsonl_file_paths = self.jsonl_file_paths()?;
+
+                Ok(log_file_paths.into_iter().chain(jsonl_file_paths))
+            });
+
+        let (paths, error) = match paths {
+            Ok(paths) => (Some(paths), None),
+            Err(err) => (None, Some(Err(err))),
+        };
+
+        let aliases = &filter.hostname_aliases;
+        let canonical: Vec<_> = filter
+            .get_hostnames()
+            .iter()
+            .map(|hostname| aliases.canonical_hostname(hostname))
+            .collect();
+
+        error
+            .into_iter()
+            .chain(paths.into_iter().flatten().flat_map(Self::query_log_file))
+            .filter_map(move |entry| {
+                let mut entry = match entry {
+                    Ok((entry, jsonl)) => {
+                        // Jsonl files can contain entries of any host.
+                        if jsonl
+                            && !canonical.is_empty()
+                            && !canonical.contains(&aliases.canonical_hostname(&entry.hostname))
+                        {
+                            return None;
+                        }
+
+                        entry
+                    }
+                    Err(err) => return Some(Err(err)),
+                };
+
+                if !aliases.is_empty() {
+                    entry.hostname = aliases.canonical_hostname(&entry.hostname);
+                }
+
+                if filter.matches(&entry) {
+                    Some(Ok(entry))
+                } else {
+                    None
+                }
+            })
+    }
+
+    /// Returns the entries of the log file one by one together with whether
+    /// they come from a jsonl file.
+    fn query_log_file(
+        file_path: PathBuf,
+    ) -> Box<dyn Iterator<Item = Result<(Entry, bool), Error>>> {
+        let file = match fs::File::open(&file_path) {
+            Ok(file) => file,
+            Err(err) => return Box::new(std::iter::once(Err(Error::OpenLogFile(file_path, err)))),
+        };
 
         let reader = std::io::BufReader::new(file);
 
-        Self::read_metadata(reader)
-            .map_err(|err| Error::ReadLogFile(file_path.as_ref().to_path_buf(), err))
+        if is_jsonl(&file_path) {
+            return Box::new(
+                serde_json::Deserializer::from_reader(reader)
+                    .into_iter()
+                    .map(move |entry| {
+                        entry
+                            .map(|entry| (entry, true))
+                            .map_err(|err| Error::ReadJsonlFile(file_path.clone(), err))
+                    }),
+            );
+        }
+
+        let reader: Box<dyn std::io::Read> = if is_gzip(&file_path) {
+            Box::new(MultiGzDecoder::new(reader))
+        } else {
+            Box::new(reader)
+        };
+
+        let (csv_reader, headers) = match Self::csv_reader(reader) {
+            Ok(csv_reader) => csv_reader,
+            Err(err) => return Box::new(std::iter::once(Err(Error::ReadLogFile(file_path, err)))),
+        };
+
+        let records = csv_reader
+            .into_records()
+            .map(move |record| record.and_then(|record| deserialize_record(&record, &headers)));
+
+        Box::new(records.filter_map(move |entry| match entry {
+            Ok(entry) => Some(Ok((entry, false))),
+            Err(err) if err.is_io_error() => Some(Err(Error::ReadLogFile(file_path.clone(), err))),
+            Err(err) => {
+                warn!(
+                    "skipping line {} of {} as it is not a valid entry: {}",
+                    err.position().map_or(0, csv::Position::line),
+                    file_path.display(),
+                    err
+                );
+
+                None
+            }
+        }))
+    }
+
+    /// Returns the paths of the log files of the hosts of the filter or of
+    /// every host if the filter has no hostnames.
+    fn filter_log_file_paths(&self, filter: &Filter) -> Result<Vec<PathBuf>, Error> {
+        let aliases = &filter.hostname_aliases;
+        let hostnames = filter.get_hostnames();
+
+        Ok(if hostnames.is_empty() {
+            self.log_file_paths()?
+        } else {
+            let mut paths = Vec::new();
+
+            for hostname in hostnames {
+                for path in self.host_log_file_paths(hostname, aliases)? {
+                    // Hosts that share a canonical hostname share their files.
+                    if !paths.contains(&path) {
+                        paths.push(path);
+                    }
+                }
+            }
+
+            paths
+        })
     }
 
-    fn read_metadata<R: std::io::Read>(reader: R) -> Result<Vec<Entry>, csv::Error> {
-        let mut csv_reader = csv::ReaderBuilder::new().from_reader(reader);
+    /// Marks the entries that match the filter as deleted at the given time.
+    /// They stay in the log files until `gc` removes them. Returns how many
+    /// entries were marked in each log file. With `dry_run` the log files are
+    /// not changed.
+    pub fn delete(
+        &self,
+        filter: &Filter,
+        time_deleted: DateTime<Utc>,
+        dry_run: bool,
+    ) -> Result<Vec<(PathBuf, usize)>, Error> {
+        let mut deleted = Vec::new();
+
+        for path in self.filter_log_file_paths(filter)? {
+            // Unknown hosts have no log file that could contain entries.
+            if !path.exists() {
+                continue;
+            }
+
+            let mut entries = Self::read_log_file_strict(&path)?;
+            let mut count = 0;
+
+            for entry in &mut entries {
+                if !entry.deleted && filter.matches(entry) {
+                    entry.deleted = true;
+                    entry.time_deleted = Some(time_deleted);
+                    count += 1;
+                }
+            }
+
+            if count == 0 {
+                continue;
+            }
+
+            deleted.push((path.clone(), count));
 
-        csv_reader
-            .deserialize()
-            .collect::<Result<Vec<Entry>, csv::Error>>()
+            if !dry_run {
+                Self::write_log_file(path, &entries)?;
+            }
+        }
+
+        Ok(deleted)
     }
-}
 
-#[cfg(test)]
-mod test {
-    #[test]
-    fn dot_filename_with_extension() {
-        let folder_path = std::path::PathBuf::from("/tmp");
-        let hostname = "test.test.test";
-        let expected = std::path::PathBuf::from(format!("/tmp/{}.csv", hostname));
+    /// Removes entries that were marked as deleted before the given time.
+    /// Entries deleted before the deletion time was stored count as deleted
+    /// when they finished. Returns how many entries were removed from each log
+    /// file.
+    pub fn gc(&self, before: DateTime<Utc>) -> Result<Vec<(PathBuf, usize)>, Error> {
+        let mut removed = Vec::new();
 
-        let bad = folder_path.join(hostname).with_extension("csv");
-        let good = folder_path.join(format!("{}.csv", hostname));
+        for path in self.log_file_paths()? {
+            let entries = Self::read_log_file_strict(&path)?;
+            let count = entries.len();
 
-        assert_ne!(bad, expected);
-        assert_eq!(good, expected);
+            let kept: Vec<_> = entries
+                .into_iter()
+                .filter(|entry| {
+                    !entry.deleted || entry.time_deleted.unwrap_or(entry.time_finished) >= before
+                })
+                .collect();
+
+            if kept.len() == count {
+                continue;
+            }
+
+            removed.push((path.clone(), count - kept.len()));
+            Self::write_log_file(path, &kept)?;
+        }
+
+        Ok(removed)
+    }
+
+    /// Removes the oldest entries of every host that has more than the given
+    /// number of entries. Returns how many entries were removed from each log
+    /// file.
+    pub fn trim(&self, max_entries: usize) -> Result<Vec<(PathBuf, usize)>, Error> {
+        self.prune(None, Some(max_entries), false)
+    }
+
+    /// Removes the entries of every host that finished before the given time
+    /// and the oldest entries of hosts that have more than `keep_last`
+    /// entries. Returns how many entries were removed from each log file. With
+    /// `dry_run` the counts are returned without changing any file.
+    pub fn prune(
+        &self,
+        before: Option<DateTime<Utc>>,
+        keep_last: Option<usize>,
+        dry_run: bool,
+    ) -> Result<Vec<(PathBuf, usize)>, Error> {
+        let mut hosts: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+        for (hostname, path) in self.host_files()? {
+            hosts.entry(hostname).or_default().push(path);
+        }
+
+        let mut removed = Vec::new();
+
+        for paths in hosts.into_values() {
+            let files = paths
+                .into_iter()
+                .map(|path| Ok((Self::read_log_file_strict(&path)?, path)))
+                .collect::<Result<Vec<_>, Error>>()?;
+
+            // With the user layout the entries of a host are spread over
+            // multiple files so the oldest entries are picked across them.
+            let mut times: Vec<_> = files
+                .iter()
+                .enumerate()
+                .flat_map(|(file_index, (entries, _))| {
+                    entries
+                        .iter()
+                        .enumerate()
+                        .map(move |(index, entry)| (entry.time_finished, file_index, index))
+                })
+                .collect();
+            times.sort_unstable();
+
+            let count = times.len();
+            let oldest = keep_last.map_or(0, |keep_last| count.saturating_sub(keep_last));
+
+            let remove: HashSet<_> = times
+                .into_iter()
+                .enumerate()
+                .filter(|(position, (time_finished, ..))| {
+                    *position < oldest || before.is_some_and(|before| *time_finished < before)
+                })
+                .map(|(_, (_, file_index, index))| (file_index, index))
+                .collect();
+
+            if remove.is_empty() {
+                continue;
+            }
+
+            for (file_index, (entries, path)) in files.into_iter().enumerate() {
+                let count = entries.len();
+
+                let kept: Vec<_> = entries
+                    .into_iter()
+                    .enumerate()
+                    .filter(|(index, _)| !remove.contains(&(file_index, *index)))
+                    .map(|(_, entry)| entry)
+                    .collect();
+
+                if kept.len() == count {
+                    continue;
+                }
+
+                removed.push((path.clone(), count - kept.len()));
+
+                if !dry_run {
+                    Self::write_log_file(path, &kept)?;
+                }
+            }
+        }
+
+        Ok(removed)
+    }
+
+    /// Replaces every match of the regex in the commands of all log and jsonl
+    /// files with `***`. Returns how many entries were changed in each file.
+    pub fn scrub(&self, pattern: &Regex) -> Result<Vec<(PathBuf, usize)>, Error> {
+        let mut scrubbed = Vec::new();
+
+        for path in self
+            .log_file_paths()?
+            .into_iter()
+            .chain(self.jsonl_file_paths()?)
+        {
+            let mut entries = Self::read_log_file_strict(&path)?;
+            let mut count = 0;
+
+            for entry in &mut entries {
+                if pattern.is_match(&entry.command) {
+                    entry.command = pattern.replace_all(&entry.command, "***").to_string();
+                    count += 1;
+                }
+            }
+
+            if count == 0 {
+                continue;
+            }
+
+            scrubbed.push((path.clone(), count));
+            Self::write_log_file(path, &entries)?;
+        }
+
+        Ok(scrubbed)
+    }
+
+    /// Syncs the log files this store added entries to to disk.
+    pub fn sync_written_log_files(&self) -> Result<(), Error> {
+        for path in self.written.borrow().iter() {
+            fs::File::open(path)
+                .and_then(|file| file.sync_data())
+                .map_err(|err| Error::FlushIndexFile(path.clone(), err))?;
+        }
+
+        Ok(())
+    }
+
+    /// Sorts the entries of the log files this store added entries to if they
+    /// are not sorted. Importers append older entries to the log files which
+    /// would otherwise hide them from reading only the end of a file. Files of
+    /// other hosts, for example synced with git, are left as they are.
+    pub fn sort_written_log_files(&self) -> Result<(), Error> {
+        let mut paths: Vec<_> = self.written.borrow().iter().cloned().collect();
+        paths.sort();
+
+        for path in paths {
+            let mut entries = Self::read_log_file_strict(&path)?;
+
+            if is_sorted(&entries) {
+                continue;
+            }
+
+            entries.sort();
+            Self::write_log_file(path, &entries)?;
+        }
+
+        Ok(())
+    }
+
+    /// Compresses every plain log file that was last changed before the given
+    /// time into a `.gz` file next to it. Entries of an existing compressed
+    /// file are kept. Returns the paths of the compressed files.
+    pub fn compress(&self, before: DateTime<Utc>) -> Result<Vec<PathBuf>, Error> {
+        let mut compressed = Vec::new();
+
+        for path in self.log_file_paths()? {
+            if is_gzip(&path) {
+                continue;
+            }
+
+            let modified = fs::metadata(&path)
+                .and_then(|metadata| metadata.modified())
+                .map_err(|err| Error::OpenLogFile(path.clone(), err))?;
+
+            if DateTime::<Utc>::from(modified) >= before {
+                continue;
+            }
+
+            let mut gzip_path = path.as_os_str().to_owned();
+            gzip_path.push(".gz");
+            let gzip_path = PathBuf::from(gzip_path);
+
+            let mut entries = if gzip_path.exists() {
+                Self::read_log_file_strict(&gzip_path)?
+            } else {
+                Vec::new()
+            };
+            entries.append(&mut Self::read_log_file_strict(&path)?);
+
+            if entries.is_empty() {
+                continue;
+            }
+
+            entries.sort();
+            Self::write_log_file(&gzip_path, &entries)?;
+
+            fs::remove_file(&path).map_err(|err| Error::RemoveLogFile(path.clone(), err))?;
+
+            compressed.push(gzip_path);
+        }
+
+        Ok(compressed)
+    }
+
+    /// Reads every row of the log files on its own and returns the rows that
+    /// can not be read as an entry sorted by hostname. With `repair` the log
+    /// files are rewritten without those rows.
+    pub fn check_rows(&self, repair: bool) -> Result<Vec<RowCheck>, Error> {
+        let mut checks = self
+            .host_files()?
+            .into_iter()
+            .map(|(hostname, path)| {
+                let (entries, invalid) = Self::read_log_file_rows(&path)?;
+
+                if repair && !invalid.is_empty() {
+                    Self::write_log_file(&path, &entries)?;
+                }
+
+                Ok(RowCheck {
+                    hostname,
+                    path,
+                    entries: entries.len(),
+                    invalid,
+                })
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        checks.sort_by(|a, b| (&a.hostname, &a.path).cmp(&(&b.hostname, &b.path)));
+
+        Ok(checks)
+    }
+
+    /// Checks that the data directory matches what is committed in its git
+    /// repository and that every committed history file can be parsed.
+    pub fn verify_git(&self) -> Result<Vec<GitDiscrepancy>, Error> {
+        let mut discrepancies: Vec<_> = git::status(&self.data_dir)?
+            .into_iter()
+            .map(GitDiscrepancy::Uncommitted)
+            .collect();
+
+        for file in git::committed_files(&self.data_dir)? {
+            if !is_gzip(&file)
+                && file
+                    .extension()
+                    .map_or(true, |extension| extension != "csv")
+            {
+                continue;
+            }
+
+            let data = git::show_committed(&self.data_dir, &file)?;
+
+            let parsed = if is_gzip(&file) {
+                Self::read_rows(MultiGzDecoder::new(data.as_slice()))
+            } else {
+                Self::read_rows(data.as_slice())
+            };
+
+            // Reading skips invalid rows but a committed file should not have
+            // any.
+            let error = match parsed {
+                Ok((_, invalid)) => invalid.into_iter().next().map(|row| row.error),
+                Err(err) => Some(err),
+            };
+
+            if let Some(err) = error {
+                discrepancies.push(GitDiscrepancy::InvalidCommitted(file, err));
+            }
+        }
+
+        Ok(discrepancies)
+    }
+
+    /// Returns true when the data dir is the top level of a git repository.
+    pub fn is_git_repository(&self) -> bool {
+        git::is_repository_root(&self.data_dir)
+    }
+
+    /// Returns true when the git repository of the data dir has changes that
+    /// are not committed.
+    pub fn has_uncommitted_changes(&self) -> Result<bool, Error> {
+        Ok(!git::status(&self.data_dir)?.is_empty())
+    }
+
+    pub fn commit(&self, message: &str) -> Result<(), Error> {
+        git::commit_all(&self.data_dir, message)?;
+
+        Ok(())
+    }
+
+    /// Replaces every match of the regex with `***` in all commits of the git
+    /// repository of the data dir.
+    pub fn scrub_history(&self, pattern: &Regex) -> Result<(), Error> {
+        git::replace_in_history(&self.data_dir, pattern.as_str())?;
+
+        Ok(())
+    }
+
+    /// Returns the hostname and path of every log file in the data dir. Files
+    /// of both storage key layouts are returned so files written with a
+    /// different setting are still found. With `HostUser` a host can have
+    /// more than one file.
+    pub fn host_files(&self) -> Result<Vec<(String, PathBuf)>, Error> {
+        // Compressed files are read like the plain files of the same host.
+        let host_files = Self::glob(self.data_dir.join("*.csv"))?
+            .into_iter()
+            .chain(Self::glob(self.data_dir.join("*.csv.gz"))?)
+            .filter_map(|path| {
+                // Can't use .file_stem here as it will not work properly with
+                // hostnames that contain dots.
+                let file_name = path.file_name()?.to_string_lossy();
+                let hostname = file_name
+                    .strip_suffix(".csv.gz")
+                    .or_else(|| file_name.strip_suffix(".csv"))?
+                    .to_string();
+
+                Some((hostname, path))
+            });
+
+        let user_files = Self::glob(self.data_dir.join("*").join("*.csv"))?
+            .into_iter()
+            .chain(Self::glob(self.data_dir.join("*").join("*.csv.gz"))?)
+            .filter_map(|path| {
+                let hostname = path.parent()?.file_name()?.to_string_lossy().to_string();
+
+                Some((hostname, path))
+            });
+
+        Ok(host_files.chain(user_files).collect())
+    }
+
+    /// Returns the size and number of entries of every log file sorted by
+    /// hostname. Deleted entries are counted as they still take up space.
+    pub fn usage(&self) -> Result<Vec<FileUsage>, Error> {
+        let mut usage = self
+            .host_files()?
+            .into_iter()
+            .map(|(hostname, path)| {
+                let bytes = fs::metadata(&path)
+                    .map_err(|err| Error::OpenLogFile(path.clone(), err))?
+                    .len();
+                let entries = Self::read_log_file(&path)?.len();
+
+                Ok(FileUsage {
+                    hostname,
+                    path,
+                    bytes,
+                    entries,
+                })
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        usage.sort_by(|a, b| (&a.hostname, &a.path).cmp(&(&b.hostname, &b.path)));
+
+        Ok(usage)
+    }
+
+    fn log_file_paths(&self) -> Result<Vec<PathBuf>, Error> {
+        Ok(self
+            .host_files()?
+            .into_iter()
+            .map(|(_, path)| path)
+            .collect())
+    }
+
+    /// Returns the paths of the jsonl files in the data dir. Every line of them
+    /// is one entry, like the files written by `export --format ndjson`.
+    fn jsonl_file_paths(&self) -> Result<Vec<PathBuf>, Error> {
+        Self::glob(self.data_dir.join("*.jsonl"))
+    }
+
+    /// Returns the paths of all log files of the given host and of the hosts
+    /// that have the same canonical hostname.
+    fn host_log_file_paths(
+        &self,
+        hostname: &str,
+        aliases: &HostnameAliases,
+    ) -> Result<Vec<PathBuf>, Error> {
+        let canonical = aliases.canonical_hostname(hostname);
+
+        let paths: Vec<_> = self
+            .host_files()?
+            .into_iter()
+            .filter(|(host, _)| aliases.canonical_hostname(host) == canonical)
+            .map(|(_, path)| path)
+            .collect();
+
+        // Keep the host file even if it does not exist so reading an unknown
+        // host fails like it used to.
+        if paths.is_empty() {
+            return Ok(vec![self.data_dir.join(format!("{hostname}.csv"))]);
+        }
+
+        Ok(paths)
+    }
+
+    fn glob(pattern: impl AsRef<Path>) -> Result<Vec<PathBuf>, Error> {
+        let glob = glob::glob(&pattern.as_ref().to_string_lossy()).map_err(Error::InvalidGlob)?;
+
+        glob.collect::<Result<Vec<PathBuf>, glob::GlobError>>()
+            .map_err(Error::GlobIteration)
+    }
+
+    /// Replaces the content of the log file with the given entries. The
+    /// entries are written to a temporary file first which is then renamed
+    /// over the log file so readers never see a partially written file.
+    fn write_log_file(file_path: impl AsRef<Path>, entries: &[Entry]) -> Result<(), Error> {
+        let file_path = file_path.as_ref();
+
+        // Without entries the csv writer would not write a header. As the
+        // header is only written for new files we remove the file instead so
+        // the next entry recreates it properly.
+        if entries.is_empty() {
+            return fs::remove_file(file_path)
+                .map_err(|err| Error::RemoveLogFile(file_path.to_path_buf(), err));
+        }
+
+        let mut tmp_path = file_path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+
+        let tmp_file = fs::File::create(&tmp_path)
+            .map_err(|err| Error::WriteLogFile(tmp_path.clone(), err))?;
+
+        let tmp_file = if is_jsonl(file_path) {
+            Self::write_jsonl_entries(tmp_file, &tmp_path, entries)?
+        } else if is_gzip(file_path) {
+            Self::write_entries(
+                GzEncoder::new(tmp_file, Compression::default()),
+                &tmp_path,
+                entries,
+            )?
+            .finish()
+            .map_err(|err| Error::WriteLogFile(tmp_path.clone(), err))?
+        } else {
+            Self::write_entries(tmp_file, &tmp_path, entries)?
+        };
+
+        // The content has to be on disk before the rename so a crash can not
+        // leave an empty or partial log file behind.
+        tmp_file
+            .sync_all()
+            .map_err(|err| Error::WriteLogFile(tmp_path.clone(), err))?;
+
+        fs::rename(&tmp_path, file_path)
+            .map_err(|err| Error::ReplaceLogFile(file_path.to_path_buf(), err))?;
+
+        Ok(())
+    }
+
+    fn write_entries<W: Write>(writer: W, path: &Path, entries: &[Entry]) -> Result<W, Error> {
+        let mut writer = csv::Writer::from_writer(writer);
+
+        for entry in entries {
+            writer.serialize(entry).map_err(Error::SerializeEntry)?;
+        }
+
+        writer
+            .flush()
+            .map_err(|err| Error::WriteLogFile(path.to_path_buf(), err))?;
+
+        // Everything has been flushed already so this can only fail like the
+        // flush above.
+        writer.into_inner().map_err(|err| {
+            Error::WriteLogFile(
+                path.to_path_buf(),
+                std::io::Error::new(err.error().kind(), err.to_string()),
+            )
+        })
+    }
+
+    fn write_jsonl_entries<W: Write>(
+        writer: W,
+        path: &Path,
+        entries: &[Entry],
+    ) -> Result<W, Error> {
+        let write_error = |err| Error::WriteLogFile(path.to_path_buf(), err);
+        let mut writer = std::io::BufWriter::new(writer);
+
+        for entry in entries {
+            serde_json::to_writer(&mut writer, entry)
+                .map_err(|err| write_error(std::io::Error::from(err)))?;
+            writer.write_all(b"\n").map_err(write_error)?;
+        }
+
+        writer
+            .into_inner()
+            .map_err(|err| write_error(std::io::Error::new(err.error().kind(), err.to_string())))
+    }
+
+    fn read_log_file<P: AsRef<Path>>(file_path: P) -> Result<Vec<Entry>, Error> {
+        let file = std::fs::File::open(&file_path)
+            .map_err(|err| Error::OpenLogFile(file_path.as_ref().to_path_buf(), err))?;
+
+        let reader = std::io::BufReader::new(file);
+
+        if is_jsonl(file_path.as_ref()) {
+            return serde_json::Deserializer::from_reader(reader)
+                .into_iter()
+                .collect::<Result<Vec<Entry>, _>>()
+                .map_err(|err| Error::ReadJsonlFile(file_path.as_ref().to_path_buf(), err));
+        }
+
+        let entries = if is_gzip(file_path.as_ref()) {
+            Self::read_metadata(file_path.as_ref(), MultiGzDecoder::new(reader))
+        } else {
+            Self::read_metadata(file_path.as_ref(), reader)
+        };
+
+        entries.map_err(|err| Error::ReadLogFile(file_path.as_ref().to_path_buf(), err))
+    }
+
+    /// Reads the entries of a log file that is rewritten afterwards. Fails if
+    /// the file has rows that are not valid entries as the rewrite would drop
+    /// them, only `check --rows --repair` removes those.
+    fn read_log_file_strict(file_path: &Path) -> Result<Vec<Entry>, Error> {
+        // Invalid lines of jsonl files already fail the read.
+        if is_jsonl(file_path) {
+            return Self::read_log_file(file_path);
+        }
+
+        let (entries, invalid) = Self::read_log_file_rows(file_path)?;
+
+        if !invalid.is_empty() {
+            return Err(Error::InvalidRows(file_path.to_path_buf(), invalid.len()));
+        }
+
+        Ok(entries)
+    }
+
+    fn read_log_file_rows(file_path: &Path) -> Result<(Vec<Entry>, Vec<InvalidRow>), Error> {
+        let file = fs::File::open(file_path)
+            .map_err(|err| Error::OpenLogFile(file_path.to_path_buf(), err))?;
+        let reader = std::io::BufReader::new(file);
+
+        let rows = if is_gzip(file_path) {
+            Self::read_rows(MultiGzDecoder::new(reader))
+        } else {
+            Self::read_rows(reader)
+        };
+
+        rows.map_err(|err| Error::ReadLogFile(file_path.to_path_buf(), err))
+    }
+
+    /// Returns the cached entries of the log file if it did not change since it
+    /// was parsed last and parses it again otherwise.
+    fn read_log_file_cached(
+        cache: &RefCell<HashMap<PathBuf, CachedLogFile>>,
+        file_path: PathBuf,
+    ) -> Result<Vec<Entry>, Error> {
+        let metadata =
+            fs::metadata(&file_path).map_err(|err| Error::OpenLogFile(file_path.clone(), err))?;
+        let modified = metadata
+            .modified()
+            .map_err(|err| Error::OpenLogFile(file_path.clone(), err))?;
+        let size = metadata.len();
+
+        if let Some(cached) = cache.borrow().get(&file_path) {
+            if cached.modified == modified && cached.size == size {
+                return Ok(cached.entries.clone());
+            }
+        }
+
+        let entries = Self::read_log_file(&file_path)?;
+
+        cache.borrow_mut().insert(
+            file_path,
+            CachedLogFile {
+                modified,
+                size,
+                entries: entries.clone(),
+            },
+        );
+
+        Ok(entries)
+    }
+
+    /// Reads entries from the end of the log file until enough of them match
+    /// the filter. Reads the whole file if the end does not start at a record
+    /// or the entries are not sorted.
+    fn read_log_file_tail(
+        file_path: impl AsRef<Path>,
+        filter: &Filter,
+    ) -> Result<Vec<Entry>, Error> {
+        let file_path = file_path.as_ref();
+        let open_error = |err| Error::OpenLogFile(file_path.to_path_buf(), err);
+        let read_error = |err| Error::ReadLogFile(file_path.to_path_buf(), csv::Error::from(err));
+
+        let mut file = fs::File::open(file_path).map_err(open_error)?;
+        let length = file.metadata().map_err(open_error)?.len();
+
+        let mut reader = std::io::BufReader::new(&file);
+        let mut header = Vec::new();
+        reader.read_until(b'\n', &mut header).map_err(read_error)?;
+
+        let Ok(Some(first)) = Self::read_first(header.as_slice().chain(reader)) else {
+            return Self::read_log_file(file_path);
+        };
+
+        let header_length = header.len() as u64;
+        let mut start = length;
+        let mut block_size = TAIL_BLOCK_SIZE;
+        let mut data = Vec::new();
+
+        loop {
+            let block_start = start.saturating_sub(block_size).max(header_length);
+            if block_start == header_length {
+                return Self::read_log_file(file_path);
+            }
+
+            #[allow(clippy::cast_possible_truncation)]
+            let mut block = vec![0; (start - block_start) as usize];
+            file.seek(SeekFrom::Start(block_start))
+                .map_err(read_error)?;
+            file.read_exact(&mut block).map_err(read_error)?;
+
+            block.append(&mut data);
+            data = block;
+            start = block_start;
+            block_size *= 2;
+
+            let Some(boundary) = record_boundary(&data) else {
+                continue;
+            };
+
+            // Invalid rows are only skipped with a warning when the whole file
+            // is read, they could also come from a wrong record boundary.
+            let entries = match Self::read_rows(header.as_slice().chain(&data[boundary..])) {
+                Ok((entries, invalid)) if invalid.is_empty() => entries,
+                _ => return Self::read_log_file(file_path),
+            };
+
+            // Entries that are older than the first entry of the file or out of
+            // order were not appended by the server so newer entries might be
+            // anywhere in the file.
+            if !is_sorted(&entries) || entries.first().is_some_and(|entry| entry < &first) {
+                return Self::read_log_file(file_path);
+            }
+
+            if filter.filter_entries(entries.clone()).len() >= filter.count {
+                return Ok(entries);
+            }
+        }
+    }
+
+    fn read_first<R: std::io::Read>(reader: R) -> Result<Option<Entry>, csv::Error> {
+        let (mut csv_reader, headers) = Self::csv_reader(reader)?;
+        let mut record = csv::StringRecord::new();
+
+        if !csv_reader.read_record(&mut record)? {
+            return Ok(None);
+        }
+
+        deserialize_record(&record, &headers).map(Some)
+    }
+
+    /// Returns a reader for the rows of a log file together with the headers
+    /// to deserialize them with. The header is only written when a file is
+    /// created so files created before fields were added to an entry keep
+    /// their shorter header while the rows appended later have the new fields
+    /// at the end. Those rows are read by position with the current header.
+    fn csv_reader<R: std::io::Read>(
+        reader: R,
+    ) -> Result<(csv::Reader<R>, csv::StringRecord), csv::Error> {
+        // Rows appended after new fields were added to an entry can be longer
+        // than the header of an existing file, so we have to allow records
+        // with a varying number of fields.
+        let mut csv_reader = csv::ReaderBuilder::new().flexible(true).from_reader(reader);
+        let headers = csv_reader.headers()?;
+
+        let outdated = headers.len() < ENTRY_HEADERS.len()
+            && headers
+                .iter()
+                .zip(ENTRY_HEADERS)
+                .all(|(header, current)| header == *current);
+
+        let headers = if outdated {
+            csv::StringRecord::from(ENTRY_HEADERS.to_vec())
+        } else {
+            headers.clone()
+        };
+
+        Ok((csv_reader, headers))
+    }
+
+    /// Reads the entries of the log file and skips the rows that are not valid
+    /// entries with a warning so one corrupt row does not hide the whole
+    /// history.
+    fn read_metadata<R: std::io::Read>(
+        file_path: &Path,
+        reader: R,
+    ) -> Result<Vec<Entry>, csv::Error> {
+        let (entries, invalid) = Self::read_rows(reader)?;
+
+        for row in invalid {
+            warn!(
+                "skipping line {} of {} as it is not a valid entry: {}",
+                row.line,
+                file_path.display(),
+                row.error
+            );
+        }
+
+        Ok(entries)
+    }
+
+    /// Reads the rows one by one so a row that is not a valid entry does not
+    /// stop the rows after it from being read. Only fails if the data can not
+    /// be read at all.
+    fn read_rows<R: std::io::Read>(reader: R) -> Result<(Vec<Entry>, Vec<InvalidRow>), csv::Error> {
+        let (mut csv_reader, headers) = Self::csv_reader(reader)?;
+
+        let mut entries = Vec::new();
+        let mut invalid = Vec::new();
+        let mut record = csv::StringRecord::new();
+
+        loop {
+            let error = match csv_reader.read_record(&mut record) {
+                Ok(false) => break,
+                Ok(true) => match deserialize_record(&record, &headers) {
+                    Ok(entry) => {
+                        entries.push(entry);
+                        continue;
+                    }
+                    Err(err) => err,
+                },
+                Err(err) if err.is_io_error() => return Err(err),
+                Err(err) => err,
+            };
+
+            let line = error
+                .position()
+                .or_else(|| record.position())
+                .map_or(0, csv::Position::line);
+
+            invalid.push(InvalidRow { line, error });
+        }
+
+        Ok((entries, invalid))
+    }
+}
+
+/// The columns of a log file in the order the fields of an entry are written.
+const ENTRY_HEADERS: &[&str] = &[
+    "time_finished",
+    "time_start",
+    "hostname",
+    "command",
+    "pwd",
+    "result",
+    "session_id",
+    "user",
+    "hist_event",
+    "deleted",
+    "duration_ms",
+    "tag",
+    "seq",
+    "time_deleted",
+];
+
+/// Deserializes the row with only as many headers as it has fields so the
+/// fields an older row is missing get their default value.
+fn deserialize_record(
+    record: &csv::StringRecord,
+    headers: &csv::StringRecord,
+) -> Result<Entry, csv::Error> {
+    if record.len() >= headers.len() {
+        return record.deserialize(Some(headers));
+    }
+
+    let headers = headers.iter().take(record.len()).collect();
+    record.deserialize(Some(&headers))
+}
+
+fn is_jsonl(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension == "jsonl")
+}
+
+fn is_gzip(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == "gz")
+}
+
+fn is_sorted(entries: &[Entry]) -> bool {
+    entries.windows(2).all(|pair| pair[0] <= pair[1])
+}
+
+/// Returns the index after the first line break that ends a csv record. A
+/// line break inside quotes is part of a field. The data ends at the end of
+/// the file which is outside of quotes so a line break ends a record when an
+/// even number of quotes follows it.
+fn record_boundary(data: &[u8]) -> Option<usize> {
+    let mut quotes_after = 0;
+    let mut boundary = None;
+
+    for (index, byte) in data.iter().enumerate().rev() {
+        match byte {
+            b'"' => quotes_after += 1,
+            b'\n' if quotes_after % 2 == 0 => boundary = Some(index + 1),
+            _ => {}
+        }
+    }
+
+    boundary
+}
+
+#[cfg(test)]
+mod test {
+    use crate::entry::Entry;
+
+    #[test]
+    fn dot_filename_with_extension() {
+        let folder_path = std::path::PathBuf::from("/tmp");
+        let hostname = "test.test.test";
+        let expected = std::path::PathBuf::from(format!("/tmp/{hostname}.csv"));
+
+        let bad = folder_path.join(hostname).with_extension("csv");
+        let good = folder_path.join(format!("{hostname}.csv"));
+
+        assert_ne!(bad, expected);
+        assert_eq!(good, expected);
+    }
+
+    #[test]
+    fn read_metadata_rows_longer_than_header() {
+        let data = "time_finished,time_start,hostname,command,pwd,result,session_id,user
+2021-06-01T10:00:00Z,2021-06-01T10:00:00Z,host,ls,/tmp,0,4f8a1f2a-6a43-4b6b-9a3c-2a1f1c4e0b11,user
+2021-06-01T10:01:00Z,2021-06-01T10:01:00Z,host,pwd,/tmp,0,4f8a1f2a-6a43-4b6b-9a3c-2a1f1c4e0b11,\
+                    user,42
+";
+
+        let entries =
+            super::Store::read_metadata(std::path::Path::new("host.csv"), data.as_bytes())
+                .expect("should parse");
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].hist_event, None);
+        assert_eq!(entries[0].tag, None);
+        assert_eq!(entries[1].command, "pwd");
+    }
+
+    #[test]
+    fn entry_headers() {
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        writer
+            .serialize(Entry::for_test("host", "ls"))
+            .expect("can serialize entry");
+        let data = writer.into_inner().expect("can flush writer");
+
+        let headers = csv::Reader::from_reader(data.as_slice())
+            .headers()
+            .expect("has headers")
+            .clone();
+
+        assert_eq!(headers.iter().collect::<Vec<_>>(), super::ENTRY_HEADERS);
+    }
+
+    #[test]
+    fn add_entry_to_file_with_old_header() {
+        let data_dir = tempfile::tempdir().expect("can create data dir");
+        let file_path = data_dir.path().join("host.csv");
+
+        std::fs::write(
+            &file_path,
+            "time_finished,time_start,hostname,command,pwd,result,session_id,user
+2021-06-01T10:00:00Z,2021-06-01T10:00:00Z,host,ls,/tmp,0,4f8a1f2a-6a43-4b6b-9a3c-2a1f1c4e0b11,user
+",
+        )
+        .expect("can write log file");
+
+        let entry = Entry {
+            hist_event: Some(42),
+            duration_ms: Some(1500),
+            tag: Some("deploy".to_string()),
+            seq: 7,
+            ..Entry::for_test("host", "make")
+        };
+
+        let store = super::new(data_dir.path().to_path_buf());
+        store.add_entry(&entry).expect("can add entry");
+        store
+            .add_entry(&Entry {
+                deleted: true,
+                ..Entry::for_test("host", "rm")
+            })
+            .expect("can add entry");
+
+        let entries = super::Store::read_log_file(&file_path).expect("can read log file");
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].hist_event, None);
+        assert_eq!(entries[0].tag, None);
+        assert_eq!(entries[1], entry);
+        assert!(entries[2].deleted);
+
+        let queried = super::Store::query_log_file(file_path.clone())
+            .map(|entry| entry.expect("can read entry").0)
+            .collect::<Vec<_>>();
+        assert_eq!(queried, entries);
+
+        let first = super::Store::read_first(std::fs::File::open(&file_path).expect("can open"))
+            .expect("can read first entry");
+        assert_eq!(first, Some(entries[0].clone()));
+    }
+
+    #[test]
+    fn read_metadata_tag() {
+        let data = "time_finished,time_start,hostname,command,pwd,result,session_id,user,\
+                    hist_event,deleted,duration_ms,tag
+2021-06-01T10:00:00Z,2021-06-01T10:00:00Z,host,ls,/tmp,0,4f8a1f2a-6a43-4b6b-9a3c-2a1f1c4e0b11,user,\
+                    ,false,,
+2021-06-01T10:01:00Z,2021-06-01T10:01:00Z,host,make,/tmp,0,4f8a1f2a-6a43-4b6b-9a3c-2a1f1c4e0b11,\
+                    user,,false,5,deploy
+";
+
+        let entries =
+            super::Store::read_metadata(std::path::Path::new("host.csv"), data.as_bytes())
+                .expect("should parse");
+
+        assert_eq!(entries[0].tag, None);
+        assert_eq!(entries[1].tag, Some("deploy".to_string()));
+    }
+
+    #[test]
+    fn read_metadata_skips_invalid_rows() {
+        let data = "time_finished,time_start,hostname,command,pwd,result,session_id,user
+2021-06-01T10:00:00Z,2021-06-01T10:00:00Z,host,ls,/tmp,0,4f8a1f2a-6a43-4b6b-9a3c-2a1f1c4e0b11,user
+this is not an entry
+2021-06-01T10:02:00Z,2021-06-01T10:02:00Z,host,cd,/tmp,0,4f8a1f2a-6a43-4b6b-9a3c-2a1f1c4e0b11,user
+";
+
+        let entries =
+            super::Store::read_metadata(std::path::Path::new("host.csv"), data.as_bytes())
+                .expect("should parse");
+        let commands: Vec<_> = entries.iter().map(|entry| entry.command.as_str()).collect();
+
+        assert_eq!(commands, vec!["ls", "cd"]);
+
+        // The skipped row is the one that is logged as a warning.
+        let (_, invalid) = super::Store::read_rows(data.as_bytes()).expect("should parse");
+        assert_eq!(
+            invalid.iter().map(|row| row.line).collect::<Vec<_>>(),
+            vec![3]
+        );
+    }
+
+    #[test]
+    fn rewrite_keeps_invalid_rows() {
+        let data_dir = tempfile::tempdir().expect("can create data dir");
+        let file_path = data_dir.path().join("host.csv");
+        let data = "time_finished,time_start,hostname,command,pwd,result,session_id,user
+2021-06-01T10:01:00Z,2021-06-01T10:01:00Z,host,cd,/tmp,0,4f8a1f2a-6a43-4b6b-9a3c-2a1f1c4e0b11,user
+this is not an entry
+2021-06-01T10:00:00Z,2021-06-01T10:00:00Z,host,ls,/tmp,0,4f8a1f2a-6a43-4b6b-9a3c-2a1f1c4e0b11,user
+";
+        std::fs::write(&file_path, data).expect("can write log file");
+
+        let store = super::new(data_dir.path().to_path_buf());
+        let invalid_rows =
+            |err: Option<super::Error>| matches!(err, Some(super::Error::InvalidRows(_, 1)));
+
+        assert!(invalid_rows(
+            store
+                .scrub(&regex::Regex::new("ls").expect("valid regex"))
+                .err()
+        ));
+        assert!(invalid_rows(store.trim(1).err()));
+        assert!(invalid_rows(
+            store
+                .compress(chrono::Utc::now() + chrono::Duration::days(1))
+                .err()
+        ));
+
+        assert_eq!(
+            std::fs::read_to_string(&file_path).expect("can read log file"),
+            data
+        );
+
+        store
+            .add_entry(&Entry::for_test("host", "pwd"))
+            .expect("can add entry");
+        assert!(invalid_rows(store.sort_written_log_files().err()));
+
+        store.check_rows(true).expect("can repair log file");
+        store.sort_written_log_files().expect("can sort log file");
+    }
+
+    #[test]
+    fn sort_written_log_files() {
+        let data_dir = tempfile::tempdir().expect("can create data dir");
+        let store = super::new(data_dir.path().to_path_buf());
+
+        let now = chrono::Utc::now();
+        let entry = |hostname: &str, command: &str, minutes_ago| {
+            Entry::for_test_at(
+                hostname,
+                command,
+                now - chrono::Duration::minutes(minutes_ago),
+            )
+        };
+
+        // The file of another host that is for example synced with git.
+        let other = data_dir.path().join("server.csv");
+        super::Store::write_log_file(&other, &[entry("server", "b", 1), entry("server", "a", 2)])
+            .expect("can write log file");
+        let other_content = std::fs::read(&other).expect("can read log file");
+
+        store
+            .add_entry(&entry("laptop", "new", 1))
+            .expect("can add entry");
+        store
+            .add_entry(&entry("laptop", "old", 2))
+            .expect("can add entry");
+
+        store.sort_written_log_files().expect("can sort log files");
+
+        let commands: Vec<_> = super::Store::read_log_file(data_dir.path().join("laptop.csv"))
+            .expect("can read log file")
+            .into_iter()
+            .map(|entry| entry.command)
+            .collect();
+
+        assert_eq!(commands, vec!["old", "new"]);
+        assert_eq!(
+            std::fs::read(&other).expect("can read log file"),
+            other_content
+        );
+    }
+
+    #[test]
+    fn verify_git_uncommitted_change() {
+        let data_dir = tempfile::tempdir().expect("can create data dir");
+
+        let git = |args: &[&str]| {
+            let status = std::process::Command::new("git")
+                .arg("-C")
+                .arg(data_dir.path())
+                .args(["-c", "user.name=hstdb", "-c", "user.email=hstdb@localhost"])
+                .args(args)
+                .status()
+                .expect("can run git");
+
+            assert!(status.success());
+        };
+
+        let host_file = data_dir.path().join("host.csv");
+        let header = "time_finished,time_start,hostname,command,pwd,result,session_id,user\n";
+        std::fs::write(&host_file, header).expect("can write host file");
+
+        git(&["init", "--quiet"]);
+        git(&["add", "--all"]);
+        git(&["commit", "--quiet", "--message", "init"]);
+
+        let store = super::new(data_dir.path().to_path_buf());
+        assert!(store.verify_git().expect("can verify").is_empty());
+
+        std::fs::write(
+            &host_file,
+            format!(
+                "{header}2021-06-01T10:00:00Z,2021-06-01T10:00:00Z,host,ls,/tmp,0,\
+                 4f8a1f2a-6a43-4b6b-9a3c-2a1f1c4e0b11,user\n"
+            ),
+        )
+        .expect("can append to host file");
+
+        let discrepancies = store.verify_git().expect("can verify");
+
+        assert_eq!(discrepancies.len(), 1);
+        assert!(matches!(
+            &discrepancies[0],
+            super::GitDiscrepancy::Uncommitted(status) if status.ends_with("host.csv")
+        ));
+    }
+
+    #[test]
+    fn gc_removes_old_tombstones() {
+        use chrono::{
+            Duration,
+            Utc,
+        };
+
+        let data_dir = tempfile::tempdir().expect("can create data dir");
+        let store = super::new(data_dir.path().to_path_buf());
+
+        let now = Utc::now();
+        let entry = |command: &str, time_finished, deleted| Entry {
+            deleted,
+            ..Entry::for_test_at("testhostname", command, time_finished)
+        };
+
+        store
+            .add_entry(&entry("old", now - Duration::days(60), true))
+            .expect("can add entry");
+        store
+            .add_entry(&entry("recent", now - Duration::days(1), true))
+            .expect("can add entry");
+        store
+            .add_entry(&entry("kept", now - Duration::days(60), false))
+            .expect("can add entry");
+
+        let removed = store.gc(now - Duration::days(30)).expect("can gc");
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed[0].1, 1);
+
+        let filter = super::Filter::default().include_deleted(true);
+        let commands: Vec<_> = store
+            .get_entries(&filter)
+            .expect("can get entries")
+            .into_iter()
+            .map(|entry| entry.command)
+            .collect();
+
+        assert_eq!(commands, vec!["kept", "recent"]);
+    }
+
+    #[test]
+    fn delete_marks_entries() {
+        use chrono::{
+            Duration,
+            Utc,
+        };
+
+        let data_dir = tempfile::tempdir().expect("can create data dir");
+        let store = super::new(data_dir.path().to_path_buf());
+
+        let now = Utc::now();
+        let entry =
+            |command: &str| Entry::for_test_at("testhostname", command, now - Duration::days(60));
+
+        store
+            .add_entry(&entry("export TOKEN=hunter2"))
+            .expect("can add entry");
+        store.add_entry(&entry("ls")).expect("can add entry");
+
+        let filter = super::Filter::default()
+            .hostnames(vec!["testhostname".to_string()], false)
+            .expect("can set hostname")
+            .command(
+                None,
+                Some(regex::Regex::new("hunter2").expect("valid regex")),
+                None,
+            );
+
+        let deleted_at = now - Duration::days(1);
+        assert_eq!(
+            store.delete(&filter, deleted_at, true).expect("can delete"),
+            vec![(data_dir.path().join("testhostname.csv"), 1)]
+        );
+        assert_eq!(
+            store
+                .delete(&filter, deleted_at, false)
+                .expect("can delete"),
+            vec![(data_dir.path().join("testhostname.csv"), 1)]
+        );
+        assert!(store
+            .delete(&filter, now, false)
+            .expect("can delete")
+            .is_empty());
+
+        let entries = store
+            .get_entries(&super::Filter::default().include_deleted(true))
+            .expect("can get entries");
+        let deleted: Vec<_> = entries.iter().filter(|entry| entry.deleted).collect();
+        assert_eq!(deleted.len(), 1);
+        assert_eq!(deleted[0].time_deleted, Some(deleted_at));
+
+        // The entry finished long ago but was only deleted recently.
+        assert!(store
+            .gc(now - Duration::days(30))
+            .expect("can gc")
+            .is_empty());
+        assert_eq!(store.gc(now).expect("can gc").len(), 1);
+
+        let commands: Vec<_> = store
+            .get_entries(&super::Filter::default().include_deleted(true))
+            .expect("can get entries")
+            .into_iter()
+            .map(|entry| entry.command)
+            .collect();
+        assert_eq!(commands, vec!["ls"]);
+    }
+
+    #[test]
+    fn add_entry_is_written_to_disk() {
+        let data_dir = tempfile::tempdir().expect("can create data dir");
+
+        let entry = Entry::for_test("testhostname", "echo \"a\nb\"");
+
+        super::new(data_dir.path().to_path_buf())
+            .add_entry(&entry)
+            .expect("can add entry");
+
+        let entries = super::new(data_dir.path().to_path_buf())
+            .get_entries(&super::Filter::default())
+            .expect("can get entries");
+
+        assert_eq!(entries, vec![entry]);
+    }
+
+    #[test]
+    fn batch_sync() {
+        let data_dir = tempfile::tempdir().expect("can create data dir");
+        let store = super::new(data_dir.path().to_path_buf()).batch_sync();
+
+        let newer = Entry::for_test("testhostname", "newer");
+        let mut older = Entry::for_test("testhostname", "older");
+        older.time_finished = newer.time_finished - chrono::Duration::hours(1);
+
+        store.add_entry(&newer).expect("can add entry");
+        store.add_entry(&older).expect("can add entry");
+        super::StoreBackend::finish_import(&store).expect("can finish import");
+
+        let entries = super::new(data_dir.path().to_path_buf())
+            .get_entries(&super::Filter::default())
+            .expect("can get entries");
+
+        assert_eq!(entries, vec![older, newer]);
+    }
+
+    #[test]
+    fn scrub_removes_secret() {
+        use regex::Regex;
+
+        let data_dir = tempfile::tempdir().expect("can create data dir");
+        let store = super::new(data_dir.path().to_path_buf());
+
+        let entry = Entry::for_test;
+
+        for entry in &[
+            entry("laptop", "export TOKEN=hunter2"),
+            entry("laptop", "ls"),
+            entry("server", "curl -H 'token: hunter2' https://example.com"),
+        ] {
+            store.add_entry(entry).expect("can add entry");
+        }
+
+        let dump = entry("desktop", "mysql -phunter2");
+        std::fs::write(
+            data_dir.path().join("dump.jsonl"),
+            format!(
+                "{}\n",
+                serde_json::to_string(&dump).expect("can serialize entry")
+            ),
+        )
+        .expect("can write jsonl file");
+
+        let pattern = Regex::new("hunter2").expect("valid regex");
+        let mut scrubbed = store.scrub(&pattern).expect("can scrub entries");
+        scrubbed.sort();
+
+        assert_eq!(
+            scrubbed,
+            vec![
+                (data_dir.path().join("dump.jsonl"), 1),
+                (data_dir.path().join("laptop.csv"), 1),
+                (data_dir.path().join("server.csv"), 1),
+            ]
+        );
+
+        for file in &["dump.jsonl", "laptop.csv", "server.csv"] {
+            let content =
+                std::fs::read_to_string(data_dir.path().join(file)).expect("can read log file");
+            assert!(!content.contains("hunter2"), "{}", content);
+        }
+
+        let commands: Vec<_> = store
+            .get_entries(&super::Filter::default())
+            .expect("can get entries")
+            .into_iter()
+            .map(|entry| entry.command)
+            .collect();
+
+        assert!(commands.contains(&"export TOKEN=***".to_string()));
+        assert!(commands.contains(&"mysql -p***".to_string()));
+        assert!(store.scrub(&pattern).expect("can scrub entries").is_empty());
+    }
+
+    #[test]
+    fn trim_keeps_newest_entries() {
+        use chrono::{
+            Duration,
+            Utc,
+        };
+
+        let data_dir = tempfile::tempdir().expect("can create data dir");
+        let store = super::new(data_dir.path().to_path_buf());
+
+        let now = Utc::now();
+        let entry = |hostname: &str, command: &str, days_ago| {
+            Entry::for_test_at(hostname, command, now - Duration::days(days_ago))
+        };
+
+        let commands = ["oldest", "newest", "old", "new"];
+        for (command, days_ago) in commands.iter().zip(&[4, 1, 3, 2]) {
+            store
+                .add_entry(&entry("testhostname", command, *days_ago))
+                .expect("can add entry");
+        }
+        store
+            .add_entry(&entry("otherhostname", "other", 10))
+            .expect("can add entry");
+
+        let removed = store.trim(2).expect("can trim");
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed[0].1, 2);
+
+        let filter = super::Filter::default();
+        let commands: Vec<_> = store
+            .get_entries(&filter)
+            .expect("can get entries")
+            .into_iter()
+            .map(|entry| entry.command)
+            .collect();
+
+        assert_eq!(commands, vec!["other", "new", "newest"]);
+    }
+
+    #[test]
+    fn prune_removes_old_entries() {
+        use chrono::{
+            Duration,
+            Utc,
+        };
+
+        let data_dir = tempfile::tempdir().expect("can create data dir");
+        let store = super::new(data_dir.path().to_path_buf());
+
+        let now = Utc::now();
+        let entry = |command: &str, days_ago| {
+            Entry::for_test_at("testhostname", command, now - Duration::days(days_ago))
+        };
+
+        for (command, days_ago) in &[
+            ("ancient", 40),
+            ("echo \"a,\nb\"", 20),
+            ("old", 3),
+            ("new", 2),
+            ("newest", 1),
+        ] {
+            store
+                .add_entry(&entry(command, *days_ago))
+                .expect("can add entry");
+        }
+
+        let host_file = data_dir.path().join("testhostname.csv");
+        let content = std::fs::read_to_string(&host_file).expect("can read host file");
+
+        let removed = store
+            .prune(Some(now - Duration::days(30)), Some(2), true)
+            .expect("can prune");
+        assert_eq!(removed, vec![(host_file.clone(), 3)]);
+        assert_eq!(
+            std::fs::read_to_string(&host_file).expect("can read host file"),
+            content
+        );
+
+        let removed = store
+            .prune(Some(now - Duration::days(30)), None, false)
+            .expect("can prune");
+        assert_eq!(removed, vec![(host_file.clone(), 1)]);
+
+        let commands = || {
+            store
+                .get_entries(&super::Filter::default())
+                .expect("can get entries")
+                .into_iter()
+                .map(|entry| entry.command)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(commands(), vec!["echo \"a,\nb\"", "old", "new", "newest"]);
+
+        store.prune(None, Some(3), false).expect("can prune");
+        assert_eq!(commands(), vec!["old", "new", "newest"]);
+
+        let content = std::fs::read_to_string(&host_file).expect("can read host file");
+        assert!(content.starts_with("time_finished,"), "{}", content);
+    }
+
+    #[test]
+    fn storage_key_host_user() {
+        let data_dir = tempfile::tempdir().expect("can create data dir");
+        let store =
+            super::new(data_dir.path().to_path_buf()).storage_key(super::StorageKey::HostUser);
+
+        let entry = |command: &str, user: &str| Entry {
+            user: user.to_string(),
+            ..Entry::for_test("testhostname", command)
+        };
+
+        store
+            .add_entry(&entry("ls", "alice"))
+            .expect("can add entry");
+        store
+            .add_entry(&entry("pwd", "bob"))
+            .expect("can add entry");
+
+        let host_dir = data_dir.path().join("testhostname");
+        assert!(host_dir.join("alice.csv").is_file());
+        assert!(host_dir.join("bob.csv").is_file());
+
+        let filter = super::Filter::default()
+            .hostnames(vec!["testhostname".to_string()], false)
+            .expect("can set hostname");
+
+        let mut users: Vec<_> = store
+            .get_entries(&filter)
+            .expect("can get entries")
+            .into_iter()
+            .map(|entry| entry.user)
+            .collect();
+        users.sort();
+
+        assert_eq!(users, vec!["alice", "bob"]);
+
+        let all = store
+            .get_entries(&super::Filter::default())
+            .expect("can get entries");
+        assert_eq!(all.len(), 2);
+    }
+
+    #[test]
+    fn host_files() {
+        let data_dir = tempfile::tempdir().expect("can create data dir");
+        let store = super::new(data_dir.path().to_path_buf());
+
+        let header = "time_finished,time_start,hostname,command,pwd,result,session_id,user\n";
+        for file in &["host.csv", "test.local.csv", "other/alice.csv"] {
+            let path = data_dir.path().join(file);
+            std::fs::create_dir_all(path.parent().expect("has parent")).expect("can create dir");
+            std::fs::write(path, header).expect("can write host file");
+        }
+        std::fs::write(data_dir.path().join("notes.txt"), "").expect("can write other file");
+
+        let mut got = store.host_files().expect("can list host files");
+        got.sort();
+
+        assert_eq!(
+            got,
+            vec![
+                ("host".to_string(), data_dir.path().join("host.csv")),
+                ("other".to_string(), data_dir.path().join("other/alice.csv")),
+                (
+                    "test.local".to_string(),
+                    data_dir.path().join("test.local.csv")
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn usage() {
+        let data_dir = tempfile::tempdir().expect("can create data dir");
+        let store = super::new(data_dir.path().to_path_buf());
+
+        let entry = Entry::for_test;
+
+        store
+            .add_entry(&entry("beta", "ls"))
+            .expect("can add entry");
+        store
+            .add_entry(&entry("alpha", "ls"))
+            .expect("can add entry");
+        store
+            .add_entry(&entry("alpha", "cd /tmp"))
+            .expect("can add entry");
+
+        let usage = store.usage().expect("can get usage");
+
+        let file_len = |file: &str| {
+            std::fs::metadata(data_dir.path().join(file))
+                .expect("file exists")
+                .len()
+        };
+
+        assert_eq!(
+            usage,
+            vec![
+                super::FileUsage {
+                    hostname: "alpha".to_string(),
+                    path: data_dir.path().join("alpha.csv"),
+                    bytes: file_len("alpha.csv"),
+                    entries: 2,
+                },
+                super::FileUsage {
+                    hostname: "beta".to_string(),
+                    path: data_dir.path().join("beta.csv"),
+                    bytes: file_len("beta.csv"),
+                    entries: 1,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn compress() {
+        use chrono::{
+            Duration,
+            Utc,
+        };
+
+        let data_dir = tempfile::tempdir().expect("can create data dir");
+        let store = super::new(data_dir.path().to_path_buf());
+
+        let entry = |command: &str| Entry::for_test("host", command);
+
+        store.add_entry(&entry("first")).expect("can add entry");
+
+        assert!(store
+            .compress(Utc::now() - Duration::days(1))
+            .expect("can compress")
+            .is_empty());
+
+        let compressed = store
+            .compress(Utc::now() + Duration::days(1))
+            .expect("can compress");
+        let gzip_path = data_dir.path().join("host.csv.gz");
+
+        assert_eq!(compressed, vec![gzip_path.clone()]);
+        assert!(!data_dir.path().join("host.csv").exists());
+
+        // New entries are written to the plain file again and both files are
+        // read.
+        store.add_entry(&entry("second")).expect("can add entry");
+
+        let commands = |store: &super::Store| {
+            store
+                .get_entries(&super::Filter::default().count(1))
+                .expect("can get entries")
+                .into_iter()
+                .map(|entry| entry.command)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(commands(&store), vec!["second"]);
+
+        let all = store
+            .get_entries(&super::Filter::default())
+            .expect("can get entries");
+        assert_eq!(all.len(), 2);
+
+        store
+            .compress(Utc::now() + Duration::days(1))
+            .expect("can compress");
+
+        let all: Vec<_> = super::Store::read_log_file(&gzip_path)
+            .expect("can read compressed file")
+            .into_iter()
+            .map(|entry| entry.command)
+            .collect();
+        assert_eq!(all, vec!["first", "second"]);
+    }
+
+    #[test]
+    fn get_entries_merges_hostnames() {
+        let data_dir = tempfile::tempdir().expect("can create data dir");
+        let store = super::new(data_dir.path().to_path_buf());
+
+        for hostname in &["laptop", "laptop.local", "laptop.home.arpa", "server"] {
+            store
+                .add_entry(&Entry::for_test(hostname, "ls"))
+                .expect("can add entry");
+        }
+
+        let aliases =
+            super::HostnameAliases::new(vec![(r"^laptop(\..+)?$", "laptop")]).expect("valid");
+
+        let filter = super::Filter::default()
+            .hostnames(vec!["laptop.local".to_string()], false)
+            .expect("can set hostname")
+            .hostname_aliases(aliases);
+
+        let entries = store.get_entries(&filter).expect("can get entries");
+
+        assert_eq!(entries.len(), 3);
+        assert!(entries.iter().all(|entry| entry.hostname == "laptop"));
+    }
+
+    #[test]
+    fn get_entries_reads_jsonl() {
+        use chrono::{
+            Duration,
+            Utc,
+        };
+        use std::io::Write;
+
+        let data_dir = tempfile::tempdir().expect("can create data dir");
+        let store = super::new(data_dir.path().to_path_buf());
+
+        let now = Utc::now();
+        let entry = |hostname: &str, command: &str, minutes_ago| {
+            Entry::for_test_at(hostname, command, now - Duration::minutes(minutes_ago))
+        };
+
+        store
+            .add_entry(&entry("laptop", "csv", 2))
+            .expect("can add entry");
+
+        let mut file =
+            std::fs::File::create(data_dir.path().join("dump.jsonl")).expect("can create file");
+        for entry in &[entry("laptop", "jsonl", 3), entry("server", "other", 1)] {
+            serde_json::to_writer(&mut file, entry).expect("can write entry");
+            writeln!(file).expect("can write newline");
+        }
+
+        let commands = |filter: super::Filter| {
+            store
+                .get_entries(&filter)
+                .expect("can get entries")
+                .into_iter()
+                .map(|entry| entry.command)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            commands(
+                super::Filter::default()
+                    .hostnames(vec!["laptop".to_string()], false)
+                    .expect("can set hostname")
+            ),
+            vec!["jsonl", "csv"]
+        );
+
+        assert_eq!(
+            commands(
+                super::Filter::default()
+                    .hostnames(Vec::new(), true)
+                    .expect("can set hostname")
+                    .count(1)
+            ),
+            vec!["other"]
+        );
+    }
+
+    #[test]
+    fn get_entries_multiple_hostnames() {
+        let data_dir = tempfile::tempdir().expect("can create data dir");
+        let store = super::new(data_dir.path().to_path_buf());
+
+        for hostname in &["laptop", "desktop", "server"] {
+            store
+                .add_entry(&Entry::for_test(hostname, "ls"))
+                .expect("can add entry");
+        }
+
+        let filter = super::Filter::default()
+            .hostnames(vec!["laptop".to_string(), "server".to_string()], false)
+            .expect("can set hostnames");
+
+        let mut hostnames: Vec<_> = store
+            .get_entries(&filter)
+            .expect("can get entries")
+            .into_iter()
+            .map(|entry| entry.hostname)
+            .collect();
+        hostnames.sort();
+
+        assert_eq!(hostnames, vec!["laptop", "server"]);
+
+        let filter = super::Filter::default()
+            .hostnames(Vec::new(), true)
+            .expect("can set hostnames");
+
+        assert_eq!(
+            store.get_entries(&filter).expect("can get entries").len(),
+            3
+        );
+    }
+
+    #[test]
+    fn record_boundary() {
+        assert_eq!(super::record_boundary(b"partial\nnext,row\n"), Some(8));
+        assert_eq!(super::record_boundary(b"in\"\nquotes\"\nnext\n"), Some(12));
+        assert_eq!(super::record_boundary(b"\"\"\"\n\"\nnext\n"), Some(6));
+        assert_eq!(super::record_boundary(b"no line break"), None);
+    }
+
+    #[test]
+    fn get_entries_reads_tail() {
+        use chrono::{
+            Duration,
+            Utc,
+        };
+
+        let data_dir = tempfile::tempdir().expect("can create data dir");
+        let store = super::new(data_dir.path().to_path_buf());
+
+        let now = Utc::now();
+        let entry = |index: i64| Entry {
+            deleted: index % 7 == 0,
+            // Quoted line breaks make sure the end is only read from record
+            // boundaries.
+            ..Entry::for_test_at(
+                "testhostname",
+                &format!("echo \"{index}\n\"\n\"\""),
+                now - Duration::minutes(10_000 - index),
+            )
+        };
+
+        for index in 0..5000 {
+            store.add_entry(&entry(index)).expect("can add entry");
+        }
+
+        let filter = || {
+            super::Filter::default()
+                .hostnames(vec!["testhostname".to_string()], false)
+                .expect("can set hostname")
+        };
+
+        let all = store.get_entries(&filter()).expect("can get entries");
+        let entries = store
+            .get_entries(&filter().count(1000))
+            .expect("can get entries");
+
+        assert_eq!(entries.len(), 1000);
+        assert_eq!(entries[..], all[all.len() - 1000..]);
+
+        // An older entry at the end means the file was not only appended to
+        // by the server so the whole file has to be read.
+        store.add_entry(&entry(-1)).expect("can add entry");
+
+        let commands: Vec<_> = store
+            .get_entries(&filter().count(1))
+            .expect("can get entries")
+            .into_iter()
+            .map(|entry| entry.command)
+            .collect();
+
+        assert_eq!(commands, vec![entry(4999).command]);
+    }
+
+    #[test]
+    fn get_entries_cached() {
+        let data_dir = tempfile::tempdir().expect("can create data dir");
+        let store = super::new(data_dir.path().to_path_buf()).with_cache();
+
+        let entry = |command: &str| Entry::for_test("testhostname", command);
+
+        let commands = || {
+            store
+                .get_entries(&super::Filter::default())
+                .expect("can get entries")
+                .into_iter()
+                .map(|entry| entry.command)
+                .collect::<Vec<_>>()
+        };
+
+        store.add_entry(&entry("first")).expect("can add entry");
+        assert_eq!(commands(), vec!["first"]);
+
+        // Changing the cached entries shows whether the file is parsed again.
+        for cached in store
+            .cache
+            .as_ref()
+            .expect("cache is enabled")
+            .borrow_mut()
+            .values_mut()
+        {
+            cached.entries[0].command = "cached".to_string();
+        }
+        assert_eq!(commands(), vec!["cached"]);
+
+        store.add_entry(&entry("second")).expect("can add entry");
+        assert_eq!(commands(), vec!["first", "second"]);
+    }
+
+    #[test]
+    fn check_rows() {
+        let data_dir = tempfile::tempdir().expect("can create data dir");
+        let host_file = data_dir.path().join("host.csv");
+
+        std::fs::write(
+            &host_file,
+            "time_finished,time_start,hostname,command,pwd,result,session_id,user
+2021-06-01T10:00:00Z,2021-06-01T10:00:00Z,host,ls,/tmp,0,4f8a1f2a-6a43-4b6b-9a3c-2a1f1c4e0b11,user
+2021-06-01T10:01:00Z,broken,host,pwd,/tmp,0,4f8a1f2a-6a43-4b6b-9a3c-2a1f1c4e0b11,user
+2021-06-01T10:02:00Z,2021-06-01T10:02:00Z,host,cd,/tmp,0,4f8a1f2a-6a43-4b6b-9a3c-2a1f1c4e0b11,user
+",
+        )
+        .expect("can write host file");
+
+        let store = super::new(data_dir.path().to_path_buf());
+
+        let checks = store.check_rows(false).expect("can check rows");
+        assert_eq!(checks.len(), 1);
+        assert_eq!(checks[0].hostname, "host");
+        assert_eq!(checks[0].entries, 2);
+        assert_eq!(
+            checks[0]
+                .invalid
+                .iter()
+                .map(|row| row.line)
+                .collect::<Vec<_>>(),
+            vec![3]
+        );
+        assert_eq!(
+            super::Store::read_log_file(&host_file)
+                .expect("skips invalid rows")
+                .len(),
+            2
+        );
+
+        store.check_rows(true).expect("can repair rows");
+
+        let commands: Vec<_> = super::Store::read_log_file(&host_file)
+            .expect("can read repaired file")
+            .into_iter()
+            .map(|entry| entry.command)
+            .collect();
+        assert_eq!(commands, vec!["ls", "cd"]);
+
+        let checks = store.check_rows(false).expect("can check rows");
+        assert!(checks[0].invalid.is_empty());
+    }
+
+    #[test]
+    fn query() {
+        let data_dir = tempfile::tempdir().expect("can create data dir");
+        let store = super::new(data_dir.path().to_path_buf());
+
+        let entry = Entry::for_test;
+
+        for (hostname, command) in &[
+            ("laptop", "cargo build"),
+            ("laptop", "ls"),
+            ("server", "cargo test"),
+            ("laptop", "cargo run"),
+        ] {
+            store
+                .add_entry(&entry(hostname, command))
+                .expect("can add entry");
+        }
+
+        let filter = super::Filter::default()
+            .hostnames(vec!["laptop".to_string()], false)
+            .expect("can set hostname")
+            .command(
+                None,
+                Some(regex::Regex::new("^cargo").expect("valid")),
+                None,
+            );
+
+        let commands: Vec<_> = store
+            .query(&filter)
+            .map(|entry| entry.expect("can read entry").command)
+            .collect();
+        assert_eq!(commands, vec!["cargo build", "cargo run"]);
+
+        let entries = store.get_entries(&filter).expect("can get entries");
+        assert_eq!(entries.len(), 2);
+
+        let unknown = super::Filter::default()
+            .hostnames(vec!["unknown".to_string()], false)
+            .expect("can set hostname");
+        assert!(store.query(&unknown).any(|entry| entry.is_err()));
     }
 }
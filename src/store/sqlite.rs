@@ -0,0 +1,503 @@
+use super::{
+    Error,
+    Filter,
+    StoreBackend,
+};
+use crate::entry::Entry;
+use chrono::{
+    DateTime,
+    TimeZone,
+    Utc,
+};
+use regex::Regex;
+use rusqlite::{
+    params,
+    params_from_iter,
+    types::{
+        ToSql,
+        Type,
+    },
+    Connection,
+    Row,
+};
+use std::{
+    ffi::OsString,
+    fs,
+    os::unix::ffi::{
+        OsStrExt,
+        OsStringExt,
+    },
+    path::{
+        Path,
+        PathBuf,
+    },
+};
+use uuid::Uuid;
+
+const DATABASE_FILE: &str = "history.sqlite";
+
+const SCHEMA: &str = "
+create table if not exists entries (
+    time_finished integer not null,
+    time_start integer not null,
+    hostname text not null,
+    command text not null,
+    pwd blob not null,
+    result integer not null,
+    session_id text not null,
+    user text not null,
+    hist_event integer,
+    deleted integer not null default 0,
+    duration_ms integer,
+    tag text,
+    seq integer not null default 0,
+    time_deleted integer
+);
+
+create index if not exists entries_time_finished on entries (time_finished);
+create index if not exists entries_hostname on entries (hostname);
+";
+
+/// Stores all entries in one `SQLite` database in the data dir. Filters on
+/// hostname, time, status, user and deleted entries are applied in the query.
+#[derive(Debug)]
+pub struct SqliteStore {
+    path: PathBuf,
+    connection: Connection,
+}
+
+pub fn open(data_dir: impl AsRef<Path>) -> Result<SqliteStore, Error> {
+    let data_dir = data_dir.as_ref();
+
+    fs::create_dir_all(data_dir)
+        .map_err(|err| Error::CreateLogFolder(data_dir.to_path_buf(), err))?;
+
+    let path = data_dir.join(DATABASE_FILE);
+    let connection = Connection::open(&path).map_err(Error::OpenDatabase)?;
+
+    connection
+        .execute_batch(SCHEMA)
+        .map_err(Error::CreateDatabaseSchema)?;
+
+    add_missing_columns(&connection)?;
+
+    Ok(SqliteStore { path, connection })
+}
+
+/// Adds the columns that were added to the schema after a database was
+/// created as `create table if not exists` keeps existing tables as they are.
+fn add_missing_columns(connection: &Connection) -> Result<(), Error> {
+    let columns = [
+        ("tag", "text"),
+        ("seq", "integer not null default 0"),
+        ("time_deleted", "integer"),
+    ];
+
+    for (name, definition) in columns {
+        let exists: bool = connection
+            .query_row(
+                "select count(*) > 0 from pragma_table_info('entries') where name = ?",
+                [name],
+                |row| row.get(0),
+            )
+            .map_err(Error::CreateDatabaseSchema)?;
+
+        if !exists {
+            connection
+                .execute_batch(&format!(
+                    "alter table entries add column {name} {definition}"
+                ))
+                .map_err(Error::CreateDatabaseSchema)?;
+        }
+    }
+
+    Ok(())
+}
+
+impl StoreBackend for SqliteStore {
+    fn add(&self, entry: &Entry) -> Result<(), Error> {
+        if entry.command.is_empty() {
+            return Ok(());
+        }
+
+        self.connection
+            .execute(
+                "insert into entries (time_finished, time_start, hostname, command, pwd, result, \
+                 session_id, user, hist_event, deleted, duration_ms, tag, seq, time_deleted) \
+                 values (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
+                params![
+                    timestamp_nanos(entry.time_finished)?,
+                    timestamp_nanos(entry.time_start)?,
+                    entry.hostname,
+                    entry.command,
+                    entry.pwd.as_os_str().as_bytes(),
+                    entry.result,
+                    entry.session_id.to_string(),
+                    entry.user,
+                    entry.hist_event,
+                    entry.deleted,
+                    entry.duration_ms,
+                    entry.tag,
+                    entry.seq,
+                    entry.time_deleted.map(timestamp_nanos).transpose()?,
+                ],
+            )
+            .map_err(Error::InsertEntry)?;
+
+        Ok(())
+    }
+
+    fn get_entries(&self, filter: &Filter) -> Result<Vec<Entry>, Error> {
+        let mut conditions = Vec::new();
+        let mut values: Vec<Box<dyn ToSql>> = Vec::new();
+
+        if !filter.get_hostnames().is_empty() {
+            let hostnames = self.hostnames(filter)?;
+
+            conditions.push(format!(
+                "hostname in ({})",
+                vec!["?"; hostnames.len()].join(", ")
+            ));
+
+            for hostname in hostnames {
+                values.push(Box::new(hostname));
+            }
+        }
+
+        if let Some(since) = filter.since {
+            conditions.push("time_finished >= ?".to_string());
+            values.push(Box::new(timestamp_nanos_clamped(since)));
+        }
+
+        if let Some(until) = filter.until {
+            conditions.push("time_finished <= ?".to_string());
+            values.push(Box::new(timestamp_nanos_clamped(until)));
+        }
+
+        if let Some(user) = &filter.user {
+            conditions.push("user = ?".to_string());
+            values.push(Box::new(user.clone()));
+        }
+
+        if let Some(find_status) = filter.find_status {
+            conditions.push("result = ?".to_string());
+            values.push(Box::new(find_status));
+        }
+
+        if let Some(tag) = &filter.tag {
+            conditions.push("tag = ?".to_string());
+            values.push(Box::new(tag.clone()));
+        }
+
+        if filter.filter_failed {
+            conditions.push("result = 0".to_string());
+        }
+
+        if filter.failed_only {
+            conditions.push("result != 0".to_string());
+        }
+
+        if !filter.include_deleted {
+            conditions.push("deleted = 0".to_string());
+        }
+
+        let mut query = "select time_finished, time_start, hostname, command, pwd, result, \
+                         session_id, user, hist_event, deleted, duration_ms, tag, seq, \
+                         time_deleted from entries"
+            .to_string();
+
+        if !conditions.is_empty() {
+            query.push_str(" where ");
+            query.push_str(&conditions.join(" and "));
+        }
+
+        // Only when every predicate of the filter was part of the query the
+        // newest entries of the result are the ones that are printed.
+        if filter.count > 0 && filter.matches_columns_only() {
+            query.push_str(
+                " order by time_finished desc, time_start desc, session_id desc, seq desc",
+            );
+            query.push_str(" limit ?");
+            values.push(Box::new(filter.count));
+        }
+
+        let mut statement = self
+            .connection
+            .prepare(&query)
+            .map_err(Error::QueryEntries)?;
+
+        let mut entries = statement
+            .query_map(params_from_iter(values.iter()), entry_from_row)
+            .map_err(Error::QueryEntries)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(Error::QueryEntries)?;
+
+        let aliases = &filter.hostname_aliases;
+        if !aliases.is_empty() {
+            for entry in &mut entries {
+                entry.hostname = aliases.canonical_hostname(&entry.hostname);
+            }
+        }
+
+        entries.sort();
+
+        Ok(filter.filter_entries(entries))
+    }
+
+    fn scrub(&self, pattern: &Regex) -> Result<Vec<(PathBuf, usize)>, Error> {
+        let mut statement = self
+            .connection
+            .prepare("select rowid, command from entries")
+            .map_err(Error::QueryEntries)?;
+
+        let matches = statement
+            .query_map([], |row| {
+                Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+            })
+            .map_err(Error::QueryEntries)?
+            .filter(|command| {
+                command
+                    .as_ref()
+                    .map_or(true, |(_, command)| pattern.is_match(command))
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(Error::QueryEntries)?;
+
+        if matches.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // Either every command is scrubbed or none so a failure does not leave
+        // some of the secrets behind unnoticed.
+        let transaction = self
+            .connection
+            .unchecked_transaction()
+            .map_err(Error::UpdateEntries)?;
+
+        for (rowid, command) in &matches {
+            transaction
+                .execute(
+                    "update entries set command = ?1 where rowid = ?2",
+                    params![pattern.replace_all(command, "***"), rowid],
+                )
+                .map_err(Error::UpdateEntries)?;
+        }
+
+        transaction.commit().map_err(Error::UpdateEntries)?;
+
+        Ok(vec![(self.path.clone(), matches.len())])
+    }
+}
+
+impl SqliteStore {
+    /// Returns the stored hostnames that have the same canonical hostname as
+    /// one of the hostnames of the filter.
+    fn hostnames(&self, filter: &Filter) -> Result<Vec<String>, Error> {
+        let aliases = &filter.hostname_aliases;
+
+        if aliases.is_empty() {
+            return Ok(filter.get_hostnames().to_vec());
+        }
+
+        let canonical: Vec<_> = filter
+            .get_hostnames()
+            .iter()
+            .map(|hostname| aliases.canonical_hostname(hostname))
+            .collect();
+
+        let mut statement = self
+            .connection
+            .prepare("select distinct hostname from entries")
+            .map_err(Error::QueryEntries)?;
+
+        let hostnames = statement
+            .query_map([], |row| row.get::<_, String>(0))
+            .map_err(Error::QueryEntries)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(Error::QueryEntries)?;
+
+        Ok(hostnames
+            .into_iter()
+            .filter(|hostname| canonical.contains(&aliases.canonical_hostname(hostname)))
+            .collect())
+    }
+}
+
+/// Timestamps are stored as nanoseconds since the epoch which only covers the
+/// years 1677 to 2262.
+fn timestamp_nanos(time: DateTime<Utc>) -> Result<i64, Error> {
+    time.timestamp()
+        .checked_mul(1_000_000_000)
+        .and_then(|nanos| nanos.checked_add(i64::from(time.timestamp_subsec_nanos())))
+        .ok_or(Error::TimestampOutOfRange(time))
+}
+
+/// Times outside of what can be stored are before or after every stored entry
+/// so filtering with them can use the smallest or largest timestamp instead.
+fn timestamp_nanos_clamped(time: DateTime<Utc>) -> i64 {
+    timestamp_nanos(time).unwrap_or(if time.timestamp() < 0 {
+        i64::MIN
+    } else {
+        i64::MAX
+    })
+}
+
+fn entry_from_row(row: &Row<'_>) -> rusqlite::Result<Entry> {
+    let session_id: String = row.get(6)?;
+    let session_id = Uuid::parse_str(&session_id)
+        .map_err(|err| rusqlite::Error::FromSqlConversionFailure(6, Type::Text, Box::new(err)))?;
+
+    Ok(Entry {
+        time_finished: Utc.timestamp_nanos(row.get(0)?),
+        time_start: Utc.timestamp_nanos(row.get(1)?),
+        hostname: row.get(2)?,
+        command: row.get(3)?,
+        pwd: PathBuf::from(OsString::from_vec(row.get(4)?)),
+        result: row.get(5)?,
+        session_id,
+        user: row.get(7)?,
+        hist_event: row.get(8)?,
+        deleted: row.get(9)?,
+        duration_ms: row.get(10)?,
+        tag: row.get(11)?,
+        seq: row.get(12)?,
+        time_deleted: row
+            .get::<_, Option<i64>>(13)?
+            .map(|nanos| Utc.timestamp_nanos(nanos)),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::StoreBackend;
+    use crate::{
+        entry::Entry,
+        store::Filter,
+    };
+    use chrono::{
+        Duration,
+        TimeZone,
+        Utc,
+    };
+
+    #[test]
+    fn get_entries() {
+        let data_dir = tempfile::tempdir().expect("can create data dir");
+        let store = super::open(data_dir.path()).expect("can open store");
+
+        let now = Utc::now();
+        let entry = |hostname: &str, command: &str, result, days_ago| Entry {
+            result,
+            hist_event: Some(1),
+            ..Entry::for_test_at(hostname, command, now - Duration::days(days_ago))
+        };
+
+        let entries = vec![
+            entry("laptop", "old", 0, 10),
+            entry("laptop", "failed", 1, 5),
+            entry("laptop", "new", 0, 1),
+            entry("server", "other", 0, 2),
+        ];
+
+        for entry in &entries {
+            store.add(entry).expect("can add entry");
+        }
+
+        let commands = |filter: Filter| {
+            store
+                .get_entries(&filter)
+                .expect("can get entries")
+                .into_iter()
+                .map(|entry| entry.command)
+                .collect::<Vec<_>>()
+        };
+
+        let laptop = || {
+            Filter::default()
+                .hostnames(vec!["laptop".to_string()], false)
+                .expect("can set hostname")
+        };
+
+        assert_eq!(commands(laptop()), vec!["old", "failed", "new"]);
+        assert_eq!(commands(laptop().count(2)), vec!["failed", "new"]);
+        assert_eq!(commands(laptop().only_failed(true)), vec!["failed"]);
+        assert_eq!(
+            commands(laptop().since(Some(now - Duration::days(7)))),
+            vec!["failed", "new"]
+        );
+        assert_eq!(
+            commands(
+                laptop()
+                    .command(None, Some(regex::Regex::new("^o").expect("valid")), None)
+                    .count(1)
+            ),
+            vec!["old"]
+        );
+        assert_eq!(commands(Filter::default().count(2)), vec!["other", "new"]);
+
+        let stored = store
+            .get_entries(&Filter::default())
+            .expect("can get entries");
+        let mut expected = entries;
+        expected.sort();
+
+        assert_eq!(stored, expected);
+    }
+
+    #[test]
+    fn timestamp_out_of_range() {
+        let data_dir = tempfile::tempdir().expect("can create data dir");
+        let store = super::open(data_dir.path()).expect("can open store");
+
+        let far_future = Utc.ymd(2300, 1, 1).and_hms(0, 0, 0);
+        let entry = Entry::for_test_at("laptop", "ls", far_future);
+
+        assert!(matches!(
+            store.add(&entry),
+            Err(crate::store::Error::TimestampOutOfRange(_))
+        ));
+
+        store
+            .add(&Entry::for_test("laptop", "ls"))
+            .expect("can add entry");
+
+        let filter = Filter::default()
+            .since(Some(Utc.ymd(1500, 1, 1).and_hms(0, 0, 0)))
+            .until(Some(far_future));
+        assert_eq!(
+            store.get_entries(&filter).expect("can get entries").len(),
+            1
+        );
+    }
+
+    #[test]
+    fn scrub() {
+        let data_dir = tempfile::tempdir().expect("can create data dir");
+        let store = super::open(data_dir.path()).expect("can open store");
+
+        store
+            .add(&Entry::for_test("laptop", "export TOKEN=hunter2"))
+            .expect("can add entry");
+        store
+            .add(&Entry::for_test("laptop", "ls"))
+            .expect("can add entry");
+
+        let pattern = regex::Regex::new("hunter2").expect("valid regex");
+
+        assert_eq!(
+            store.scrub(&pattern).expect("can scrub entries"),
+            vec![(data_dir.path().join(super::DATABASE_FILE), 1)]
+        );
+        assert!(store.scrub(&pattern).expect("can scrub entries").is_empty());
+
+        let mut commands: Vec<_> = store
+            .get_entries(&Filter::default())
+            .expect("can get entries")
+            .into_iter()
+            .map(|entry| entry.command)
+            .collect();
+        commands.sort();
+
+        assert_eq!(commands, vec!["export TOKEN=***", "ls"]);
+    }
+}
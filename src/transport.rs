@@ -0,0 +1,165 @@
+use std::{
+    io::{
+        self,
+        ErrorKind,
+        Read,
+        Write,
+    },
+    net::{
+        Shutdown,
+        TcpStream,
+    },
+    os::unix::net::UnixDatagram,
+};
+#[cfg(feature = "tcp-transport")]
+use std::{
+    net::ToSocketAddrs,
+    time::Duration,
+};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("invalid tcp address {0:?}, expected tcp://<host>:<port>")]
+    InvalidTcpAddress(String),
+}
+
+/// Carries one serialized message from the client to the server.
+pub trait Transport {
+    /// Sends the data as one message.
+    fn send(&self, data: &[u8]) -> io::Result<()>;
+
+    /// Waits for one message and writes it to the start of the buffer.
+    /// Returns the size of the message.
+    fn recv(&self, buffer: &mut [u8]) -> io::Result<usize>;
+}
+
+impl Transport for UnixDatagram {
+    fn send(&self, data: &[u8]) -> io::Result<()> {
+        Self::send(self, data).map(|_| ())
+    }
+
+    fn recv(&self, buffer: &mut [u8]) -> io::Result<usize> {
+        Self::recv(self, buffer)
+    }
+}
+
+/// Every connection carries exactly one message. The client closes its side
+/// of the connection after writing the message which marks the end of it.
+impl Transport for TcpStream {
+    fn send(&self, data: &[u8]) -> io::Result<()> {
+        let mut stream = self;
+
+        stream.write_all(data)?;
+        self.shutdown(Shutdown::Write)
+    }
+
+    fn recv(&self, buffer: &mut [u8]) -> io::Result<usize> {
+        // Read one byte more than fits so messages that are too large are
+        // noticed instead of being cut off.
+        let mut data = Vec::new();
+        Read::take(self, buffer.len() as u64 + 1).read_to_end(&mut data)?;
+
+        if data.len() > buffer.len() {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "message is larger than the receive buffer",
+            ));
+        }
+
+        buffer[..data.len()].copy_from_slice(&data);
+
+        Ok(data.len())
+    }
+}
+
+/// Parses addresses like `tcp://0.0.0.0:9999` into the `host:port` part.
+pub fn parse_tcp_address(value: &str) -> Result<String, Error> {
+    match value.strip_prefix("tcp://") {
+        Some(address) if address.contains(':') => Ok(address.to_string()),
+        _ => Err(Error::InvalidTcpAddress(value.to_string())),
+    }
+}
+
+/// Connects to the first address the host resolves to that accepts the
+/// connection. With a timeout every address is only tried that long so an
+/// unreachable server does not block the shell hooks.
+#[cfg(feature = "tcp-transport")]
+pub fn connect_tcp(address: &str, timeout: Option<Duration>) -> io::Result<TcpStream> {
+    let Some(timeout) = timeout else {
+        return TcpStream::connect(address);
+    };
+
+    let mut last_err = io::Error::new(
+        ErrorKind::InvalidInput,
+        format!("{address} does not resolve to any address"),
+    );
+
+    for address in address.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&address, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(err) => last_err = err,
+        }
+    }
+
+    Err(last_err)
+}
+
+#[cfg(test)]
+mod test {
+    use super::Transport;
+    use std::net::{
+        TcpListener,
+        TcpStream,
+    };
+
+    #[test]
+    fn parse_tcp_address() {
+        assert_eq!(
+            super::parse_tcp_address("tcp://0.0.0.0:9999").expect("valid address"),
+            "0.0.0.0:9999"
+        );
+        assert_eq!(
+            super::parse_tcp_address("tcp://devbox:9999").expect("valid address"),
+            "devbox:9999"
+        );
+
+        assert!(super::parse_tcp_address("0.0.0.0:9999").is_err());
+        assert!(super::parse_tcp_address("udp://0.0.0.0:9999").is_err());
+        assert!(super::parse_tcp_address("tcp://devbox").is_err());
+    }
+
+    #[test]
+    fn tcp_stream() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("can bind listener");
+        let address = listener.local_addr().expect("has local address");
+
+        let send = |data: Vec<u8>| {
+            std::thread::spawn(move || {
+                TcpStream::connect(address)
+                    .expect("can connect")
+                    .send(&data)
+            })
+        };
+
+        let recv = |size| {
+            let (stream, _) = listener.accept().expect("can accept connection");
+            let mut buffer = vec![0_u8; size];
+
+            stream
+                .recv(&mut buffer)
+                .map(|received| buffer[..received].to_vec())
+        };
+
+        let sender = send(b"message".to_vec());
+        assert_eq!(recv(7).expect("can receive message"), b"message");
+        sender
+            .join()
+            .expect("sender does not panic")
+            .expect("can send message");
+
+        let sender = send(b"message".to_vec());
+        assert!(recv(6).is_err());
+        sender.join().expect("sender does not panic").ok();
+    }
+}
@@ -107,16 +107,19 @@ fn write_entry() {
     let start_data = CommandStart {
         command: "Test".to_string(),
         pwd: PathBuf::from("/tmp"),
-        session_id: session_id.clone(),
+        session_id,
         time_stamp: Utc::now(),
         user: "testuser".to_string(),
         hostname: "testhostname".to_string(),
+        hist_event: Some(42),
+        tag: Some("deploy".to_string()),
     };
 
     let finish_data = CommandFinished {
         session_id,
         time_stamp: Utc::now(),
         result: 0,
+        reported_duration_ms: None,
     };
 
     client
@@ -154,6 +157,12 @@ fn write_entry() {
         result: finish_data.result,
         session_id: start_data.session_id,
         user: start_data.user,
+        hist_event: start_data.hist_event,
+        deleted: false,
+        duration_ms: Some((finish_data.time_stamp - start_data.time_stamp).num_milliseconds()),
+        tag: start_data.tag,
+        seq: 0,
+        time_deleted: None,
     };
 
     assert_eq!(expected, got);
@@ -168,16 +177,19 @@ fn write_entry_whitespace() {
     let start_data = CommandStart {
         command: r#"Test\nTest\nTest      "#.to_string(),
         pwd: PathBuf::from("/tmp"),
-        session_id: session_id.clone(),
+        session_id,
         time_stamp: Utc::now(),
         user: "testuser".to_string(),
         hostname: "testhostname".to_string(),
+        hist_event: None,
+        tag: None,
     };
 
     let finish_data = CommandFinished {
         session_id,
         time_stamp: Utc::now(),
         result: 0,
+        reported_duration_ms: None,
     };
 
     client
@@ -215,11 +227,372 @@ fn write_entry_whitespace() {
         result: finish_data.result,
         session_id: start_data.session_id,
         user: start_data.user,
+        hist_event: start_data.hist_event,
+        deleted: false,
+        duration_ms: Some((finish_data.time_stamp - start_data.time_stamp).num_milliseconds()),
+        tag: None,
+        seq: 0,
+        time_deleted: None,
     };
 
     assert_eq!(expected, got);
 }
 
+#[test]
+fn sequence_numbers() {
+    let client = create_client_and_server(true);
+
+    let session_id = Uuid::new_v4();
+    let time_stamp = Utc::now();
+
+    // Same timestamps and commands that would sort the other way around
+    // without the sequence number.
+    for command in &["c", "b", "a"] {
+        let start_data = CommandStart {
+            command: command.to_string(),
+            pwd: PathBuf::from("/tmp"),
+            session_id,
+            time_stamp,
+            user: "testuser".to_string(),
+            hostname: "testhostname".to_string(),
+            hist_event: None,
+            tag: None,
+        };
+
+        let finish_data = CommandFinished {
+            session_id,
+            time_stamp,
+            result: 0,
+            reported_duration_ms: None,
+        };
+
+        client
+            .client
+            .send(&Message::CommandStart(start_data))
+            .unwrap();
+
+        client
+            .client
+            .send(&Message::CommandFinished(finish_data))
+            .unwrap();
+    }
+
+    client.client.send(&Message::Stop).unwrap();
+
+    let data_dir = client.data_dir.clone();
+    drop(client);
+
+    let entries = store::new(data_dir.clone())
+        .get_entries(&Filter::default())
+        .unwrap();
+
+    std::fs::remove_dir_all(data_dir).unwrap();
+
+    let commands: Vec<_> = entries.iter().map(|entry| entry.command.as_str()).collect();
+    assert_eq!(commands, vec!["c", "b", "a"]);
+    assert!(entries.windows(2).all(|pair| pair[0].seq < pair[1].seq));
+}
+
+#[test]
+fn running_commands() {
+    let client = create_client_and_server(false);
+
+    let start_data = CommandStart {
+        command: "sleep 1000".to_string(),
+        pwd: PathBuf::from("/tmp"),
+        session_id: Uuid::new_v4(),
+        time_stamp: Utc::now(),
+        user: "testuser".to_string(),
+        hostname: "testhostname".to_string(),
+        hist_event: None,
+        tag: None,
+    };
+
+    client
+        .client
+        .send(&Message::CommandStart(start_data.clone()))
+        .unwrap();
+
+    let running = client
+        .client
+        .running(std::time::Duration::from_secs(5))
+        .unwrap();
+
+    client.client.send(&Message::Stop).unwrap();
+
+    assert_eq!(running.len(), 1);
+    assert_eq!(running[0].command, start_data.command);
+    assert_eq!(running[0].session_id, start_data.session_id);
+}
+
+#[test]
+fn write_queued_entries_on_stop() {
+    let client = create_client_and_server(true);
+
+    for index in 0..100 {
+        let session_id = Uuid::new_v4();
+
+        let start_data = CommandStart {
+            command: format!("Test {}", index),
+            pwd: PathBuf::from("/tmp"),
+            session_id,
+            time_stamp: Utc::now(),
+            user: "testuser".to_string(),
+            hostname: "testhostname".to_string(),
+            hist_event: None,
+            tag: None,
+        };
+
+        let finish_data = CommandFinished {
+            session_id,
+            time_stamp: Utc::now(),
+            result: 0,
+            reported_duration_ms: None,
+        };
+
+        client
+            .client
+            .send(&Message::CommandStart(start_data))
+            .unwrap();
+
+        client
+            .client
+            .send(&Message::CommandFinished(finish_data))
+            .unwrap();
+    }
+
+    // Same message the signal handler sends.
+    client.client.send(&Message::Stop).unwrap();
+
+    let data_dir = client.data_dir.clone();
+    drop(client);
+
+    let entries = store::new(data_dir.clone())
+        .get_entries(&Filter::default())
+        .unwrap();
+
+    std::fs::remove_dir_all(data_dir).unwrap();
+
+    assert_eq!(entries.len(), 100);
+}
+
+#[test]
+fn ping() {
+    let client = create_client_and_server(false);
+
+    let pid = client
+        .client
+        .ping(std::time::Duration::from_secs(5))
+        .unwrap();
+
+    client.client.send(&Message::Stop).unwrap();
+
+    assert_eq!(pid, std::process::id());
+}
+
+#[test]
+fn flush() {
+    let client = create_client_and_server(false);
+
+    let session_id = Uuid::new_v4();
+
+    let start_data = CommandStart {
+        command: "Test".to_string(),
+        pwd: PathBuf::from("/tmp"),
+        session_id,
+        time_stamp: Utc::now(),
+        user: "testuser".to_string(),
+        hostname: "testhostname".to_string(),
+        hist_event: None,
+        tag: None,
+    };
+
+    let finish_data = CommandFinished {
+        session_id,
+        time_stamp: Utc::now(),
+        result: 0,
+        reported_duration_ms: None,
+    };
+
+    client
+        .client
+        .send(&Message::CommandStart(start_data))
+        .unwrap();
+
+    client
+        .client
+        .send(&Message::CommandFinished(finish_data))
+        .unwrap();
+
+    // Messages are processed in order so the entry is written once the flush
+    // is answered.
+    client
+        .client
+        .flush(std::time::Duration::from_secs(5))
+        .unwrap();
+
+    let entries = store::new(client.data_dir.clone())
+        .get_entries(&Filter::default())
+        .unwrap();
+
+    client.client.send(&Message::Stop).unwrap();
+
+    assert_eq!(entries.len(), 1);
+}
+
+#[cfg(feature = "tcp-transport")]
+#[test]
+fn tcp_transport() {
+    let cache_dir = tempfile::tempdir().unwrap();
+    let data_dir = tempfile::tempdir().unwrap();
+    let socket_dir = tempfile::tempdir().unwrap();
+    let socket = socket_dir.path().join("server_socket");
+
+    let server = server::builder(
+        cache_dir.path().to_path_buf(),
+        data_dir.path().to_path_buf(),
+        socket.clone(),
+        false,
+    )
+    .listen(Some("127.0.0.1:0".to_string()))
+    .build()
+    .unwrap();
+
+    let address = server.tcp_address().unwrap();
+    let server = thread::spawn(move || server.run().unwrap());
+
+    let remote = client::new(socket.clone()).server_address(Some(address.to_string()));
+
+    // Control messages are only accepted on the socket, if the server would
+    // stop here the entry below would never be written.
+    remote.send(&Message::Stop).unwrap();
+
+    let session_id = Uuid::new_v4();
+
+    remote
+        .send(&Message::CommandStart(CommandStart {
+            command: "Test".to_string(),
+            pwd: PathBuf::from("/tmp"),
+            session_id,
+            time_stamp: Utc::now(),
+            user: "testuser".to_string(),
+            hostname: "testhostname".to_string(),
+            hist_event: None,
+            tag: None,
+        }))
+        .unwrap();
+
+    remote
+        .send(&Message::CommandFinished(CommandFinished {
+            session_id,
+            time_stamp: Utc::now(),
+            result: 0,
+            reported_duration_ms: None,
+        }))
+        .unwrap();
+
+    let filter = Filter::default()
+        .hostnames(vec!["testhostname".to_string()], false)
+        .unwrap();
+
+    let mut entries = Vec::new();
+    for _ in 0..100 {
+        if let Ok(found) = store::new(data_dir.path().to_path_buf()).get_entries(&filter) {
+            entries = found;
+            break;
+        }
+
+        thread::sleep(std::time::Duration::from_millis(50));
+    }
+
+    client::new(socket).send(&Message::Stop).unwrap();
+    server.join().unwrap();
+
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].command, "Test");
+}
+
+#[test]
+fn auth_token() {
+    let cache_dir = tempfile::tempdir().unwrap();
+    let data_dir = tempfile::tempdir().unwrap();
+    let socket_dir = tempfile::tempdir().unwrap();
+    let socket = socket_dir.path().join("server_socket");
+
+    let server = server::builder(
+        cache_dir.path().to_path_buf(),
+        data_dir.path().to_path_buf(),
+        socket.clone(),
+        false,
+    )
+    .auth_token(Some("secret".to_string()))
+    .build()
+    .unwrap();
+
+    let server = thread::spawn(move || server.run().unwrap());
+
+    let record = |client: &Client, command: &str| {
+        let session_id = Uuid::new_v4();
+
+        client
+            .send(&Message::CommandStart(CommandStart {
+                command: command.to_string(),
+                pwd: PathBuf::from("/tmp"),
+                session_id,
+                time_stamp: Utc::now(),
+                user: "testuser".to_string(),
+                hostname: "testhostname".to_string(),
+                hist_event: None,
+                tag: None,
+            }))
+            .unwrap();
+
+        client
+            .send(&Message::CommandFinished(CommandFinished {
+                session_id,
+                time_stamp: Utc::now(),
+                result: 0,
+                reported_duration_ms: None,
+            }))
+            .unwrap();
+    };
+
+    let tokenless = client::new(socket.clone());
+    let wrong = client::new(socket.clone()).auth_token(Some("wrong".to_string()));
+    let valid = client::new(socket).auth_token(Some("secret".to_string()));
+
+    record(&tokenless, "tokenless");
+    record(&wrong, "wrong");
+    record(&valid, "valid");
+
+    tokenless.send(&Message::Stop).unwrap();
+    assert!(wrong.ping(std::time::Duration::from_millis(100)).is_err());
+
+    valid.flush(std::time::Duration::from_secs(5)).unwrap();
+
+    let entries = store::new(data_dir.path().to_path_buf())
+        .get_entries(
+            &Filter::default()
+                .hostnames(vec!["testhostname".to_string()], false)
+                .unwrap(),
+        )
+        .unwrap();
+
+    valid.send(&Message::Stop).unwrap();
+    server.join().unwrap();
+
+    let commands: Vec<_> = entries.into_iter().map(|entry| entry.command).collect();
+    assert_eq!(commands, vec!["valid"]);
+}
+
+#[test]
+fn ping_without_server() {
+    let socket_dir = tempfile::tempdir().unwrap();
+    let client = client::new(socket_dir.path().join("server_socket"));
+
+    assert!(client.ping(std::time::Duration::from_secs(1)).is_err());
+}
+
 // TODO: Make a test for this probably needs a restructuring of how we
 // detect leading spaces in commands
 //#[test]
@@ -231,10 +604,11 @@ fn write_entry_whitespace() {
 //    let start_data = CommandStart {
 //        command: " Test".to_string(),
 //        pwd: PathBuf::from("/tmp"),
-//        session_id: session_id.clone(),
+//        session_id,
 //        time_stamp: Utc::now(),
 //        user: "testuser".to_string(),
 //        hostname: "testhostname".to_string(),
+//        hist_event: None,
 //    };
 //
 //    let finish_data = CommandFinished {
@@ -278,16 +652,19 @@ fn write_empty_command() {
     let start_data = CommandStart {
         command: "".to_string(),
         pwd: PathBuf::from("/tmp"),
-        session_id: session_id.clone(),
+        session_id,
         time_stamp: Utc::now(),
         user: "testuser".to_string(),
         hostname: "testhostname".to_string(),
+        hist_event: None,
+        tag: None,
     };
 
     let finish_data = CommandFinished {
         session_id,
         time_stamp: Utc::now(),
         result: 0,
+        reported_duration_ms: None,
     };
 
     client
@@ -336,16 +713,19 @@ fn write_newline_command() {
         let start_data = CommandStart {
             command,
             pwd: PathBuf::from("/tmp"),
-            session_id: session_id.clone(),
+            session_id,
             time_stamp: Utc::now(),
             user: "testuser".to_string(),
             hostname: "testhostname".to_string(),
+            hist_event: None,
+            tag: None,
         };
 
         let finish_data = CommandFinished {
             session_id,
             time_stamp: Utc::now(),
             result: 0,
+            reported_duration_ms: None,
         };
 
         client